@@ -0,0 +1,536 @@
+//! Typed encoding of L1 message payloads.
+//!
+//! Messages between Ethereum and the IC carry their arguments as a flat
+//! `uint256[]`, received on the IC as `Vec<Nat>`. A payload struct lists its
+//! fields in the order of the L1 contract and gets `Payload` through
+//! `impl_payload!`; every field type implements `PayloadField`.
+//!
+//! ```
+//! use payload_codec::{impl_payload, Nat, Payload, Principal};
+//!
+//! struct DepositPayload {
+//!     to: Principal,
+//!     amount: Nat,
+//! }
+//!
+//! impl_payload!(DepositPayload { to, amount });
+//!
+//! assert!(DepositPayload::decode_payload(&[Nat::from(1)]).is_err());
+//! ```
+
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+
+pub use candid::{Nat, Principal};
+use num_bigint::BigUint;
+use terabethia_common::{FromNat, PrincipalKind, ToNat};
+
+/// Size of an L1 word, `uint256`
+const WORD_SIZE: usize = 32;
+
+const ADDRESS_SIZE: usize = 20;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PayloadError {
+    /// The payload ends before the field at `index`
+    MissingField { index: usize },
+    /// The value at `index` does not fit the field type
+    InvalidField { index: usize, reason: String },
+    /// The payload has more values than the fields of its layout
+    TrailingFields { expected: usize, found: usize },
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PayloadError::MissingField { index } => write!(f, "missing payload field {}", index),
+            PayloadError::InvalidField { index, reason } => {
+                write!(f, "invalid payload field {}: {}", index, reason)
+            }
+            PayloadError::TrailingFields { expected, found } => write!(
+                f,
+                "payload has {} fields, {} were expected",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+/// A message payload with a fixed layout
+pub trait Payload: Sized {
+    fn decode_payload(payload: &[Nat]) -> Result<Self, PayloadError>;
+
+    fn encode_payload(&self) -> Vec<Nat>;
+}
+
+/// A value taking one or more consecutive words of a payload
+pub trait PayloadField: Sized {
+    fn decode_field(reader: &mut PayloadReader) -> Result<Self, PayloadError>;
+
+    fn encode_field(&self, payload: &mut Vec<Nat>);
+}
+
+/// Cursor over the words of a payload
+pub struct PayloadReader<'a> {
+    payload: &'a [Nat],
+    index: usize,
+}
+
+impl<'a> PayloadReader<'a> {
+    pub fn new(payload: &'a [Nat]) -> Self {
+        Self { payload, index: 0 }
+    }
+
+    /// Convert the next word, errors are reported with its index
+    pub fn read<T, F>(&mut self, convert: F) -> Result<T, PayloadError>
+    where
+        F: FnOnce(&Nat) -> Result<T, String>,
+    {
+        let index = self.index;
+        let value = self
+            .payload
+            .get(index)
+            .ok_or(PayloadError::MissingField { index })?;
+        self.index += 1;
+
+        convert(value).map_err(|reason| PayloadError::InvalidField { index, reason })
+    }
+
+    /// Ensure the whole payload was read
+    pub fn finish(self) -> Result<(), PayloadError> {
+        if self.index < self.payload.len() {
+            return Err(PayloadError::TrailingFields {
+                expected: self.index,
+                found: self.payload.len(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Implement `Payload` for a struct, its fields are encoded in the given order
+#[macro_export]
+macro_rules! impl_payload {
+    ($name:ident { $($field:ident),* $(,)? }) => {
+        impl $crate::Payload for $name {
+            fn decode_payload(payload: &[$crate::Nat]) -> Result<Self, $crate::PayloadError> {
+                let mut reader = $crate::PayloadReader::new(payload);
+                let decoded = $name {
+                    $($field: $crate::PayloadField::decode_field(&mut reader)?,)*
+                };
+                reader.finish()?;
+
+                Ok(decoded)
+            }
+
+            fn encode_payload(&self) -> Vec<$crate::Nat> {
+                let mut payload = Vec::new();
+                $($crate::PayloadField::encode_field(&self.$field, &mut payload);)*
+
+                payload
+            }
+        }
+    };
+}
+
+fn word_bytes(value: &Nat, max_size: usize) -> Result<Vec<u8>, String> {
+    let bytes = value.0.to_bytes_be();
+    if value.0 != BigUint::default() && bytes.len() > max_size {
+        return Err(format!("{} does not fit in {} bytes", value, max_size));
+    }
+
+    Ok(bytes)
+}
+
+/// `uint256`
+impl PayloadField for Nat {
+    fn decode_field(reader: &mut PayloadReader) -> Result<Self, PayloadError> {
+        reader.read(|value| word_bytes(value, WORD_SIZE).map(|_| value.clone()))
+    }
+
+    fn encode_field(&self, payload: &mut Vec<Nat>) {
+        payload.push(self.clone());
+    }
+}
+
+macro_rules! impl_uint_field {
+    ($($ty:ty),*) => {
+        $(
+            impl PayloadField for $ty {
+                fn decode_field(reader: &mut PayloadReader) -> Result<Self, PayloadError> {
+                    reader.read(|value| {
+                        let bytes = word_bytes(value, std::mem::size_of::<$ty>())?;
+                        let mut word = [0u8; std::mem::size_of::<$ty>()];
+                        word[std::mem::size_of::<$ty>() - bytes.len()..].copy_from_slice(&bytes);
+                        Ok(<$ty>::from_be_bytes(word))
+                    })
+                }
+
+                fn encode_field(&self, payload: &mut Vec<Nat>) {
+                    payload.push(Nat::from(*self));
+                }
+            }
+        )*
+    };
+}
+
+impl_uint_field!(u8, u16, u32, u64);
+
+/// A principal packed in a `uint256`, restored with the `FromNat` padding
+impl PayloadField for Principal {
+    fn decode_field(reader: &mut PayloadReader) -> Result<Self, PayloadError> {
+        reader.read(|value| Principal::from_nat(value.clone()))
+    }
+
+    fn encode_field(&self, payload: &mut Vec<Nat>) {
+        payload.push(self.to_nat());
+    }
+}
+
+/// A principal with its length in the most significant byte of the word, so
+/// it decodes exactly whatever its kind. Words with an empty length byte come
+/// from senders unaware of it and fall back to the `FromNat` padding, as does
+/// the empty principal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizedPrincipal(pub Principal);
+
+impl PayloadField for SizedPrincipal {
+    fn decode_field(reader: &mut PayloadReader) -> Result<Self, PayloadError> {
+        reader.read(|value| {
+            let bytes = word_bytes(value, WORD_SIZE)?;
+            if bytes.len() < WORD_SIZE {
+                return Principal::from_nat(value.clone()).map(SizedPrincipal);
+            }
+
+            let kind = PrincipalKind::Opaque(bytes[0]);
+            let principal = Nat::from(BigUint::from_bytes_be(&bytes[1..]));
+
+            Principal::from_nat_with_kind(principal, kind).map(SizedPrincipal)
+        })
+    }
+
+    fn encode_field(&self, payload: &mut Vec<Nat>) {
+        let bytes = self.0.as_slice();
+        let mut word = [0u8; WORD_SIZE];
+        word[0] = bytes.len() as u8;
+        word[WORD_SIZE - bytes.len()..].copy_from_slice(bytes);
+
+        payload.push(Nat::from(BigUint::from_bytes_be(&word)));
+    }
+}
+
+/// An Ethereum `address`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EthAddress(pub [u8; ADDRESS_SIZE]);
+
+impl EthAddress {
+    /// The address as the 20 bytes principal used to identify L1 contracts
+    pub fn as_principal(&self) -> Principal {
+        Principal::from_slice(&self.0)
+    }
+}
+
+impl TryFrom<&str> for EthAddress {
+    type Error = String;
+
+    fn try_from(hex_addr: &str) -> Result<Self, Self::Error> {
+        let bytes = hex::decode(hex_addr.trim_start_matches("0x")).map_err(|e| e.to_string())?;
+        let addr = bytes
+            .try_into()
+            .map_err(|_| format!("{} is not a 20 bytes address", hex_addr))?;
+
+        Ok(EthAddress(addr))
+    }
+}
+
+impl PayloadField for EthAddress {
+    fn decode_field(reader: &mut PayloadReader) -> Result<Self, PayloadError> {
+        reader.read(|value| {
+            let bytes = word_bytes(value, ADDRESS_SIZE)?;
+            let mut addr = [0u8; ADDRESS_SIZE];
+            addr[ADDRESS_SIZE - bytes.len()..].copy_from_slice(&bytes);
+
+            Ok(EthAddress(addr))
+        })
+    }
+
+    fn encode_field(&self, payload: &mut Vec<Nat>) {
+        payload.push(Nat::from(BigUint::from_bytes_be(&self.0)));
+    }
+}
+
+/// A short string packed in a `bytes32`, as by `stringToBytes32` on L1.
+/// Trailing zeros are padding and are dropped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bytes32String(String);
+
+impl Bytes32String {
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl TryFrom<String> for Bytes32String {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value.len() > WORD_SIZE {
+            return Err(format!("{} is longer than {} bytes", value, WORD_SIZE));
+        }
+
+        Ok(Bytes32String(value))
+    }
+}
+
+impl AsRef<str> for Bytes32String {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PayloadField for Bytes32String {
+    fn decode_field(reader: &mut PayloadReader) -> Result<Self, PayloadError> {
+        reader.read(|value| {
+            let mut bytes = word_bytes(value, WORD_SIZE)?;
+            while bytes.last() == Some(&0) {
+                bytes.pop();
+            }
+
+            String::from_utf8(bytes)
+                .map(Bytes32String)
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    fn encode_field(&self, payload: &mut Vec<Nat>) {
+        let mut word = [0u8; WORD_SIZE];
+        word[..self.0.len()].copy_from_slice(self.0.as_bytes());
+
+        payload.push(Nat::from(BigUint::from_bytes_be(&word)));
+    }
+}
+
+/// `T[]`, its length followed by its items
+impl<T: PayloadField> PayloadField for Vec<T> {
+    fn decode_field(reader: &mut PayloadReader) -> Result<Self, PayloadError> {
+        let len: u32 = PayloadField::decode_field(reader)?;
+
+        (0..len).map(|_| T::decode_field(reader)).collect()
+    }
+
+    fn encode_field(&self, payload: &mut Vec<Nat>) {
+        payload.push(Nat::from(self.len()));
+        for item in self {
+            item.encode_field(payload);
+        }
+    }
+}
+
+/// `T[N]`, its items only
+impl<T: PayloadField, const N: usize> PayloadField for [T; N] {
+    fn decode_field(reader: &mut PayloadReader) -> Result<Self, PayloadError> {
+        let items = (0..N)
+            .map(|_| T::decode_field(reader))
+            .collect::<Result<Vec<T>, PayloadError>>()?;
+
+        match items.try_into() {
+            Ok(array) => Ok(array),
+            Err(_) => unreachable!("exactly {} items were decoded", N),
+        }
+    }
+
+    fn encode_field(&self, payload: &mut Vec<Nat>) {
+        for item in self {
+            item.encode_field(payload);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use terabethia_common::MAX_PRINCIPAL_LEN;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct DepositPayload {
+        token: EthAddress,
+        to: Principal,
+        amount: Nat,
+        name: Bytes32String,
+        symbol: Bytes32String,
+        decimals: u8,
+    }
+
+    impl_payload!(DepositPayload {
+        token,
+        to,
+        amount,
+        name,
+        symbol,
+        decimals,
+    });
+
+    #[derive(Debug, PartialEq)]
+    struct BatchPayload {
+        recipients: Vec<Principal>,
+        amounts: [Nat; 2],
+    }
+
+    impl_payload!(BatchPayload {
+        recipients,
+        amounts
+    });
+
+    fn deposit_payload() -> Vec<Nat> {
+        vec![
+            // token
+            Nat::from_str("1390849295786071768276380950238675083608645509734").unwrap(),
+            // to
+            Nat::from_str("5575946531581959547228116840874869615988566799087422752926889285441538")
+                .unwrap(),
+            // amount
+            Nat::from_str("100000000000000000").unwrap(),
+            // name, stringToBytes32("fighters")
+            Nat::from(BigUint::from_bytes_be(&{
+                let mut word = [0u8; 32];
+                word[..8].copy_from_slice(b"fighters");
+                word
+            })),
+            // symbol, without padding
+            Nat::from(BigUint::from_bytes_be(b"foo")),
+            // decimals
+            Nat::from(18),
+        ]
+    }
+
+    #[test]
+    fn test_decode_deposit_payload() {
+        let deposit = DepositPayload::decode_payload(&deposit_payload()).unwrap();
+
+        assert_eq!(
+            deposit.token,
+            EthAddress::try_from("0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266").unwrap()
+        );
+        assert_eq!(
+            deposit.to.to_text(),
+            "avesb-mgo2l-ds25i-g7kd4-3he5l-z7ary-3biiq-sojiw-xjgbk-ich5l-mae"
+        );
+        assert_eq!(deposit.amount, Nat::from(100_000_000_000_000_000u64));
+        assert_eq!(deposit.name.as_ref(), "fighters");
+        assert_eq!(deposit.symbol.as_ref(), "foo");
+        assert_eq!(deposit.decimals, 18);
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let deposit = DepositPayload::decode_payload(&deposit_payload()).unwrap();
+        let encoded = deposit.encode_payload();
+
+        assert_eq!(DepositPayload::decode_payload(&encoded).unwrap(), deposit);
+
+        let batch = BatchPayload {
+            recipients: vec![
+                Principal::from_text("tcy4r-qaaaa-aaaab-qadyq-cai").unwrap(),
+                Principal::from_text(
+                    "avesb-mgo2l-ds25i-g7kd4-3he5l-z7ary-3biiq-sojiw-xjgbk-ich5l-mae",
+                )
+                .unwrap(),
+            ],
+            amounts: [Nat::from(1), Nat::from(2)],
+        };
+        let encoded = batch.encode_payload();
+
+        assert_eq!(encoded.len(), 5);
+        assert_eq!(encoded[0], Nat::from(2));
+        assert_eq!(BatchPayload::decode_payload(&encoded).unwrap(), batch);
+    }
+
+    #[test]
+    fn test_principal_padding() {
+        let canister = Principal::from_text("tcy4r-qaaaa-aaaab-qadyq-cai").unwrap();
+        let mut payload = Vec::new();
+        canister.encode_field(&mut payload);
+
+        let decoded: Principal =
+            PayloadField::decode_field(&mut PayloadReader::new(&payload)).unwrap();
+        assert_eq!(decoded, canister);
+
+        let too_long = vec![Nat::from(BigUint::from_bytes_be(&[1; 30]))];
+        assert!(matches!(
+            Principal::decode_field(&mut PayloadReader::new(&too_long)),
+            Err(PayloadError::InvalidField { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_sized_principal() {
+        // 28 bytes, `FromNat` would pad it to 29
+        let principal = Principal::from_slice(&[7; 28]);
+        let mut payload = Vec::new();
+        SizedPrincipal(principal).encode_field(&mut payload);
+
+        let decoded = SizedPrincipal::decode_field(&mut PayloadReader::new(&payload)).unwrap();
+        assert_eq!(decoded.0, principal);
+        assert_ne!(Principal::from_nat(principal.to_nat()).unwrap(), principal);
+
+        // without a length byte
+        let canister = Principal::from_text("tcy4r-qaaaa-aaaab-qadyq-cai").unwrap();
+        let legacy = vec![canister.to_nat()];
+        let decoded = SizedPrincipal::decode_field(&mut PayloadReader::new(&legacy)).unwrap();
+        assert_eq!(decoded.0, canister);
+
+        // length byte shorter than the principal
+        let mut word = [0u8; WORD_SIZE];
+        word[0] = 2;
+        word[29..].copy_from_slice(&[1, 2, 3]);
+        let invalid = vec![Nat::from(BigUint::from_bytes_be(&word))];
+        assert!(SizedPrincipal::decode_field(&mut PayloadReader::new(&invalid)).is_err());
+
+        word[0] = MAX_PRINCIPAL_LEN as u8 + 1;
+        let invalid = vec![Nat::from(BigUint::from_bytes_be(&word))];
+        assert!(SizedPrincipal::decode_field(&mut PayloadReader::new(&invalid)).is_err());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let payload = deposit_payload();
+
+        assert_eq!(
+            DepositPayload::decode_payload(&payload[..2]),
+            Err(PayloadError::MissingField { index: 2 })
+        );
+
+        let mut trailing = payload.clone();
+        trailing.push(Nat::from(0));
+        assert_eq!(
+            DepositPayload::decode_payload(&trailing),
+            Err(PayloadError::TrailingFields {
+                expected: 6,
+                found: 7
+            })
+        );
+
+        let mut wide_decimals = payload.clone();
+        wide_decimals[5] = Nat::from(256);
+        assert!(matches!(
+            DepositPayload::decode_payload(&wide_decimals),
+            Err(PayloadError::InvalidField { index: 5, .. })
+        ));
+
+        let mut wide_token = payload;
+        wide_token[0] = Nat::from(BigUint::from_bytes_be(&[1; 21]));
+        assert!(matches!(
+            DepositPayload::decode_payload(&wide_token),
+            Err(PayloadError::InvalidField { index: 0, .. })
+        ));
+
+        let too_long: Result<Bytes32String, _> = "a".repeat(33).try_into();
+        assert!(too_long.is_err());
+    }
+}
@@ -0,0 +1,37 @@
+use candid::{CandidType, Deserialize};
+
+/// Low-water mark a canister starts with, 1T cycles
+pub const DEFAULT_LOW_CYCLES_THRESHOLD: u64 = 1_000_000_000_000;
+
+/// Cycle balance of a canister against its low-water mark
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CycleMetrics {
+    pub balance: u64,
+    pub low_cycles_threshold: u64,
+    /// The balance dropped below the low-water mark and needs a top up
+    pub degraded: bool,
+}
+
+impl CycleMetrics {
+    pub fn new(balance: u64, low_cycles_threshold: u64) -> Self {
+        CycleMetrics {
+            balance,
+            low_cycles_threshold,
+            degraded: balance < low_cycles_threshold,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_degraded_below_threshold() {
+        assert!(CycleMetrics::new(999, 1_000).degraded);
+        assert!(!CycleMetrics::new(1_000, 1_000).degraded);
+        assert!(
+            !CycleMetrics::new(DEFAULT_LOW_CYCLES_THRESHOLD, DEFAULT_LOW_CYCLES_THRESHOLD).degraded
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use candid::{CandidType, Deserialize, Nat};
+use serde::Serialize;
+use sha3::{Digest, Keccak256};
+
+use crate::nat::NonceBytes;
+
+pub type MessageHash = String;
+
+#[derive(Serialize, CandidType, Deserialize)]
+pub struct Message;
+
+/// keccak256(abi.encodePacked(uint256 from, uint256 to, uint256 nonce, uint256 payload.length, uint256[] payload))
+#[derive(CandidType, Deserialize)]
+pub struct IncomingMessageHashParams {
+    pub from: Nat,
+    pub to: Nat,
+    pub nonce: Nat,
+    pub payload: Vec<Nat>,
+}
+
+/// keccak256(abi.encodePacked(uint256 from, uint256 to, uint256 payload.length, uint256[] payload))
+#[derive(CandidType, Deserialize)]
+pub struct OutgoingMessageHashParams {
+    pub from: Nat,
+    pub to: Nat,
+    pub payload: Vec<Nat>,
+}
+
+pub trait Keccak256HashFn<T> {
+    fn calculate_hash(&self, params: T) -> MessageHash;
+}
+
+impl Keccak256HashFn<IncomingMessageHashParams> for Message {
+    fn calculate_hash(&self, params: IncomingMessageHashParams) -> MessageHash {
+        let mut data = vec![
+            params.from,
+            params.to,
+            params.nonce,
+            Nat::from(params.payload.len()),
+        ];
+        data.extend(params.payload);
+
+        keccak256_words(data)
+    }
+}
+
+impl Keccak256HashFn<OutgoingMessageHashParams> for Message {
+    fn calculate_hash(&self, params: OutgoingMessageHashParams) -> MessageHash {
+        let mut data = vec![params.from, params.to, Nat::from(params.payload.len())];
+        data.extend(params.payload);
+
+        keccak256_words(data)
+    }
+}
+
+/// Hash of the words packed as uint256, hex encoded
+fn keccak256_words(data: Vec<Nat>) -> MessageHash {
+    let mut hasher = Keccak256::new();
+
+    for x in data {
+        // left pad each word with zeros up to 32 bytes
+        let slice = x.0.to_bytes_be();
+        let mut word: NonceBytes = [0u8; 32];
+        word[32 - slice.len()..].copy_from_slice(&slice);
+
+        hasher.update(word);
+    }
+
+    hex::encode(hasher.finalize())
+}
+
+/// Vectors were checked against the encoding of `Terabethia.sol`
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use candid::Principal;
+
+    use super::*;
+    use crate::nat::ToNat;
+
+    fn eth_addr(hex_str: &str) -> Nat {
+        let slice = hex::decode(hex_str).unwrap();
+
+        Nat::from(num_bigint::BigUint::from_bytes_be(&slice[..]))
+    }
+
+    #[test]
+    fn message_hash() {
+        let from = Principal::from_text("rdbii-uiaaa-aaaab-qadva-cai").unwrap();
+
+        let msg_hash = Message.calculate_hash(IncomingMessageHashParams {
+            from: from.to_nat(),
+            to: eth_addr("dc64a140aa3e981100a9beca4e685f962f0cf6c9"),
+            nonce: Nat::from(4),
+            payload: vec![
+                Nat::from_str("00").unwrap(),
+                Nat::from_str("1390849295786071768276380950238675083608645509734").unwrap(),
+                Nat::from_str("100000000000000000").unwrap(),
+            ],
+        });
+
+        assert_eq!(
+            msg_hash,
+            "dbd4422a30eb532893ee1009c651c760fd1c49429665daaf87914b9e7231f55b"
+        );
+    }
+
+    #[test]
+    fn deposit_message_hash() {
+        let to = Principal::from_text("tcy4r-qaaaa-aaaab-qadyq-cai").unwrap();
+
+        let msg_hash = Message.calculate_hash(IncomingMessageHashParams {
+            from: eth_addr("1b864e1CA9189CFbD8A14a53A02E26B00AB5e91a"),
+            to: to.to_nat(),
+            nonce: Nat::from(4),
+            payload: vec![
+                Nat::from_str(
+                    "5575946531581959547228116840874869615988566799087422752926889285441538",
+                )
+                .unwrap(),
+                Nat::from_str("69000000").unwrap(),
+            ],
+        });
+
+        assert_eq!(
+            msg_hash,
+            "c9e23418a985892acc0fa031331080bfce112bdf841a3ae04a5181c6da1610b1"
+        );
+    }
+
+    #[test]
+    fn outgoing_message_hash() {
+        let from = Principal::from_text("tcy4r-qaaaa-aaaab-qadyq-cai").unwrap();
+
+        let msg_hash = Message.calculate_hash(OutgoingMessageHashParams {
+            from: from.to_nat(),
+            to: eth_addr("2e130e57021bb4dfb95eb4dd0dd8cfceb936148a"),
+            payload: vec![
+                eth_addr("f39fd6e51aad88f6f4ce6ab8827279cfffb92266"),
+                Nat::from(69000000),
+            ],
+        });
+
+        assert_eq!(
+            msg_hash,
+            "dcddd51ae1d6de82d47ac46ed0e15f822406e15e88b580cfdd08978120b82c6b"
+        );
+    }
+
+    #[test]
+    fn message_hash_depends_on_nonce() {
+        let params = |nonce: u32| IncomingMessageHashParams {
+            from: Nat::from(1),
+            to: Nat::from(2),
+            nonce: Nat::from(nonce),
+            payload: vec![],
+        };
+
+        assert_ne!(
+            Message.calculate_hash(params(1)),
+            Message.calculate_hash(params(2))
+        );
+    }
+}
@@ -0,0 +1,17 @@
+//! Conversions, message hashing and metrics shared by tera and the
+//! bridge canisters. Conversions and hashes have to match the L1 contracts
+//! byte for byte, keep the test vectors in sync with `Terabethia.sol`.
+
+mod cycles;
+mod hash;
+mod metrics;
+mod nat;
+
+pub use cycles::{CycleMetrics, DEFAULT_LOW_CYCLES_THRESHOLD};
+pub use hash::{
+    IncomingMessageHashParams, Keccak256HashFn, Message, MessageHash, OutgoingMessageHashParams,
+};
+pub use metrics::{
+    serve_metrics, HeaderField, HttpRequest, HttpResponse, MetricsEncoder, METRICS_PATH,
+};
+pub use nat::{FromNat, NonceBytes, PrincipalKind, ToBytes, ToNat, MAX_PRINCIPAL_LEN};
@@ -0,0 +1,166 @@
+use std::fmt::Write;
+
+use candid::{CandidType, Deserialize};
+
+use crate::CycleMetrics;
+
+/// Path the metrics are served on by `http_request`
+pub const METRICS_PATH: &str = "/metrics";
+
+pub type HeaderField = (String, String);
+
+/// Request forwarded by the http gateway to the `http_request` query
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<HeaderField>,
+    #[serde(with = "serde_bytes")]
+    pub body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<HeaderField>,
+    #[serde(with = "serde_bytes")]
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn text(status_code: u16, content_type: &str, body: String) -> Self {
+        HttpResponse {
+            status_code,
+            headers: vec![
+                ("Content-Type".to_string(), content_type.to_string()),
+                ("Content-Length".to_string(), body.len().to_string()),
+            ],
+            body: body.into_bytes(),
+        }
+    }
+
+    pub fn not_found() -> Self {
+        HttpResponse::text(404, "text/plain", "Not found".to_string())
+    }
+}
+
+/// Writes metrics in the Prometheus text exposition format
+#[derive(Default)]
+pub struct MetricsEncoder {
+    body: String,
+}
+
+impl MetricsEncoder {
+    pub fn gauge(&mut self, name: &str, value: u64, help: &str) -> &mut Self {
+        self.metric(name, "gauge", value, help)
+    }
+
+    pub fn counter(&mut self, name: &str, value: u64, help: &str) -> &mut Self {
+        self.metric(name, "counter", value, help)
+    }
+
+    /// Cycle balance, low-water mark and heap size, served by every canister
+    pub fn canister_metrics(&mut self, cycles: &CycleMetrics) -> &mut Self {
+        self.gauge("cycle_balance", cycles.balance, "Cycle balance")
+            .gauge(
+                "low_cycles_threshold",
+                cycles.low_cycles_threshold,
+                "Cycle balance under which the canister is degraded",
+            )
+            .gauge(
+                "degraded",
+                cycles.degraded as u64,
+                "Whether the cycle balance is under the low-water mark",
+            )
+            .gauge(
+                "heap_memory_bytes",
+                heap_memory_size(),
+                "Size of the heap memory in bytes",
+            )
+    }
+
+    fn metric(&mut self, name: &str, kind: &str, value: u64, help: &str) -> &mut Self {
+        writeln!(self.body, "# HELP {} {}", name, help).unwrap();
+        writeln!(self.body, "# TYPE {} {}", name, kind).unwrap();
+        writeln!(self.body, "{} {}", name, value).unwrap();
+        self
+    }
+
+    pub fn into_response(self) -> HttpResponse {
+        HttpResponse::text(200, "text/plain; version=0.0.4", self.body)
+    }
+}
+
+/// Serve the metrics written by `encode` on `METRICS_PATH`, anything else
+/// is not found
+pub fn serve_metrics(
+    request: &HttpRequest,
+    encode: impl FnOnce(&mut MetricsEncoder),
+) -> HttpResponse {
+    let path = request.url.split('?').next().unwrap_or_default();
+    if path != METRICS_PATH {
+        return HttpResponse::not_found();
+    }
+
+    let mut encoder = MetricsEncoder::default();
+    encode(&mut encoder);
+    encoder.into_response()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn heap_memory_size() -> u64 {
+    const WASM_PAGE_SIZE: u64 = 65536;
+
+    core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_SIZE
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn heap_memory_size() -> u64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(url: &str) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    #[test]
+    fn test_serve_metrics() {
+        let response = serve_metrics(&request("/metrics?time=1"), |encoder| {
+            encoder.gauge("incoming_messages_pending", 3, "Pending messages");
+        });
+
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            String::from_utf8(response.body).unwrap(),
+            "# HELP incoming_messages_pending Pending messages\n\
+             # TYPE incoming_messages_pending gauge\n\
+             incoming_messages_pending 3\n"
+        );
+    }
+
+    #[test]
+    fn test_serve_unknown_path() {
+        let response = serve_metrics(&request("/"), |_| unreachable!());
+
+        assert_eq!(response, HttpResponse::not_found());
+    }
+
+    #[test]
+    fn test_canister_metrics() {
+        let mut encoder = MetricsEncoder::default();
+        encoder.canister_metrics(&CycleMetrics::new(500, 1_000));
+        let body = String::from_utf8(encoder.into_response().body).unwrap();
+
+        assert!(body.contains("\ncycle_balance 500\n"));
+        assert!(body.contains("\ndegraded 1\n"));
+    }
+}
@@ -0,0 +1,232 @@
+use std::convert::TryInto;
+
+use candid::{Nat, Principal};
+
+pub type NonceBytes = [u8; 32];
+
+/// Longest principal, a self-authenticating id
+pub const MAX_PRINCIPAL_LEN: usize = 29;
+
+/// Kind of a principal, which fixes its length in bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrincipalKind {
+    /// 10 bytes, ending with 0x01
+    Canister,
+    /// 29 bytes, ending with 0x02
+    SelfAuthenticating,
+    /// any other principal of the given length
+    Opaque(u8),
+}
+
+impl PrincipalKind {
+    pub fn byte_len(&self) -> usize {
+        match self {
+            PrincipalKind::Canister => 10,
+            PrincipalKind::SelfAuthenticating => MAX_PRINCIPAL_LEN,
+            PrincipalKind::Opaque(len) => *len as usize,
+        }
+    }
+
+    fn tag(&self) -> Option<u8> {
+        match self {
+            PrincipalKind::Canister => Some(0x01),
+            PrincipalKind::SelfAuthenticating => Some(0x02),
+            PrincipalKind::Opaque(_) => None,
+        }
+    }
+}
+
+pub trait ToNat {
+    fn to_nat(&self) -> Nat;
+}
+
+impl ToNat for NonceBytes {
+    fn to_nat(&self) -> Nat {
+        Nat::from(num_bigint::BigUint::from_bytes_be(&self[..]))
+    }
+}
+
+impl ToNat for Principal {
+    #[inline(always)]
+    fn to_nat(&self) -> Nat {
+        Nat::from(num_bigint::BigUint::from_bytes_be(self.as_slice()))
+    }
+}
+
+pub trait FromNat {
+    fn from_nat(input: Nat) -> Result<Principal, String>;
+
+    fn from_nat_with_kind(input: Nat, kind: PrincipalKind) -> Result<Principal, String>;
+}
+
+impl FromNat for Principal {
+    /// Leading zeros are lost in a Nat, they are restored by padding to the
+    /// 10 bytes of a canister id or the 29 bytes of a self-authenticating id.
+    /// The length is guessed, principals of 11 to 28 bytes come out wrong, use
+    /// `from_nat_with_kind` when the kind is known.
+    #[inline(always)]
+    fn from_nat(input: Nat) -> Result<Principal, String> {
+        let be_bytes = input.0.to_bytes_be();
+        let be_bytes_len = be_bytes.len();
+        if be_bytes_len > MAX_PRINCIPAL_LEN {
+            return Err("Invalid Nat".to_string());
+        }
+        let padding_bytes = match be_bytes_len {
+            0..=9 => 10 - be_bytes_len,
+            11..=28 => 29 - be_bytes_len,
+            _ => 0,
+        };
+        let mut p_slice = vec![0u8; padding_bytes];
+        p_slice.extend_from_slice(&be_bytes);
+        Ok(Principal::from_slice(&p_slice))
+    }
+
+    fn from_nat_with_kind(input: Nat, kind: PrincipalKind) -> Result<Principal, String> {
+        let len = kind.byte_len();
+        if len > MAX_PRINCIPAL_LEN {
+            return Err(format!("Invalid principal length {}", len));
+        }
+
+        let be_bytes = input.0.to_bytes_be();
+        let be_bytes = if input.0 == num_bigint::BigUint::default() {
+            &be_bytes[..0]
+        } else {
+            &be_bytes[..]
+        };
+        if be_bytes.len() > len {
+            return Err(format!("Nat does not fit in {:?} principal", kind));
+        }
+
+        let mut p_slice = vec![0u8; len - be_bytes.len()];
+        p_slice.extend_from_slice(be_bytes);
+
+        if let Some(tag) = kind.tag() {
+            if p_slice.last() != Some(&tag) {
+                return Err(format!("Nat is not a {:?} principal", kind));
+            }
+        }
+
+        Ok(Principal::from_slice(&p_slice))
+    }
+}
+
+pub trait ToBytes {
+    fn to_nonce_bytes(&self) -> NonceBytes;
+}
+
+impl ToBytes for Nat {
+    fn to_nonce_bytes(&self) -> NonceBytes {
+        let be_bytes = self.0.to_bytes_be();
+        let be_bytes_len = be_bytes.len();
+        let padding_bytes = 32 - be_bytes_len;
+
+        let mut p_slice = vec![0u8; padding_bytes];
+        p_slice.extend_from_slice(&be_bytes);
+
+        let nonce_bytes: [u8; 32] = p_slice.as_slice()[..].try_into().unwrap();
+        nonce_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal_from_hex(hex_str: &str) -> Result<Principal, String> {
+        let slice = hex::decode(hex_str).unwrap();
+
+        Principal::from_nat(Nat::from(num_bigint::BigUint::from_bytes_be(&slice[..])))
+    }
+
+    #[test]
+    fn user_principal_padding() {
+        let p = principal_from_hex("B2BF35A84FAC4062A1C0BC4F8891A4AF09C5E05E4155CAE6355B2402");
+
+        assert_eq!(
+            p.unwrap().to_text(),
+            "kyxzn-5aawk-7tlkc-pvrag-fioax-rhyre-nev4e-4lyc6-ifk4v-zrvlm-sae"
+        );
+    }
+
+    #[test]
+    fn canister_principal_padding() {
+        let p = principal_from_hex("3000F10101");
+
+        assert_eq!(p.unwrap().to_text(), "tcy4r-qaaaa-aaaab-qadyq-cai");
+    }
+
+    #[test]
+    fn eth_address_principal_padding() {
+        let p = principal_from_hex("c3e1c4f236223804b004eb4fe4226292a859a33d");
+
+        assert_eq!(
+            p.unwrap().to_text(),
+            "t2i2h-eqaaa-aaaaa-aaaaa-bq7by-tzdmi-ryasy-aj22p-4qrgf-evilg-rt2"
+        );
+    }
+
+    #[test]
+    fn principal_roundtrip() {
+        for text in &[
+            "tcy4r-qaaaa-aaaab-qadyq-cai",
+            "rdbii-uiaaa-aaaab-qadva-cai",
+            "kyxzn-5aawk-7tlkc-pvrag-fioax-rhyre-nev4e-4lyc6-ifk4v-zrvlm-sae",
+        ] {
+            let p = Principal::from_text(text).unwrap();
+
+            assert_eq!(Principal::from_nat(p.to_nat()).unwrap(), p);
+        }
+    }
+
+    #[test]
+    fn principal_with_kind() {
+        let user = "kyxzn-5aawk-7tlkc-pvrag-fioax-rhyre-nev4e-4lyc6-ifk4v-zrvlm-sae";
+        let user = Principal::from_text(user).unwrap();
+        let canister = Principal::from_text("tcy4r-qaaaa-aaaab-qadyq-cai").unwrap();
+
+        assert_eq!(
+            Principal::from_nat_with_kind(user.to_nat(), PrincipalKind::SelfAuthenticating),
+            Ok(user)
+        );
+        assert_eq!(
+            Principal::from_nat_with_kind(canister.to_nat(), PrincipalKind::Canister),
+            Ok(canister)
+        );
+        assert!(Principal::from_nat_with_kind(user.to_nat(), PrincipalKind::Canister).is_err());
+        assert!(Principal::from_nat_with_kind(
+            canister.to_nat(),
+            PrincipalKind::SelfAuthenticating
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn opaque_principal_with_leading_zero() {
+        // 20 bytes, `from_nat` pads it to 29
+        let mut slice = vec![0u8; 1];
+        slice.extend_from_slice(&hex::decode("1b864e1ca9189cfbd8a14a53a02e26b00ab5e9").unwrap());
+        let p = Principal::from_slice(&slice);
+
+        assert_ne!(Principal::from_nat(p.to_nat()).unwrap(), p);
+        assert_eq!(
+            Principal::from_nat_with_kind(p.to_nat(), PrincipalKind::Opaque(20)),
+            Ok(p)
+        );
+        assert!(Principal::from_nat_with_kind(p.to_nat(), PrincipalKind::Opaque(18)).is_err());
+        assert!(Principal::from_nat_with_kind(p.to_nat(), PrincipalKind::Opaque(30)).is_err());
+    }
+
+    #[test]
+    fn principal_too_long() {
+        assert!(principal_from_hex(&"01".repeat(30)).is_err());
+    }
+
+    #[test]
+    fn nonce_bytes_roundtrip() {
+        let nonce = Nat::from(0x0102_u32);
+        let nonce_bytes = nonce.to_nonce_bytes();
+
+        assert_eq!(&nonce_bytes[30..], &[1, 2]);
+        assert_eq!(nonce_bytes.to_nat(), nonce);
+    }
+}
@@ -3,7 +3,7 @@ import type { Principal } from '@dfinity/principal';
 
 export interface CallResult { 'return' : Array<number> }
 export type ConsumeMessageResponse = { 'Ok' : boolean } |
-  { 'Err' : string };
+  { 'Err' : TeraError };
 export interface OutgoingMessage {
   'msg_hash' : string,
   'msg_key' : Array<number>,
@@ -13,9 +13,18 @@ export interface OutgoingMessagePair {
   'msg_key' : string,
 }
 export type SendMessageResponse = { 'Ok' : OutgoingMessage } |
-  { 'Err' : string };
+  { 'Err' : TeraError };
 export type StoreMessageResponse = { 'Ok' : CallResult } |
-  { 'Err' : string };
+  { 'Err' : TeraError };
+export type TeraError = { 'NonceAlreadyConsumed' : null } |
+  { 'MessageNotFound' : null } |
+  { 'OutgoingQueueFull' : null } |
+  { 'Unauthorized' : null } |
+  { 'CallFailed' : { 'code' : number, 'msg' : string } } |
+  { 'InvalidPayload' : string } |
+  { 'InvalidBatch' : string } |
+  { 'SigningFailed' : string } |
+  { 'LastAdmin' : null };
 export default interface _SERVICE {
   'authorize' : (arg_0: Principal) => Promise<undefined>,
   'consume_message' : (
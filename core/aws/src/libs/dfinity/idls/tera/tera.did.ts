@@ -1,7 +1,18 @@
 export default ({ IDL }: { IDL: any }) => {
+  const TeraError = IDL.Variant({
+    NonceAlreadyConsumed: IDL.Null,
+    MessageNotFound: IDL.Null,
+    OutgoingQueueFull: IDL.Null,
+    Unauthorized: IDL.Null,
+    CallFailed: IDL.Record({ code: IDL.Nat8, msg: IDL.Text }),
+    InvalidPayload: IDL.Text,
+    InvalidBatch: IDL.Text,
+    SigningFailed: IDL.Text,
+    LastAdmin: IDL.Null,
+  });
   const ConsumeMessageResponse = IDL.Variant({
     Ok: IDL.Bool,
-    Err: IDL.Text,
+    Err: TeraError,
   });
   const OutgoingMessagePair = IDL.Record({
     msg_hash: IDL.Text,
@@ -13,12 +24,12 @@ export default ({ IDL }: { IDL: any }) => {
   });
   const SendMessageResponse = IDL.Variant({
     Ok: OutgoingMessage,
-    Err: IDL.Text,
+    Err: TeraError,
   });
   const CallResult = IDL.Record({ return: IDL.Vec(IDL.Nat8) });
   const StoreMessageResponse = IDL.Variant({
     Ok: CallResult,
-    Err: IDL.Text,
+    Err: TeraError,
   });
   return IDL.Service({
     authorize: IDL.Func([IDL.Principal], [], []),
@@ -1,16 +1,80 @@
 use candid::{candid_method, Principal};
-use ic_cdk_macros::update;
+use ic_cdk_macros::{query, update};
+use ic_kit::ic::caller;
 
-use crate::tera::STATE;
+use crate::{
+    common::types::{EventKind, Role, TeraError},
+    tera::STATE,
+};
 
-pub fn is_authorized() -> Result<(), String> {
-    STATE.with(|s| s.is_authorized())
+pub fn is_admin() -> Result<(), String> {
+    STATE
+        .with(|s| s.has_role(Role::Admin))
+        .map_err(|e| e.to_string())
 }
 
-#[update(name = "authorize")]
+pub fn is_relayer() -> Result<(), String> {
+    STATE
+        .with(|s| s.has_role(Role::Relayer))
+        .map_err(|e| e.to_string())
+}
+
+pub fn is_poller() -> Result<(), String> {
+    STATE
+        .with(|s| s.has_role(Role::Poller))
+        .map_err(|e| e.to_string())
+}
+
+/// Role required to call an update method, None if it is not meant for ingress
+pub fn required_role(method_name: &str) -> Option<Role> {
+    match method_name {
+        "store_message" | "store_messages" | "trigger_call" => Some(Role::Relayer),
+        "get_messages" | "remove_messages" | "sign_outgoing_batch" | "get_signer_address" => {
+            Some(Role::Poller)
+        }
+        "authorize"
+        | "grant_role"
+        | "revoke_role"
+        | "set_message_ttl"
+        | "set_low_cycles_threshold"
+        | "purge_stale_messages"
+        | "set_signing_config"
+        | "pause"
+        | "unpause" => Some(Role::Admin),
+        _ => None,
+    }
+}
+
+/// Grant every role to pid
+#[update(name = "authorize", guard = "is_admin")]
 #[candid_method(update)]
 fn authorize(other: Principal) {
-    STATE.with(|s| s.authorize(other))
+    Role::ALL.iter().for_each(|role| grant_role(other, *role))
+}
+
+#[update(name = "grant_role", guard = "is_admin")]
+#[candid_method(update)]
+fn grant_role(principal: Principal, role: Role) {
+    STATE.with(|s| {
+        s.grant_role(principal, role);
+        s.record_event(EventKind::Authorized { principal, role }, caller(), None);
+    })
+}
+
+#[update(name = "revoke_role", guard = "is_admin")]
+#[candid_method(update)]
+fn revoke_role(principal: Principal, role: Role) -> Result<(), TeraError> {
+    STATE.with(|s| {
+        s.revoke_role(principal, role)?;
+        s.record_event(EventKind::Unauthorized { principal, role }, caller(), None);
+        Ok(())
+    })
+}
+
+#[query(name = "list_roles", guard = "is_admin")]
+#[candid_method(query)]
+fn list_roles() -> Vec<(Principal, Vec<Role>)> {
+    STATE.with(|s| s.list_roles())
 }
 
 #[cfg(test)]
@@ -20,9 +84,12 @@ mod tests {
     use super::*;
 
     fn before_each() -> &'static mut MockContext {
-        MockContext::new()
+        let ctx = MockContext::new()
             .with_caller(mock_principals::alice())
-            .inject()
+            .inject();
+
+        STATE.with(|s| s.grant_role(mock_principals::alice(), Role::Admin));
+        ctx
     }
 
     #[test]
@@ -32,10 +99,66 @@ mod tests {
         authorize(mock_principals::bob());
 
         mock_ctx.update_caller(mock_principals::bob());
-        let is_authorized = STATE.with(|s| s.is_authorized());
 
-        println!("{:#?}", is_authorized);
+        assert!(is_admin().is_ok());
+        assert!(is_relayer().is_ok());
+        assert!(is_poller().is_ok());
+    }
 
-        assert!(is_authorized.is_ok());
+    #[test]
+    fn test_grant_and_revoke_role() {
+        let mock_ctx = before_each();
+
+        grant_role(mock_principals::bob(), Role::Relayer);
+        assert_eq!(list_roles().len(), 2);
+
+        mock_ctx.update_caller(mock_principals::bob());
+        assert!(is_relayer().is_ok());
+        assert!(is_poller().is_err());
+        assert!(is_admin().is_err());
+
+        mock_ctx.update_caller(mock_principals::alice());
+        assert!(revoke_role(mock_principals::bob(), Role::Relayer).is_ok());
+        assert!(revoke_role(mock_principals::alice(), Role::Admin).is_err());
+
+        mock_ctx.update_caller(mock_principals::bob());
+        assert!(is_relayer().is_err());
+    }
+
+    #[test]
+    fn test_role_changes_are_logged() {
+        before_each();
+
+        grant_role(mock_principals::bob(), Role::Poller);
+        assert!(revoke_role(mock_principals::bob(), Role::Poller).is_ok());
+        assert!(revoke_role(mock_principals::alice(), Role::Admin).is_err());
+
+        let kinds: Vec<EventKind> = STATE
+            .with(|s| s.get_events(0, 10))
+            .into_iter()
+            .map(|event| event.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                EventKind::Authorized {
+                    principal: mock_principals::bob(),
+                    role: Role::Poller
+                },
+                EventKind::Unauthorized {
+                    principal: mock_principals::bob(),
+                    role: Role::Poller
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_required_role() {
+        assert_eq!(required_role("store_message"), Some(Role::Relayer));
+        assert_eq!(required_role("store_messages"), Some(Role::Relayer));
+        assert_eq!(required_role("remove_messages"), Some(Role::Poller));
+        assert_eq!(required_role("revoke_role"), Some(Role::Admin));
+        assert_eq!(required_role("consume_message"), None);
     }
 }
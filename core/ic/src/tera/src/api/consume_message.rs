@@ -4,7 +4,10 @@ use ic_kit::ic::caller;
 
 use crate::{
     common::{
-        types::{ConsumeMessageResponse, IncomingMessageHashParams, Message, NonceBytes},
+        types::{
+            ConsumeMessageResponse, EventKind, IncomingMessageHashParams, Message, NonceBytes,
+            TeraError,
+        },
         utils::Keccak256HashFn,
     },
     tera::{ToNat, STATE},
@@ -14,16 +17,13 @@ use crate::{
 #[candid_method(update, rename = "consume_message")]
 fn consume(from: Principal, nonce_bytes: NonceBytes, payload: Vec<Nat>) -> ConsumeMessageResponse {
     let nonce = nonce_bytes.to_nat();
-    let nonce_exists = STATE.with(|s| s.nonce_exists(&nonce));
+    let caller = caller();
+
+    let nonce_exists = STATE.with(|s| s.nonce_exists(&from, &caller, &nonce));
     if nonce_exists {
-        return ConsumeMessageResponse(Err(format!(
-            "Message with nonce {} has already been consumed!",
-            nonce
-        )));
+        return ConsumeMessageResponse(Err(TeraError::NonceAlreadyConsumed));
     }
 
-    let caller = caller();
-
     let msg_hash = Message.calculate_hash(IncomingMessageHashParams {
         from: from.to_nat(),
         to: caller.to_nat(),
@@ -31,33 +31,16 @@ fn consume(from: Principal, nonce_bytes: NonceBytes, payload: Vec<Nat>) -> Consu
         payload: payload.clone(),
     });
 
-    let res = STATE.with(|s| {
-        let mut map = s.messages.borrow_mut();
-        let message = map.get_mut(&msg_hash);
-
-        if message.is_none() {
-            return Err("Attempted to consume invalid message".to_string());
-        }
-
-        let message_counter = message.unwrap();
-
-        // if there is exactly 1 message, we'll remove it from hashmap
-        if message_counter.clone() == 1 {
-            map.remove(&msg_hash);
-        } else {
-            *message_counter -= 1;
-        }
-
-        Ok(true)
-    });
+    let res = STATE.with(|s| s.consume_incoming_message(&msg_hash));
 
-    match res {
-        Ok(_) => {
-            STATE.with(|s| s.update_nonce(nonce));
-            ConsumeMessageResponse(res)
-        }
-        Err(error) => panic!("{:?}", error),
+    if res.is_ok() {
+        STATE.with(|s| {
+            s.update_nonce(from, caller, nonce);
+            s.record_event(EventKind::Consumed, caller, Some(msg_hash));
+        });
     }
+
+    ConsumeMessageResponse(res)
 }
 
 #[cfg(test)]
@@ -139,6 +122,18 @@ mod tests {
 
         let consume_message_2 = concume_message_with_nonce(mock_ctx, nonce.clone());
 
-        assert!(consume_message_2.0.is_err());
+        assert_eq!(consume_message_2.0, Err(TeraError::NonceAlreadyConsumed));
+    }
+
+    #[test]
+    fn test_consume_missing_message() {
+        before_each();
+        let nonce = bytes_from_nat(Nat::from(5));
+        let payload = [Nat::from(44444)].to_vec();
+
+        let consume_message = consume(mock_principals::john(), nonce, payload);
+
+        assert_eq!(consume_message.0, Err(TeraError::MessageNotFound));
+        assert!(STATE.with(|s| s.get_nonces()).is_empty());
     }
 }
@@ -0,0 +1,41 @@
+use candid::candid_method;
+use ic_cdk_macros::{query, update};
+use terabethia_common::CycleMetrics;
+
+use super::admin::is_admin;
+use crate::tera::STATE;
+
+#[query(name = "get_cycles")]
+#[candid_method(query, rename = "get_cycles")]
+fn get_cycles() -> CycleMetrics {
+    STATE.with(|s| s.get_cycles())
+}
+
+/// Set the cycle balance under which the canister reports itself as degraded
+#[update(name = "set_low_cycles_threshold", guard = "is_admin")]
+#[candid_method(update, rename = "set_low_cycles_threshold")]
+fn set_low_cycles_threshold(threshold: u64) {
+    STATE.with(|s| s.set_low_cycles_threshold(threshold))
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_kit::MockContext;
+
+    use super::*;
+
+    #[test]
+    fn test_get_cycles() {
+        MockContext::new().with_balance(2_000_000_000_000).inject();
+
+        let metrics = get_cycles();
+        assert_eq!(metrics.balance, 2_000_000_000_000);
+        assert!(!metrics.degraded);
+
+        set_low_cycles_threshold(3_000_000_000_000);
+
+        let metrics = get_cycles();
+        assert_eq!(metrics.low_cycles_threshold, 3_000_000_000_000);
+        assert!(metrics.degraded);
+    }
+}
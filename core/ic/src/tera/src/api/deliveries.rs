@@ -0,0 +1,152 @@
+use candid::candid_method;
+use ic_cdk_macros::{heartbeat, query};
+use ic_kit::ic::{id, spawn, time};
+
+use super::{admin::is_relayer, store_message::deliver};
+use crate::{common::types::Delivery, tera::STATE};
+
+/// Deliveries retried by a single heartbeat
+const MAX_RETRIES_PER_HEARTBEAT: usize = 10;
+
+/// Interval between two checks for due deliveries, ten seconds in nanoseconds
+const DELIVERY_CHECK_INTERVAL: u64 = 10 * 1_000_000_000;
+
+#[heartbeat]
+fn heartbeat() {
+    let now = time();
+    let next_check_at = STATE.with(|s| *s.next_delivery_check_at.borrow());
+    if now < next_check_at {
+        return;
+    }
+
+    STATE.with(|s| {
+        s.next_delivery_check_at
+            .replace(now + DELIVERY_CHECK_INTERVAL)
+    });
+    retry_due_deliveries(now);
+}
+
+/// Retry failed deliveries whose backoff ran out
+fn retry_due_deliveries(now: u64) {
+    let due = STATE.with(|s| s.get_due_deliveries(now, MAX_RETRIES_PER_HEARTBEAT));
+
+    for delivery in due {
+        // marked before spawning, so the next heartbeat does not pick it up while in flight
+        STATE.with(|s| s.start_delivery_attempt(&delivery.msg_hash, now));
+
+        spawn(async move {
+            deliver(
+                id(),
+                delivery.from,
+                delivery.to,
+                delivery.nonce,
+                delivery.payload,
+            )
+            .await;
+        });
+    }
+}
+
+/// Incoming messages that were stored but never delivered to their target canister
+#[query(name = "get_undelivered_messages", guard = "is_relayer")]
+#[candid_method(query, rename = "get_undelivered_messages")]
+fn get_undelivered_messages() -> Vec<Delivery> {
+    STATE.with(|s| s.get_undelivered_messages())
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::{Nat, Principal};
+    use ic_kit::{Method, MockContext, RawHandler, RejectionCode};
+
+    use super::*;
+    use crate::{
+        common::{
+            types::{DeliveryStatus, IncomingMessageHashParams, Message, TeraError},
+            utils::Keccak256HashFn,
+        },
+        tera::ToNat,
+    };
+
+    fn store(from: Principal, to: Principal, nonce: u64) -> String {
+        let msg_hash = Message.calculate_hash(IncomingMessageHashParams {
+            from: from.to_nat(),
+            to: to.to_nat(),
+            nonce: Nat::from(nonce),
+            payload: vec![Nat::from(1)],
+        });
+
+        STATE.with(|s| {
+            s.store_incoming_message(msg_hash.clone());
+            s.record_delivery(
+                msg_hash.clone(),
+                from,
+                to,
+                Nat::from(nonce),
+                vec![Nat::from(1)],
+            );
+        });
+
+        msg_hash
+    }
+
+    #[test]
+    fn test_retry_rejected_delivery() {
+        MockContext::new()
+            .with_handler(RawHandler::raw(Box::new(|_, _, _, _| {
+                Err((RejectionCode::CanisterError, "trapped".to_string()))
+            })))
+            .inject();
+        let msg_hash = store(
+            Principal::from_slice(&[1, 0x00]),
+            Principal::from_slice(&[2, 0x00]),
+            1,
+        );
+
+        let now = time();
+        STATE.with(|s| s.delivery_failed(&msg_hash, now, TeraError::InvalidBatch(String::new())));
+        let next_attempt_at = STATE.with(|s| s.get_delivery(&msg_hash).unwrap().next_attempt_at);
+
+        retry_due_deliveries(next_attempt_at.unwrap());
+
+        let delivery = STATE.with(|s| s.get_delivery(&msg_hash).unwrap());
+        assert_eq!(delivery.status, DeliveryStatus::Pending);
+        assert_eq!(delivery.attempts, 2);
+        assert!(matches!(
+            delivery.last_error,
+            Some(TeraError::CallFailed { .. })
+        ));
+        assert_eq!(STATE.with(|s| s.get_undelivered_messages()), vec![delivery]);
+        assert_eq!(STATE.with(|s| s.undelivered_messages_count()), 1);
+    }
+
+    #[test]
+    fn test_retry_delivers_message() {
+        MockContext::new()
+            .with_handler(Method::new().response(()))
+            .inject();
+        let msg_hash = store(
+            Principal::from_slice(&[1, 0x00]),
+            Principal::from_slice(&[2, 0x00]),
+            1,
+        );
+
+        let now = time();
+        STATE.with(|s| s.delivery_failed(&msg_hash, now, TeraError::InvalidBatch(String::new())));
+        let next_attempt_at = STATE.with(|s| s.get_delivery(&msg_hash).unwrap().next_attempt_at);
+
+        retry_due_deliveries(next_attempt_at.unwrap() - 1);
+        assert_eq!(
+            STATE.with(|s| s.get_delivery(&msg_hash).unwrap().attempts),
+            1
+        );
+
+        retry_due_deliveries(next_attempt_at.unwrap());
+
+        let delivery = STATE.with(|s| s.get_delivery(&msg_hash).unwrap());
+        assert_eq!(delivery.status, DeliveryStatus::Delivered);
+        assert_eq!(delivery.attempts, 2);
+        assert!(STATE.with(|s| s.get_undelivered_messages()).is_empty());
+        assert_eq!(STATE.with(|s| s.undelivered_messages_count()), 0);
+    }
+}
@@ -0,0 +1,18 @@
+use candid::candid_method;
+use ic_cdk_macros::query;
+
+use super::admin::is_admin;
+use crate::{common::types::Event, tera::STATE};
+
+/// Get a page of the event log, starting at the event with id `start`
+#[query(name = "get_events", guard = "is_admin")]
+#[candid_method(query, rename = "get_events")]
+fn get_events(start: u64, limit: u32) -> Vec<Event> {
+    STATE.with(|s| s.get_events(start, limit as usize))
+}
+
+#[query(name = "get_events_count", guard = "is_admin")]
+#[candid_method(query, rename = "get_events_count")]
+fn get_events_count() -> u64 {
+    STATE.with(|s| s.events_count())
+}
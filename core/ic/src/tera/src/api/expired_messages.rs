@@ -0,0 +1,97 @@
+use candid::candid_method;
+use ic_cdk_macros::{query, update};
+use ic_kit::ic::{caller, time};
+
+use super::admin::is_admin;
+use crate::{
+    common::types::{EventKind, ExpiredMessage, IncomingMessage},
+    tera::STATE,
+};
+
+#[query(name = "get_message_ttl", guard = "is_admin")]
+#[candid_method(query, rename = "get_message_ttl")]
+fn get_message_ttl() -> u64 {
+    STATE.with(|s| s.get_message_ttl())
+}
+
+/// Set the time to live of unconsumed incoming messages, in nanoseconds
+#[update(name = "set_message_ttl", guard = "is_admin")]
+#[candid_method(update, rename = "set_message_ttl")]
+fn set_message_ttl(ttl: u64) {
+    STATE.with(|s| {
+        s.set_message_ttl(ttl);
+        s.record_event(EventKind::MessageTtlSet(ttl), caller(), None);
+    })
+}
+
+#[query(name = "get_stale_messages", guard = "is_admin")]
+#[candid_method(query, rename = "get_stale_messages")]
+fn get_stale_messages(limit: u32) -> Vec<IncomingMessage> {
+    STATE.with(|s| s.get_stale_messages(time(), limit as usize))
+}
+
+/// Archive at most `limit` stale messages, oldest first
+#[update(name = "purge_stale_messages", guard = "is_admin")]
+#[candid_method(update, rename = "purge_stale_messages")]
+fn purge_stale_messages(limit: u32) -> Vec<ExpiredMessage> {
+    STATE.with(|s| {
+        let expired = s.purge_stale_messages(time(), limit as usize);
+        for message in expired.iter() {
+            s.record_event(EventKind::Expired, caller(), Some(message.msg_hash.clone()));
+        }
+
+        expired
+    })
+}
+
+/// Get a page of archived messages, starting at the `start`th one
+#[query(name = "get_expired_messages", guard = "is_admin")]
+#[candid_method(query, rename = "get_expired_messages")]
+fn get_expired_messages(start: u64, limit: u32) -> Vec<ExpiredMessage> {
+    STATE.with(|s| s.get_expired_messages(start, limit as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_kit::{mock_principals, MockContext};
+
+    use super::*;
+    use crate::common::{stable::IncomingMessageEntry, types::Role};
+
+    #[test]
+    fn test_ttl_and_purge_are_logged() {
+        MockContext::new()
+            .with_caller(mock_principals::alice())
+            .inject();
+        STATE.with(|s| s.grant_role(mock_principals::alice(), Role::Admin));
+
+        let msg_hash =
+            String::from("c9e23418a985892acc0fa031331080bfce112bdf841a3ae04a5181c6da1610b1");
+        STATE.with(|s| {
+            s.messages.borrow_mut().insert(
+                msg_hash.clone(),
+                IncomingMessageEntry {
+                    count: 1,
+                    stored_at: 0,
+                },
+            )
+        });
+
+        set_message_ttl(1_000);
+        let expired = purge_stale_messages(10);
+        assert_eq!(expired.len(), 1);
+
+        let events: Vec<(EventKind, Option<String>)> = STATE
+            .with(|s| s.get_events(0, 10))
+            .into_iter()
+            .map(|event| (event.kind, event.msg_hash))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (EventKind::MessageTtlSet(1_000), None),
+                (EventKind::Expired, Some(msg_hash)),
+            ]
+        );
+    }
+}
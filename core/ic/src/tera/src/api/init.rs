@@ -1,9 +1,26 @@
 use ic_cdk_macros::init;
 use ic_kit::ic::caller;
 
-use crate::tera::STATE;
+use crate::{
+    common::{
+        stable::STABLE_SCHEMA_VERSION,
+        types::{EventKind, Role},
+    },
+    tera::STATE,
+};
 
 #[init]
 fn init() {
-    STATE.with(|s| s.authorized.borrow_mut().push(caller()));
+    STATE.with(|s| s.set_schema_version(STABLE_SCHEMA_VERSION));
+    STATE.with(|s| {
+        s.grant_role(caller(), Role::Admin);
+        s.record_event(
+            EventKind::Authorized {
+                principal: caller(),
+                role: Role::Admin,
+            },
+            caller(),
+            None,
+        );
+    });
 }
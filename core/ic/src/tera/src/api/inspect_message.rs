@@ -2,12 +2,20 @@ use ic_cdk::api;
 use ic_cdk_macros::inspect_message;
 use ic_kit_sys::ic0;
 
-use super::admin::is_authorized;
+use super::admin::required_role;
+use super::pause::paused_scope;
+use crate::tera::STATE;
+
 const MAX_ARG_LIMIT: usize = 1_900_000; // 1.9MB
 
 #[inspect_message]
 fn inspect_message() {
-    if is_authorized().is_ok() && payload_size().is_ok() {
+    let authorized = required_role(&api::call::method_name())
+        .is_some_and(|role| STATE.with(|s| s.has_role(role)).is_ok());
+    let paused = paused_scope(&api::call::method_name())
+        .is_some_and(|scope| STATE.with(|s| s.ensure_not_paused(scope)).is_err());
+
+    if authorized && !paused && payload_size().is_ok() {
         api::call::accept_message()
     }
 }
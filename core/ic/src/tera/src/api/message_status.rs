@@ -0,0 +1,110 @@
+use candid::{candid_method, Nat, Principal};
+use ic_cdk_macros::query;
+
+use crate::{
+    common::{
+        types::{IncomingMessageHashParams, Message, MessageStatusInfo, Nonce},
+        utils::Keccak256HashFn,
+    },
+    tera::{ToNat, STATE},
+};
+
+/// Status of the incoming message from L1 with the given nonce and payload
+#[query(name = "get_message_status")]
+#[candid_method(query, rename = "get_message_status")]
+fn get_message_status(
+    from: Principal,
+    to: Principal,
+    nonce: Nonce,
+    payload: Vec<Nat>,
+) -> MessageStatusInfo {
+    let msg_hash = Message.calculate_hash(IncomingMessageHashParams {
+        from: from.to_nat(),
+        to: to.to_nat(),
+        nonce,
+        payload,
+    });
+
+    STATE.with(|s| s.get_message_status(&msg_hash))
+}
+
+#[query(name = "get_message_status_by_hash")]
+#[candid_method(query, rename = "get_message_status_by_hash")]
+fn get_message_status_by_hash(msg_hash: String) -> MessageStatusInfo {
+    STATE.with(|s| s.get_message_status(&msg_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_kit::MockContext;
+
+    use super::*;
+    use crate::common::types::MessageStatus;
+
+    fn scope() -> (Principal, Principal) {
+        (
+            Principal::from_slice(&[1, 0x00]),
+            Principal::from_slice(&[2, 0x00]),
+        )
+    }
+
+    fn msg_hash(nonce: &Nonce, payload: &[Nat]) -> String {
+        let (from, to) = scope();
+
+        Message.calculate_hash(IncomingMessageHashParams {
+            from: from.to_nat(),
+            to: to.to_nat(),
+            nonce: nonce.clone(),
+            payload: payload.to_vec(),
+        })
+    }
+
+    #[test]
+    fn test_message_status_lifecycle() {
+        MockContext::new().inject();
+        let (from, to) = scope();
+        let nonce = Nat::from(1);
+        let payload = vec![Nat::from(100)];
+        let msg_hash = msg_hash(&nonce, &payload);
+
+        let status = get_message_status(from, to, nonce.clone(), payload.clone());
+        assert_eq!(status.status, MessageStatus::Unknown);
+
+        STATE.with(|s| {
+            s.store_incoming_message(msg_hash.clone());
+            s.record_delivery(msg_hash.clone(), from, to, nonce.clone(), payload.clone());
+        });
+        let status = get_message_status_by_hash(msg_hash.clone());
+        assert_eq!(status.status, MessageStatus::Pending);
+        assert!(status.stored_at.is_some());
+
+        STATE.with(|s| {
+            s.start_delivery_attempt(&msg_hash, 10);
+            s.delivery_succeeded(&msg_hash);
+        });
+        let status = get_message_status(from, to, nonce.clone(), payload.clone());
+        assert_eq!(status.status, MessageStatus::Delivered);
+        assert_eq!(status.delivered_at, Some(10));
+
+        assert!(STATE
+            .with(|s| s.consume_incoming_message(&msg_hash))
+            .is_ok());
+        let status = get_message_status_by_hash(msg_hash);
+        assert_eq!(status.status, MessageStatus::Consumed);
+        assert_eq!(status.delivered_at, Some(10));
+        assert!(status.stored_at.is_some() && status.consumed_at.is_some());
+    }
+
+    #[test]
+    fn test_message_status_ignores_nonce() {
+        MockContext::new().inject();
+        let (from, to) = scope();
+        let nonce = Nat::from(7);
+
+        // a consumed nonce does not tell which message it was consumed by
+        STATE.with(|s| s.update_nonce(from, to, nonce.clone()));
+
+        let status = get_message_status(from, to, nonce, vec![]);
+        assert_eq!(status.status, MessageStatus::Unknown);
+    }
+}
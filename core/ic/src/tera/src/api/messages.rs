@@ -1,29 +1,61 @@
 use candid::candid_method;
 use ic_cdk_macros::{query, update};
+use ic_kit::ic::caller;
 
-use super::admin::is_authorized;
+use super::admin::is_poller;
 use crate::{
-    common::types::{OutgoingMessagePair, RemoveMessagesResponse},
+    common::types::{
+        EventKind, IndexedOutgoingMessage, MessageProof, MessagesRoot, OutgoingMessagePair,
+        RemoveMessagesResponse,
+    },
     tera::STATE,
 };
 
-#[update(name = "remove_messages", guard = "is_authorized")]
+#[update(name = "remove_messages", guard = "is_poller")]
 #[candid_method(update, rename = "remove_messages")]
 fn remove_messages(messages: Vec<OutgoingMessagePair>) -> RemoveMessagesResponse {
-    STATE.with(|s| RemoveMessagesResponse(s.remove_messages(messages)))
+    let caller = caller();
+
+    STATE.with(|s| {
+        let res = s.take_messages(messages).map(|removed| {
+            for message in removed {
+                s.record_event(EventKind::OutgoingRemoved, caller, Some(message.msg_hash));
+            }
+            true
+        });
+
+        RemoveMessagesResponse(res)
+    })
 }
 
-#[update(name = "get_messages", guard = "is_authorized")]
+#[update(name = "get_messages", guard = "is_poller")]
 #[candid_method(update, rename = "get_messages")]
 fn get_messages() -> Vec<OutgoingMessagePair> {
     STATE.with(|s| s.get_messages())
 }
 
-#[query(name = "get_messages_count", guard = "is_authorized")]
+#[query(name = "get_messages_page", guard = "is_poller")]
+#[candid_method(query, rename = "get_messages_page")]
+fn get_messages_page(after_index: u64, limit: u32) -> Vec<IndexedOutgoingMessage> {
+    STATE.with(|s| s.get_messages_page(after_index, limit as usize))
+}
+
+#[query(name = "get_messages_root", guard = "is_poller")]
+#[candid_method(query, rename = "get_messages_root")]
+fn get_messages_root() -> Option<MessagesRoot> {
+    STATE.with(|s| s.get_messages_root())
+}
+
+#[query(name = "get_message_proof", guard = "is_poller")]
+#[candid_method(query, rename = "get_message_proof")]
+fn get_message_proof(msg_key: String) -> Option<MessageProof> {
+    STATE.with(|s| s.get_message_proof(msg_key))
+}
+
+#[query(name = "get_messages_count", guard = "is_poller")]
 #[candid_method(query, rename = "get_messages_count")]
 fn get_messages_count() -> u32 {
-    let count = STATE.with(|s| s.outgoing_messages_count()) as u32;
-    count
+    STATE.with(|s| s.outgoing_messages_count()) as u32
 }
 
 #[cfg(test)]
@@ -0,0 +1,91 @@
+use candid::candid_method;
+use ic_cdk_macros::query;
+use terabethia_common::{serve_metrics, HttpRequest, HttpResponse};
+
+use crate::tera::STATE;
+
+/// Metrics in the Prometheus text format, served on `/metrics`
+#[query(name = "http_request")]
+#[candid_method(query, rename = "http_request")]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    serve_metrics(&request, |encoder| {
+        STATE.with(|s| {
+            encoder
+                .gauge(
+                    "incoming_messages_pending",
+                    s.incoming_messages_count(),
+                    "Incoming messages stored and not consumed yet",
+                )
+                .gauge(
+                    "outgoing_messages_pending",
+                    s.outgoing_messages_count() as u64,
+                    "Outgoing messages not removed by the poller yet",
+                )
+                .gauge(
+                    "undelivered_messages",
+                    s.undelivered_messages_count(),
+                    "Incoming messages not delivered to their target canister",
+                )
+                .counter(
+                    "consumed_messages_total",
+                    s.consumed_messages_count(),
+                    "Incoming messages consumed",
+                )
+                .counter(
+                    "nonces_seen_total",
+                    s.nonces_count(),
+                    "Nonces of consumed incoming messages",
+                )
+                .canister_metrics(&s.get_cycles());
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::{Nat, Principal};
+    use ic_kit::MockContext;
+
+    use super::*;
+
+    fn request(url: &str) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    #[test]
+    fn test_http_request() {
+        MockContext::new().inject();
+
+        let from = Principal::from_slice(&[1, 0x00]);
+        let to = Principal::from_slice(&[2, 0x00]);
+        STATE.with(|s| {
+            s.store_incoming_message("a".to_string());
+            s.store_incoming_message("b".to_string());
+            s.consume_incoming_message("b").unwrap();
+            s.update_nonce(from, to, Nat::from(1));
+            s.update_nonce(from, to, Nat::from(2));
+            s.update_nonce(from, to, Nat::from(5));
+        });
+
+        let response = http_request(request("/metrics"));
+        let body = String::from_utf8(response.body).unwrap();
+
+        assert_eq!(response.status_code, 200);
+        assert!(body.contains("\nincoming_messages_pending 1\n"));
+        assert!(body.contains("\nconsumed_messages_total 1\n"));
+        assert!(body.contains("\nnonces_seen_total 3\n"));
+        assert!(body.contains("\ncycle_balance "));
+    }
+
+    #[test]
+    fn test_http_request_not_found() {
+        MockContext::new().inject();
+
+        assert_eq!(http_request(request("/")).status_code, 404);
+    }
+}
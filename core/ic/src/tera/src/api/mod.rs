@@ -1,8 +1,16 @@
 pub mod admin;
 pub mod consume_message;
+pub mod cycles;
+pub mod deliveries;
+pub mod events;
+pub mod expired_messages;
 pub mod init;
 pub mod inspect_message;
+pub mod message_status;
 pub mod messages;
+pub mod metrics;
 pub mod nonce;
+pub mod pause;
 pub mod send_message;
+pub mod sign_batch;
 pub mod store_message;
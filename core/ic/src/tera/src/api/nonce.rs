@@ -1,11 +1,20 @@
 use candid::candid_method;
 use ic_cdk_macros::query;
 
-use super::admin::is_authorized;
-use crate::{common::types::Nonce, tera::STATE};
+use super::admin::is_poller;
+use crate::{
+    common::types::{NamespaceNonces, NonceNamespaceInfo},
+    tera::STATE,
+};
 
-#[query(name = "get_nonces", guard = "is_authorized")]
+#[query(name = "get_nonces", guard = "is_poller")]
 #[candid_method(query, rename = "get_nonces")]
-fn get_nonces() -> Vec<Nonce> {
+fn get_nonces() -> Vec<NamespaceNonces> {
     STATE.with(|s| s.get_nonces())
 }
+
+#[query(name = "get_nonce_namespaces", guard = "is_poller")]
+#[candid_method(query, rename = "get_nonce_namespaces")]
+fn get_nonce_namespaces() -> Vec<NonceNamespaceInfo> {
+    STATE.with(|s| s.get_nonce_namespaces())
+}
@@ -0,0 +1,92 @@
+use candid::candid_method;
+use ic_cdk_macros::{query, update};
+use ic_kit::ic::caller;
+
+use super::admin::is_admin;
+use crate::{
+    common::types::{EventKind, PauseScope},
+    tera::STATE,
+};
+
+/// Scope pausing a method, None if it can not be paused
+pub fn paused_scope(method_name: &str) -> Option<PauseScope> {
+    match method_name {
+        "store_message" | "store_messages" => Some(PauseScope::StoreMessage),
+        "send_message" => Some(PauseScope::SendMessage),
+        _ => None,
+    }
+}
+
+#[update(name = "pause", guard = "is_admin")]
+#[candid_method(update)]
+fn pause(scope: PauseScope) {
+    STATE.with(|s| {
+        s.pause(scope);
+        s.record_event(EventKind::Paused(scope), caller(), None);
+    })
+}
+
+#[update(name = "unpause", guard = "is_admin")]
+#[candid_method(update)]
+fn unpause(scope: PauseScope) {
+    STATE.with(|s| {
+        s.unpause(scope);
+        s.record_event(EventKind::Unpaused(scope), caller(), None);
+    })
+}
+
+/// Operations currently paused
+#[query(name = "get_pause_state")]
+#[candid_method(query)]
+fn get_pause_state() -> Vec<PauseScope> {
+    STATE.with(|s| s.get_pause_state())
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_kit::MockContext;
+
+    use super::*;
+    use crate::common::types::TeraError;
+
+    #[test]
+    fn test_pause_and_unpause() {
+        MockContext::new().inject();
+
+        pause(PauseScope::SendMessage);
+        assert_eq!(get_pause_state(), vec![PauseScope::SendMessage]);
+        assert_eq!(
+            STATE.with(|s| s.ensure_not_paused(PauseScope::SendMessage)),
+            Err(TeraError::Paused(PauseScope::SendMessage))
+        );
+        assert!(STATE
+            .with(|s| s.ensure_not_paused(PauseScope::StoreMessage))
+            .is_ok());
+
+        unpause(PauseScope::SendMessage);
+        assert!(get_pause_state().is_empty());
+
+        let kinds: Vec<EventKind> = STATE
+            .with(|s| s.get_events(0, 10))
+            .into_iter()
+            .map(|event| event.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                EventKind::Paused(PauseScope::SendMessage),
+                EventKind::Unpaused(PauseScope::SendMessage)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_paused_scope() {
+        assert_eq!(
+            paused_scope("store_messages"),
+            Some(PauseScope::StoreMessage)
+        );
+        assert_eq!(paused_scope("send_message"), Some(PauseScope::SendMessage));
+        assert_eq!(paused_scope("consume_message"), None);
+    }
+}
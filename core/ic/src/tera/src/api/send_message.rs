@@ -4,7 +4,7 @@ use ic_kit::ic::caller;
 
 use crate::{
     common::{
-        types::{Message, OutgoingMessageHashParams, SendMessageResponse},
+        types::{EventKind, Message, OutgoingMessageHashParams, PauseScope, SendMessageResponse},
         utils::Keccak256HashFn,
     },
     tera::{ToNat, STATE},
@@ -15,13 +15,24 @@ use crate::{
 fn send(to: Principal, payload: Vec<Nat>) -> SendMessageResponse {
     let caller = caller();
 
+    if let Err(error) = STATE.with(|s| s.ensure_not_paused(PauseScope::SendMessage)) {
+        return SendMessageResponse(Err(error));
+    }
+
     let msg_hash = Message.calculate_hash(OutgoingMessageHashParams {
         from: caller.to_nat(),
         to: to.to_nat(),
         payload: payload.clone(),
     });
 
-    STATE.with(|s| SendMessageResponse(s.store_outgoing_message(msg_hash)))
+    STATE.with(|s| {
+        let res = s.store_outgoing_message(msg_hash.clone());
+        if res.is_ok() {
+            s.record_event(EventKind::OutgoingSent, caller, Some(msg_hash));
+        }
+
+        SendMessageResponse(res)
+    })
 }
 
 #[cfg(test)]
@@ -29,6 +40,7 @@ mod tests {
     use ic_kit::{mock_principals, MockContext};
 
     use super::*;
+    use crate::common::types::TeraError;
 
     pub fn msg_hash() -> String {
         String::from("bce2b126cbac772605afb3fe363078f1a5b422b602cbf65bc59006cb77661482")
@@ -69,4 +81,18 @@ mod tests {
 
         assert_eq!(get_messages.first().unwrap().msg_hash, msg_hash());
     }
+
+    #[test]
+    fn test_send_message_paused() {
+        before_each();
+        STATE.with(|s| s.pause(PauseScope::SendMessage));
+
+        let send_message = send(Principal::from_slice(&[1, 0x00]), vec![Nat::from(1)]);
+
+        assert!(matches!(
+            send_message.0,
+            Err(TeraError::Paused(PauseScope::SendMessage))
+        ));
+        assert_eq!(STATE.with(|s| s.outgoing_messages_count()), 0);
+    }
 }
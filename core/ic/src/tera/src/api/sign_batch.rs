@@ -0,0 +1,181 @@
+use ic_kit::candid::candid_method;
+use ic_kit::ic;
+use ic_kit::macros::{query, update};
+
+use super::admin::{is_admin, is_poller};
+use crate::{
+    common::{
+        ecdsa::{eth_address, to_recoverable_signature, EcdsaSigner, ManagementCanister},
+        types::{SignBatchResponse, SignedBatch, SigningConfig, TeraError},
+        utils::{batch_digest, eth_address_bytes},
+    },
+    tera::STATE,
+};
+
+/// Set the threshold ECDSA key and the L1 domain outgoing batches are signed for
+#[update(name = "set_signing_config", guard = "is_admin")]
+#[candid_method(update, rename = "set_signing_config")]
+fn set_signing_config(config: SigningConfig) -> Result<(), TeraError> {
+    if config.key_name.is_empty() {
+        return Err(TeraError::InvalidPayload(String::from(
+            "ECDSA key name can not be empty",
+        )));
+    }
+    eth_address_bytes(&config.l1_contract).map_err(TeraError::InvalidPayload)?;
+
+    STATE.with(|s| s.set_signing_config(config));
+    Ok(())
+}
+
+#[query(name = "get_signing_config")]
+#[candid_method(query, rename = "get_signing_config")]
+fn get_signing_config() -> Option<SigningConfig> {
+    STATE.with(|s| s.get_signing_config())
+}
+
+#[update(name = "sign_outgoing_batch", guard = "is_poller")]
+#[candid_method(update, rename = "sign_outgoing_batch")]
+async fn sign_outgoing_batch(from_index: u64, to_index: u64) -> SignBatchResponse {
+    let signed_batch = match signing_config() {
+        Ok(config) => {
+            let signer = ManagementCanister {
+                key_name: config.key_name.clone(),
+            };
+            sign_batch(&signer, &config, from_index, to_index).await
+        }
+        Err(error) => Err(error),
+    };
+
+    SignBatchResponse(signed_batch)
+}
+
+#[update(name = "get_signer_address", guard = "is_poller")]
+#[candid_method(update, rename = "get_signer_address")]
+async fn get_signer_address() -> Result<String, TeraError> {
+    let signer = ManagementCanister {
+        key_name: signing_config()?.key_name,
+    };
+    let public_key = signer
+        .public_key()
+        .await
+        .map_err(TeraError::SigningFailed)?;
+
+    eth_address(&public_key).map_err(TeraError::SigningFailed)
+}
+
+fn signing_config() -> Result<SigningConfig, TeraError> {
+    STATE
+        .with(|s| s.get_signing_config())
+        .ok_or_else(|| TeraError::SigningFailed(String::from("Signing is not configured")))
+}
+
+pub async fn sign_batch<S: EcdsaSigner>(
+    signer: &S,
+    config: &SigningConfig,
+    from_index: u64,
+    to_index: u64,
+) -> Result<SignedBatch, TeraError> {
+    let batch = STATE.with(|s| s.get_outgoing_batch(from_index, to_index))?;
+    let digest = batch_digest(config, &ic::id(), from_index, to_index, &batch)
+        .map_err(TeraError::InvalidPayload)?;
+
+    let public_key = signer
+        .public_key()
+        .await
+        .map_err(TeraError::SigningFailed)?;
+    let signature = signer
+        .sign(digest.clone())
+        .await
+        .map_err(TeraError::SigningFailed)?;
+    let signature = to_recoverable_signature(&digest, &signature, &public_key)
+        .map_err(TeraError::SigningFailed)?;
+
+    let (indexes, msg_hashes) = batch
+        .into_iter()
+        .map(|(index, message)| (index, message.msg_hash))
+        .unzip();
+
+    Ok(SignedBatch {
+        from_index,
+        to_index,
+        indexes,
+        msg_hashes,
+        digest: hex::encode(digest),
+        signature: hex::encode(signature),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_kit::MockContext;
+    use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+
+    use super::*;
+    use crate::common::ecdsa::mock::MockSigner;
+
+    pub fn msg_hash() -> String {
+        String::from("d0379be15bb6f33737b756e512dad1e71226b31fa648da57811f930badf6c163")
+    }
+
+    fn config() -> SigningConfig {
+        SigningConfig {
+            key_name: String::from("dfx_test_key"),
+            chain_id: 5,
+            l1_contract: String::from("0x60dc1a8a4d7b5b5a4e13a49e8e6d2ecc5c4e0b4d"),
+        }
+    }
+
+    #[async_std::test]
+    async fn test_sign_batch() {
+        MockContext::new().inject();
+        let signer = MockSigner::default();
+        let message = STATE
+            .with(|s| s.store_outgoing_message(msg_hash()))
+            .unwrap();
+
+        let signed_batch = sign_batch(&signer, &config(), 1, 1).await.unwrap();
+
+        assert_eq!(signed_batch.indexes, vec![1]);
+        assert_eq!(signed_batch.msg_hashes, vec![message.msg_hash]);
+
+        let digest = hex::decode(&signed_batch.digest).unwrap();
+        let signature = hex::decode(&signed_batch.signature).unwrap();
+        assert_eq!(signature.len(), 65);
+
+        let recovery_id = RecoveryId::from_byte(signature[64] - 27).unwrap();
+        let recovered = VerifyingKey::recover_from_prehash(
+            &digest,
+            &Signature::from_slice(&signature[..64]).unwrap(),
+            recovery_id,
+        )
+        .unwrap();
+
+        assert_eq!(&recovered, signer.signing_key.verifying_key());
+    }
+
+    #[async_std::test]
+    async fn test_sign_empty_batch() {
+        MockContext::new().inject();
+        let signer = MockSigner::default();
+
+        let signed_batch = sign_batch(&signer, &config(), 1, 10).await;
+
+        assert!(signed_batch.is_err());
+    }
+
+    #[test]
+    fn test_set_signing_config() {
+        MockContext::new().inject();
+        assert_eq!(get_signing_config(), None);
+
+        let invalid_contract = SigningConfig {
+            l1_contract: String::from("0x1234"),
+            ..config()
+        };
+        assert!(set_signing_config(invalid_contract).is_err());
+        assert!(signing_config().is_err());
+
+        assert!(set_signing_config(config()).is_ok());
+        assert_eq!(get_signing_config(), Some(config()));
+    }
+}
@@ -1,16 +1,23 @@
 use candid::{candid_method, Nat, Principal};
-use ic_kit::macros::update;
+use futures::future::join_all;
+use ic_kit::{
+    ic::{caller, time},
+    macros::update,
+};
 
-use super::admin::is_authorized;
+use super::admin::is_relayer;
 use crate::{
     common::{
-        types::{CallResult, IncomingMessageHashParams, Message, Nonce, StoreMessageResponse},
+        types::{
+            CallResult, EventKind, IncomingMessageHashParams, Message, Nonce, PauseScope,
+            StoreMessageParams, StoreMessageResponse, TeraError,
+        },
         utils::Keccak256HashFn,
     },
     tera::{ToNat, STATE},
 };
 
-#[update(name = "trigger_call", guard = "is_authorized")]
+#[update(name = "trigger_call", guard = "is_relayer")]
 #[candid_method(update, rename = "trigger_call")]
 async fn trigger_call(
     from: Principal,
@@ -18,14 +25,17 @@ async fn trigger_call(
     nonce: Nonce,
     payload: Vec<Nat>,
 ) -> StoreMessageResponse {
-    let nonce_exists = STATE.with(|s| s.nonce_exists(&nonce));
-    if nonce_exists {
-        return StoreMessageResponse(Err(format!(
-            "Transaction with nonce {} already exists!",
-            nonce
-        )));
-    }
+    deliver(caller(), from, to, nonce, payload).await
+}
 
+/// Call handle_message on the target canister and record the outcome of the delivery
+pub(crate) async fn deliver(
+    caller: Principal,
+    from: Principal,
+    to: Principal,
+    nonce: Nonce,
+    payload: Vec<Nat>,
+) -> StoreMessageResponse {
     let msg_hash = Message.calculate_hash(IncomingMessageHashParams {
         from: from.to_nat(),
         to: to.to_nat(),
@@ -33,24 +43,67 @@ async fn trigger_call(
         payload: payload.clone(),
     });
 
-    let message_exists = STATE.with(|s| s.message_exists(msg_hash));
+    let nonce_exists = STATE.with(|s| s.nonce_exists(&from, &to, &nonce));
+    if nonce_exists {
+        STATE.with(|s| s.remove_delivery(&msg_hash));
+        return StoreMessageResponse(Err(TeraError::NonceAlreadyConsumed));
+    }
+
+    let message_exists = STATE.with(|s| s.message_exists(msg_hash.clone()));
 
     if message_exists.is_err() {
+        STATE.with(|s| s.remove_delivery(&msg_hash));
         return StoreMessageResponse(Err(message_exists.err().unwrap()));
     }
 
-    let args_raw = ic_kit::candid::encode_args((&from, &nonce, &payload)).unwrap();
+    STATE.with(|s| {
+        s.record_delivery(msg_hash.clone(), from, to, nonce.clone(), payload.clone());
+        s.start_delivery_attempt(&msg_hash, time());
+    });
+
+    let args_raw = match ic_kit::candid::encode_args((&from, &nonce, &payload)) {
+        Ok(args_raw) => args_raw,
+        Err(error) => {
+            let error = TeraError::InvalidPayload(error.to_string());
+            STATE.with(|s| {
+                s.delivery_failed(&msg_hash, time(), error.clone());
+                s.record_event(
+                    EventKind::DeliveryFailed(error.clone()),
+                    caller,
+                    Some(msg_hash),
+                );
+            });
+            return StoreMessageResponse(Err(error));
+        }
+    };
 
     match ic_kit::ic::call_raw(to, "handle_message", args_raw, 0).await {
-        Ok(x) => StoreMessageResponse(Ok(CallResult { r#return: x })),
-        Err((code, msg)) => StoreMessageResponse(Err(format!(
-            "An error happened during the call: {}: {}",
-            code as u8, msg
-        ))),
+        Ok(x) => {
+            STATE.with(|s| {
+                s.delivery_succeeded(&msg_hash);
+                s.record_event(EventKind::Delivered, caller, Some(msg_hash));
+            });
+            StoreMessageResponse(Ok(CallResult { r#return: x }))
+        }
+        Err((code, msg)) => {
+            let error = TeraError::CallFailed {
+                code: code as u8,
+                msg,
+            };
+            STATE.with(|s| {
+                s.delivery_failed(&msg_hash, time(), error.clone());
+                s.record_event(
+                    EventKind::DeliveryFailed(error.clone()),
+                    caller,
+                    Some(msg_hash),
+                );
+            });
+            StoreMessageResponse(Err(error))
+        }
     }
 }
 
-#[update(name = "store_message", guard = "is_authorized")]
+#[update(name = "store_message", guard = "is_relayer")]
 #[candid_method(update, rename = "store_message")]
 async fn store_message(
     from: Principal,
@@ -58,29 +111,72 @@ async fn store_message(
     nonce: Nonce,
     payload: Vec<Nat>,
 ) -> StoreMessageResponse {
-    let nonce_exists = STATE.with(|s| s.nonce_exists(&nonce));
+    if let Err(error) = store(caller(), &from, &to, &nonce, &payload) {
+        return StoreMessageResponse(Err(error));
+    }
+
+    trigger_call(from, to, nonce, payload).await
+}
+
+/// Store a batch of messages, then deliver the stored ones concurrently.
+/// Results are in the order of `messages`.
+#[update(name = "store_messages", guard = "is_relayer")]
+#[candid_method(update, rename = "store_messages")]
+async fn store_messages(messages: Vec<StoreMessageParams>) -> Vec<StoreMessageResponse> {
+    let caller = caller();
+
+    // nothing interleaves before the first await, the whole batch is stored at once
+    let stored: Vec<Result<(), TeraError>> = messages
+        .iter()
+        .map(|m| store(caller, &m.from, &m.to, &m.nonce, &m.payload))
+        .collect();
+
+    let calls = messages
+        .into_iter()
+        .zip(stored)
+        .map(|(m, stored)| async move {
+            match stored {
+                Ok(()) => deliver(caller, m.from, m.to, m.nonce, m.payload).await,
+                Err(error) => StoreMessageResponse(Err(error)),
+            }
+        });
+
+    join_all(calls).await
+}
+
+/// Record an incoming message, unless its nonce was already consumed
+fn store(
+    caller: Principal,
+    from: &Principal,
+    to: &Principal,
+    nonce: &Nonce,
+    payload: &[Nat],
+) -> Result<(), TeraError> {
+    STATE.with(|s| s.ensure_not_paused(PauseScope::StoreMessage))?;
+
+    let nonce_exists = STATE.with(|s| s.nonce_exists(from, to, nonce));
     if nonce_exists {
-        return StoreMessageResponse(Err(format!(
-            "Transaction with nonce {} already exists!",
-            nonce
-        )));
+        return Err(TeraError::NonceAlreadyConsumed);
     }
 
     let msg_hash = Message.calculate_hash(IncomingMessageHashParams {
         from: from.to_nat(),
         to: to.to_nat(),
         nonce: nonce.clone(),
-        payload: payload.clone(),
+        payload: payload.to_vec(),
     });
 
-    STATE.with(|s| s.store_incoming_message(msg_hash));
+    STATE.with(|s| {
+        s.store_incoming_message(msg_hash.clone());
+        s.record_event(EventKind::IncomingStored, caller, Some(msg_hash));
+    });
 
-    trigger_call(from, to, nonce, payload).await
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use ic_kit::{mock_principals, MockContext};
+    use ic_kit::{mock_principals, Method, MockContext};
     use std::str::FromStr;
 
     use super::*;
@@ -125,4 +221,60 @@ mod tests {
         // assert!(store_msg.is_ok());
         // println!("{:#?}", store_message);
     }
+
+    fn params(nonce: u64) -> StoreMessageParams {
+        StoreMessageParams {
+            from: Principal::from_slice(&[1, 0x00]),
+            to: Principal::from_slice(&[2, 0x00]),
+            nonce: Nat::from(nonce),
+            payload: vec![Nat::from(nonce * 100)],
+        }
+    }
+
+    #[async_test]
+    async fn test_store_messages() {
+        MockContext::new()
+            .with_caller(Principal::from_slice(&[3, 0x00]))
+            .with_handler(Method::new().response(()))
+            .inject();
+
+        let consumed = params(2);
+        STATE.with(|s| s.update_nonce(consumed.from, consumed.to, consumed.nonce.clone()));
+
+        let res = store_messages(vec![params(1), consumed, params(3)]).await;
+
+        assert_eq!(res.len(), 3);
+        assert!(res[0].0.is_ok());
+        assert!(matches!(res[1].0, Err(TeraError::NonceAlreadyConsumed)));
+        assert!(res[2].0.is_ok());
+
+        for nonce in [1, 3] {
+            let m = params(nonce);
+            let msg_hash = Message.calculate_hash(IncomingMessageHashParams {
+                from: m.from.to_nat(),
+                to: m.to.to_nat(),
+                nonce: m.nonce,
+                payload: m.payload,
+            });
+            assert!(STATE.with(|s| s.message_exists(msg_hash)).is_ok());
+        }
+        assert_eq!(STATE.with(|s| s.events_count()), 4);
+    }
+
+    #[async_test]
+    async fn test_store_messages_paused() {
+        MockContext::new()
+            .with_caller(Principal::from_slice(&[3, 0x00]))
+            .with_handler(Method::new().response(()))
+            .inject();
+        STATE.with(|s| s.pause(PauseScope::StoreMessage));
+
+        let res = store_messages(vec![params(1)]).await;
+
+        assert!(matches!(
+            res[0].0,
+            Err(TeraError::Paused(PauseScope::StoreMessage))
+        ));
+        assert_eq!(STATE.with(|s| s.incoming_messages_count()), 0);
+    }
 }
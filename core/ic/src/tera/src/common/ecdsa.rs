@@ -0,0 +1,234 @@
+use async_trait::async_trait;
+use candid::{CandidType, Deserialize, Principal};
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use sha3::{Digest, Keccak256};
+
+/// Cycles attached to every `sign_with_ecdsa` call
+const SIGN_WITH_ECDSA_CYCLES: u64 = 26_153_846_153;
+
+#[derive(CandidType, Deserialize, Clone)]
+pub enum EcdsaCurve {
+    #[serde(rename = "secp256k1")]
+    Secp256k1,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct EcdsaKeyId {
+    pub curve: EcdsaCurve,
+    pub name: String,
+}
+
+#[derive(CandidType, Deserialize)]
+struct EcdsaPublicKeyArgument {
+    canister_id: Option<Principal>,
+    derivation_path: Vec<Vec<u8>>,
+    key_id: EcdsaKeyId,
+}
+
+#[derive(CandidType, Deserialize)]
+struct EcdsaPublicKeyResponse {
+    public_key: Vec<u8>,
+    chain_code: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct SignWithEcdsaArgument {
+    message_hash: Vec<u8>,
+    derivation_path: Vec<Vec<u8>>,
+    key_id: EcdsaKeyId,
+}
+
+#[derive(CandidType, Deserialize)]
+struct SignWithEcdsaResponse {
+    signature: Vec<u8>,
+}
+
+/// Source of secp256k1 signatures, the management canister in production
+#[async_trait(?Send)]
+pub trait EcdsaSigner {
+    /// SEC1 encoded public key of the signing key
+    async fn public_key(&self) -> Result<Vec<u8>, String>;
+
+    /// 64 bytes `r || s` signature of a 32 bytes message hash
+    async fn sign(&self, message_hash: Vec<u8>) -> Result<Vec<u8>, String>;
+}
+
+pub struct ManagementCanister {
+    pub key_name: String,
+}
+
+impl ManagementCanister {
+    fn key_id(&self) -> EcdsaKeyId {
+        EcdsaKeyId {
+            curve: EcdsaCurve::Secp256k1,
+            name: self.key_name.clone(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl EcdsaSigner for ManagementCanister {
+    async fn public_key(&self) -> Result<Vec<u8>, String> {
+        let args = EcdsaPublicKeyArgument {
+            canister_id: None,
+            derivation_path: vec![],
+            key_id: self.key_id(),
+        };
+
+        let (response,): (EcdsaPublicKeyResponse,) = ic_kit::ic::call(
+            Principal::management_canister(),
+            "ecdsa_public_key",
+            (args,),
+        )
+        .await
+        .map_err(|(code, msg)| format!("ecdsa_public_key failed: {}: {}", code as u8, msg))?;
+
+        Ok(response.public_key)
+    }
+
+    async fn sign(&self, message_hash: Vec<u8>) -> Result<Vec<u8>, String> {
+        let args = SignWithEcdsaArgument {
+            message_hash,
+            derivation_path: vec![],
+            key_id: self.key_id(),
+        };
+
+        let (response,): (SignWithEcdsaResponse,) = ic_kit::ic::call_with_payment(
+            Principal::management_canister(),
+            "sign_with_ecdsa",
+            (args,),
+            SIGN_WITH_ECDSA_CYCLES,
+        )
+        .await
+        .map_err(|(code, msg)| format!("sign_with_ecdsa failed: {}: {}", code as u8, msg))?;
+
+        Ok(response.signature)
+    }
+}
+
+/// Ethereum address of a SEC1 encoded secp256k1 public key
+pub fn eth_address(public_key: &[u8]) -> Result<String, String> {
+    let key = VerifyingKey::from_sec1_bytes(public_key).map_err(|e| e.to_string())?;
+    let uncompressed = key.to_encoded_point(false);
+    let hash = Keccak256::digest(&uncompressed.as_bytes()[1..]);
+
+    Ok(format!("0x{}", hex::encode(&hash[12..])))
+}
+
+/// Turn a 64 bytes `r || s` signature into the 65 bytes `r || s || v` form
+/// expected by `ecrecover`, with `s` normalized to the lower half of the curve order
+pub fn to_recoverable_signature(
+    message_hash: &[u8],
+    signature: &[u8],
+    public_key: &[u8],
+) -> Result<Vec<u8>, String> {
+    let expected_key = VerifyingKey::from_sec1_bytes(public_key).map_err(|e| e.to_string())?;
+    let signature = Signature::from_slice(signature).map_err(|e| e.to_string())?;
+    let signature = signature.normalize_s().unwrap_or(signature);
+
+    for recovery_byte in 0..2 {
+        let recovery_id = RecoveryId::from_byte(recovery_byte).unwrap();
+        let recovered = VerifyingKey::recover_from_prehash(message_hash, &signature, recovery_id);
+
+        if recovered.ok() == Some(expected_key) {
+            let mut recoverable = signature.to_bytes().to_vec();
+            recoverable.push(27 + recovery_byte);
+            return Ok(recoverable);
+        }
+    }
+
+    Err("Signature does not match the canister public key".to_string())
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use k256::ecdsa::SigningKey;
+
+    /// Local stand-in for the threshold ECDSA key
+    pub struct MockSigner {
+        pub signing_key: SigningKey,
+    }
+
+    impl Default for MockSigner {
+        fn default() -> Self {
+            MockSigner {
+                signing_key: SigningKey::from_slice(&[7u8; 32]).unwrap(),
+            }
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl EcdsaSigner for MockSigner {
+        async fn public_key(&self) -> Result<Vec<u8>, String> {
+            Ok(self
+                .signing_key
+                .verifying_key()
+                .to_encoded_point(true)
+                .as_bytes()
+                .to_vec())
+        }
+
+        async fn sign(&self, message_hash: Vec<u8>) -> Result<Vec<u8>, String> {
+            let (signature, _) = self
+                .signing_key
+                .sign_prehash_recoverable(&message_hash)
+                .map_err(|e| e.to_string())?;
+
+            Ok(signature.to_bytes().to_vec())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockSigner;
+    use super::*;
+
+    #[test]
+    fn test_eth_address() {
+        // well known address of the private key 0x00..01
+        let public_key =
+            hex::decode("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+                .unwrap();
+
+        assert_eq!(
+            eth_address(&public_key).unwrap(),
+            "0x7e5f4552091a69125d5dfcb7b8c2659029395bdf"
+        );
+    }
+
+    #[async_std::test]
+    async fn test_recoverable_signature() {
+        let signer = MockSigner::default();
+        let message_hash = Keccak256::digest(b"terabethia").to_vec();
+
+        let public_key = signer.public_key().await.unwrap();
+        let signature = signer.sign(message_hash.clone()).await.unwrap();
+
+        let recoverable = to_recoverable_signature(&message_hash, &signature, &public_key).unwrap();
+        assert_eq!(recoverable.len(), 65);
+        assert!(recoverable[64] == 27 || recoverable[64] == 28);
+
+        let recovery_id = RecoveryId::from_byte(recoverable[64] - 27).unwrap();
+        let signature = Signature::from_slice(&recoverable[..64]).unwrap();
+        let recovered =
+            VerifyingKey::recover_from_prehash(&message_hash, &signature, recovery_id).unwrap();
+
+        assert_eq!(&recovered, signer.signing_key.verifying_key());
+    }
+
+    #[async_std::test]
+    async fn test_recoverable_signature_wrong_key() {
+        let signer = MockSigner::default();
+        let other = MockSigner {
+            signing_key: k256::ecdsa::SigningKey::from_slice(&[9u8; 32]).unwrap(),
+        };
+        let message_hash = Keccak256::digest(b"terabethia").to_vec();
+
+        let signature = signer.sign(message_hash.clone()).await.unwrap();
+        let other_public_key = other.public_key().await.unwrap();
+
+        assert!(to_recoverable_signature(&message_hash, &signature, &other_public_key).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use candid::{CandidType, Deserialize};
+use sha3::{Digest, Keccak256};
+
+pub type MerkleNode = [u8; 32];
+
+/// Append-only binary Merkle tree.
+///
+/// Pairs are hashed in sorted order and an odd node is promoted to the next
+/// layer unchanged, which keeps proofs compatible with OpenZeppelin's
+/// `MerkleProof.verify` on L1.
+#[derive(CandidType, Deserialize, Default, Clone)]
+pub struct MerkleTree {
+    layers: Vec<Vec<MerkleNode>>,
+}
+
+impl MerkleTree {
+    pub fn from_leaves<I: IntoIterator<Item = MerkleNode>>(leaves: I) -> Self {
+        let mut tree = MerkleTree::default();
+        leaves.into_iter().for_each(|leaf| tree.push(leaf));
+        tree
+    }
+
+    /// Append a leaf and update the right-most path up to the root
+    pub fn push(&mut self, leaf: MerkleNode) {
+        if self.layers.is_empty() {
+            self.layers.push(Vec::new());
+        }
+        self.layers[0].push(leaf);
+
+        let mut level = 0;
+        while self.layers[level].len() > 1 {
+            let layer = &self.layers[level];
+            let index = layer.len() - 1;
+            let parent = if index % 2 == 1 {
+                hash_pair(&layer[index - 1], &layer[index])
+            } else {
+                layer[index]
+            };
+
+            if self.layers.len() == level + 1 {
+                self.layers.push(Vec::new());
+            }
+
+            let next = &mut self.layers[level + 1];
+            let parent_index = index / 2;
+            if parent_index < next.len() {
+                next[parent_index] = parent;
+            } else {
+                next.push(parent);
+            }
+
+            level += 1;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.layers.first().map_or(0, |leaves| leaves.len())
+    }
+
+    pub fn root(&self) -> Option<MerkleNode> {
+        self.layers.last().and_then(|layer| layer.first()).cloned()
+    }
+
+    /// Sibling hashes from the leaf at `position` up to the root
+    pub fn proof(&self, position: usize) -> Option<Vec<MerkleNode>> {
+        if position >= self.len() {
+            return None;
+        }
+
+        let mut proof = Vec::new();
+        let mut index = position;
+        for layer in &self.layers[..self.layers.len() - 1] {
+            let sibling = index ^ 1;
+            if sibling < layer.len() {
+                proof.push(layer[sibling]);
+            }
+            index /= 2;
+        }
+
+        Some(proof)
+    }
+}
+
+/// Leaf standing in for a message whose hash is not hex, stored before hashes
+/// were checked. It keeps the position of every later leaf, and no message
+/// hash can be proven against it.
+pub const INVALID_LEAF: MerkleNode = [0u8; 32];
+
+/// Leaves are the keccak256 of the message hash, so that a leaf can never be
+/// mistaken for an inner node of the tree
+pub fn leaf_hash(msg_hash: &str) -> Result<MerkleNode, String> {
+    let msg_hash_bytes =
+        hex::decode(msg_hash).map_err(|_| format!("Invalid message hash {}", msg_hash))?;
+
+    let mut leaf = [0u8; 32];
+    leaf.copy_from_slice(&Keccak256::digest(&msg_hash_bytes));
+    Ok(leaf)
+}
+
+pub fn hash_pair(a: &MerkleNode, b: &MerkleNode) -> MerkleNode {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+
+    let mut hasher = Keccak256::new();
+    hasher.update(first);
+    hasher.update(second);
+
+    let mut node = [0u8; 32];
+    node.copy_from_slice(&hasher.finalize());
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verify_proof(leaf: MerkleNode, proof: &[MerkleNode], root: MerkleNode) -> bool {
+        proof
+            .iter()
+            .fold(leaf, |node, sibling| hash_pair(&node, sibling))
+            == root
+    }
+
+    fn naive_root(mut layer: Vec<MerkleNode>) -> MerkleNode {
+        while layer.len() > 1 {
+            layer = layer
+                .chunks(2)
+                .map(|pair| match pair {
+                    [a, b] => hash_pair(a, b),
+                    [a] => *a,
+                    _ => unreachable!(),
+                })
+                .collect();
+        }
+        layer[0]
+    }
+
+    fn leaves(count: u8) -> Vec<MerkleNode> {
+        (0..count).map(|i| [i; 32]).collect()
+    }
+
+    #[test]
+    fn test_empty_tree() {
+        let tree = MerkleTree::default();
+
+        assert_eq!(tree.len(), 0);
+        assert!(tree.root().is_none());
+        assert!(tree.proof(0).is_none());
+    }
+
+    #[test]
+    fn test_single_leaf_root() {
+        let tree = MerkleTree::from_leaves(leaves(1));
+
+        assert_eq!(tree.root().unwrap(), [0u8; 32]);
+        assert!(tree.proof(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_root_matches_manual_hashing() {
+        let l = leaves(3);
+        let tree = MerkleTree::from_leaves(l.clone());
+
+        let expected = hash_pair(&hash_pair(&l[0], &l[1]), &l[2]);
+
+        assert_eq!(tree.root().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_incremental_root_matches_naive_root() {
+        let mut tree = MerkleTree::default();
+
+        for count in 1..=17 {
+            tree.push([count; 32]);
+
+            let expected = naive_root((1..=count).map(|i| [i; 32]).collect());
+            assert_eq!(tree.root().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_proofs_verify_for_every_leaf() {
+        let l = leaves(11);
+        let tree = MerkleTree::from_leaves(l.clone());
+        let root = tree.root().unwrap();
+
+        for (position, leaf) in l.iter().enumerate() {
+            let proof = tree.proof(position).unwrap();
+            assert!(verify_proof(*leaf, &proof, root));
+        }
+
+        let proof = tree.proof(0).unwrap();
+        assert!(!verify_proof(l[1], &proof, root));
+    }
+
+    #[test]
+    fn test_leaf_hash() {
+        let msg_hash = "c9e23418a985892acc0fa031331080bfce112bdf841a3ae04a5181c6da1610b1";
+
+        assert!(leaf_hash(msg_hash).is_ok());
+        assert!(leaf_hash("not a hash").is_err());
+    }
+}
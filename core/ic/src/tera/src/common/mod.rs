@@ -1,2 +1,5 @@
+pub mod ecdsa;
+pub mod merkle;
+pub mod stable;
 pub mod types;
 pub mod utils;
@@ -0,0 +1,350 @@
+use std::{borrow::Cow, cell::RefCell};
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{
+    memory_manager::{MemoryId, MemoryManager, VirtualMemory},
+    storable::Bound,
+    DefaultMemoryImpl, Storable,
+};
+
+use super::types::{
+    Delivery, Event, ExpiredMessage, MessageStatusInfo, NonceSet, OutgoingMessage, PauseScope,
+    Role, SigningConfig,
+};
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+pub const MESSAGES_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub const MESSAGE_TTL_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub const EXPIRED_MESSAGES_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub const NONCE_MEMORY_ID: MemoryId = MemoryId::new(3);
+pub const NONCE_NAMESPACES_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub const MESSAGES_OUT_MEMORY_ID: MemoryId = MemoryId::new(5);
+pub const MESSAGE_OUT_INDEX_MEMORY_ID: MemoryId = MemoryId::new(6);
+pub const ROLES_MEMORY_ID: MemoryId = MemoryId::new(7);
+pub const SIGNING_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(8);
+pub const SCHEMA_VERSION_MEMORY_ID: MemoryId = MemoryId::new(9);
+pub const DELIVERIES_MEMORY_ID: MemoryId = MemoryId::new(10);
+pub const DELIVERY_QUEUE_MEMORY_ID: MemoryId = MemoryId::new(11);
+pub const EVENTS_INDEX_MEMORY_ID: MemoryId = MemoryId::new(12);
+pub const EVENTS_DATA_MEMORY_ID: MemoryId = MemoryId::new(13);
+pub const CONSUMED_MESSAGES_MEMORY_ID: MemoryId = MemoryId::new(14);
+pub const CONSUMED_MESSAGES_INDEX_MEMORY_ID: MemoryId = MemoryId::new(15);
+pub const LOW_CYCLES_THRESHOLD_MEMORY_ID: MemoryId = MemoryId::new(16);
+pub const CONSUMED_MESSAGES_TOTAL_MEMORY_ID: MemoryId = MemoryId::new(17);
+pub const PAUSED_MEMORY_ID: MemoryId = MemoryId::new(18);
+
+/// Version of the layout of the stable structures. The candid snapshot the
+/// state was written as before is version 0, and the first layout of the
+/// stable structures, written before the version was recorded, is version 1.
+pub const STABLE_SCHEMA_VERSION: u32 = 1;
+
+/// Magic bytes of a candid message, which is how the state was written to
+/// stable memory before it moved to stable structures
+const CANDID_MAGIC: &[u8; 4] = b"DIDL";
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+pub fn get_memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+/// Whether stable memory still holds a candid snapshot written by `stable_save`.
+/// Must be checked before the memory manager is first used, as it claims any
+/// stable memory it does not recognize.
+pub fn has_candid_snapshot() -> bool {
+    use ic_cdk::api::stable;
+
+    if stable::stable64_size() == 0 {
+        return false;
+    }
+
+    let mut magic = [0u8; 4];
+    stable::stable64_read(0, &mut magic);
+    &magic == CANDID_MAGIC
+}
+
+/// Principal as a stable structure key
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StorablePrincipal(pub Principal);
+
+impl Storable for StorablePrincipal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.0.as_slice().to_vec())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        StorablePrincipal(Principal::from_slice(&bytes))
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 29,
+        is_fixed_size: false,
+    };
+}
+
+/// Message hash ordered by a time, such as the next delivery attempt
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimedMessageKey {
+    pub at: u64,
+    pub msg_hash: String,
+}
+
+impl Storable for TimedMessageKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.at.to_be_bytes().to_vec();
+        bytes.extend_from_slice(self.msg_hash.as_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let mut at = [0u8; 8];
+        at.copy_from_slice(&bytes[..8]);
+
+        TimedMessageKey {
+            at: u64::from_be_bytes(at),
+            msg_hash: String::from_utf8(bytes[8..].to_vec()).expect("invalid message hash"),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Incoming message counter and the time it was first stored at
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IncomingMessageEntry {
+    pub count: u32,
+    pub stored_at: u64,
+}
+
+impl Storable for IncomingMessageEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::with_capacity(12);
+        bytes.extend_from_slice(&self.count.to_be_bytes());
+        bytes.extend_from_slice(&self.stored_at.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let mut count = [0u8; 4];
+        let mut stored_at = [0u8; 8];
+        count.copy_from_slice(&bytes[..4]);
+        stored_at.copy_from_slice(&bytes[4..12]);
+
+        IncomingMessageEntry {
+            count: u32::from_be_bytes(count),
+            stored_at: u64::from_be_bytes(stored_at),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 12,
+        is_fixed_size: true,
+    };
+}
+
+impl Storable for Role {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let byte = match self {
+            Role::Admin => 0u8,
+            Role::Relayer => 1,
+            Role::Poller => 2,
+        };
+        Cow::Owned(vec![byte])
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        match bytes[0] {
+            0 => Role::Admin,
+            1 => Role::Relayer,
+            2 => Role::Poller,
+            byte => panic!("Invalid role {}", byte),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 1,
+        is_fixed_size: true,
+    };
+}
+
+impl Storable for PauseScope {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let byte = match self {
+            PauseScope::StoreMessage => 0u8,
+            PauseScope::SendMessage => 1,
+        };
+        Cow::Owned(vec![byte])
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        match bytes[0] {
+            0 => PauseScope::StoreMessage,
+            1 => PauseScope::SendMessage,
+            byte => panic!("Invalid pause scope {}", byte),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 1,
+        is_fixed_size: true,
+    };
+}
+
+fn encode<T: CandidType>(value: &T) -> Cow<'static, [u8]> {
+    Cow::Owned(Encode!(value).expect("failed to encode stable value"))
+}
+
+fn decode<T: CandidType + for<'de> Deserialize<'de>>(bytes: &[u8]) -> T {
+    Decode!(bytes, T).expect("failed to decode stable value")
+}
+
+impl Storable for OutgoingMessage {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        encode(self)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode(&bytes)
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+}
+
+impl Storable for ExpiredMessage {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        encode(self)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode(&bytes)
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+}
+
+impl Storable for SigningConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        encode(self)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode(&bytes)
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for Delivery {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        encode(self)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode(&bytes)
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for Event {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        encode(self)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode(&bytes)
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for MessageStatusInfo {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        encode(self)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode(&bytes)
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+}
+
+impl Storable for NonceSet {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        encode(self)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode(&bytes)
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::Nat;
+
+    use super::*;
+
+    fn round_trip<T: Storable>(value: &T) -> T {
+        T::from_bytes(value.to_bytes())
+    }
+
+    #[test]
+    fn test_incoming_message_entry_round_trip() {
+        let entry = IncomingMessageEntry {
+            count: 3,
+            stored_at: 1_650_000_000_000_000_000,
+        };
+
+        assert_eq!(round_trip(&entry), entry);
+    }
+
+    #[test]
+    fn test_role_round_trip() {
+        for role in Role::ALL {
+            assert_eq!(round_trip(&role), role);
+        }
+    }
+
+    #[test]
+    fn test_principal_round_trip() {
+        let principal = Principal::from_text("tcy4r-qaaaa-aaaab-qadyq-cai").unwrap();
+
+        assert!(round_trip(&StorablePrincipal(principal)) == StorablePrincipal(principal));
+    }
+
+    #[test]
+    fn test_signing_config_round_trip() {
+        let config = Some(SigningConfig {
+            key_name: String::from("dfx_test_key"),
+            chain_id: 5,
+            l1_contract: String::from("0x60dc1a8a4d7b5b5a4e13a49e8e6d2ecc5c4e0b4d"),
+        });
+
+        assert_eq!(round_trip(&config), config);
+        assert_eq!(round_trip(&None::<SigningConfig>), None);
+    }
+
+    #[test]
+    fn test_nonce_set_round_trip() {
+        let nonces: NonceSet = [1, 2, 5].iter().map(|i| Nat::from(*i)).collect();
+
+        assert_eq!(round_trip(&nonces), nonces);
+    }
+}
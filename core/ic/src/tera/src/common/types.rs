@@ -1,33 +1,104 @@
-use ic_kit::candid::{CandidType, Deserialize, Nat};
+use ic_kit::candid::{CandidType, Deserialize, Nat, Principal};
 use serde::Serialize;
+use std::{collections::BTreeSet, fmt};
+
+pub use terabethia_common::{
+    IncomingMessageHashParams, Message, NonceBytes, OutgoingMessageHashParams,
+};
 
 pub type Nonce = Nat;
-pub type NonceBytes = [u8; 32];
 
 #[derive(Serialize, CandidType, Deserialize)]
-pub struct ConsumeMessageResponse(pub(crate) Result<bool, String>);
+pub struct ConsumeMessageResponse(pub(crate) Result<bool, TeraError>);
 
 #[derive(Serialize, CandidType, Deserialize)]
-pub struct RemoveMessagesResponse(pub(crate) Result<bool, String>);
+pub struct RemoveMessagesResponse(pub(crate) Result<bool, TeraError>);
 
 #[derive(Serialize, CandidType, Deserialize)]
-pub struct SendMessageResponse(pub(crate) Result<OutgoingMessage, String>);
+pub struct SendMessageResponse(pub(crate) Result<OutgoingMessage, TeraError>);
 
 #[derive(Serialize, CandidType, Deserialize)]
-pub struct StoreMessageResponse(pub(crate) Result<CallResult, String>);
+pub struct StoreMessageResponse(pub(crate) Result<CallResult, TeraError>);
 
-#[derive(CandidType, Deserialize)]
-pub struct IncomingMessageHashParams {
-    pub(crate) from: Nat,
-    pub(crate) to: Nat,
-    pub(crate) nonce: Nonce,
-    pub(crate) payload: Vec<Nat>,
+#[derive(Serialize, CandidType, Deserialize)]
+pub struct SignBatchResponse(pub(crate) Result<SignedBatch, TeraError>);
+
+#[derive(Serialize, CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum TeraError {
+    /// Incoming message nonce has already been consumed
+    NonceAlreadyConsumed,
+    /// Incoming message was never stored or has already been consumed
+    MessageNotFound,
+    /// Outgoing message queue reached its capacity
+    OutgoingQueueFull,
+    /// Caller lacks the role required by the method
+    Unauthorized,
+    /// Inter-canister call was rejected
+    CallFailed { code: u8, msg: String },
+    /// Arguments could not be decoded or are malformed
+    InvalidPayload(String),
+    /// Outgoing batch range is empty or out of bounds
+    InvalidBatch(String),
+    /// Threshold ECDSA signing failed
+    SigningFailed(String),
+    /// The last admin can not give up its role
+    LastAdmin,
+    /// The operation was paused by an admin
+    Paused(PauseScope),
+}
+
+impl fmt::Display for TeraError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TeraError::NonceAlreadyConsumed => write!(f, "Nonce has already been consumed"),
+            TeraError::MessageNotFound => write!(f, "Message does not exist"),
+            TeraError::OutgoingQueueFull => write!(f, "Max OutgoingMessages amount reached"),
+            TeraError::Unauthorized => write!(f, "Caller is not authorized"),
+            TeraError::CallFailed { code, msg } => {
+                write!(f, "An error happened during the call: {}: {}", code, msg)
+            }
+            TeraError::InvalidPayload(msg) => write!(f, "Invalid payload: {}", msg),
+            TeraError::InvalidBatch(msg) => write!(f, "Invalid batch: {}", msg),
+            TeraError::SigningFailed(msg) => write!(f, "Signing failed: {}", msg),
+            TeraError::LastAdmin => write!(f, "Can not revoke the last admin"),
+            TeraError::Paused(scope) => write!(f, "{:?} is paused", scope),
+        }
+    }
+}
+
+#[derive(
+    Serialize, CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub enum Role {
+    /// Manages roles
+    Admin,
+    /// Stores and triggers incoming messages from L1
+    Relayer,
+    /// Polls, signs and removes outgoing messages to L1
+    Poller,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Admin, Role::Relayer, Role::Poller];
+}
+
+/// Operation an admin can pause, to stop the bridge without an upgrade
+#[derive(
+    Serialize, CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub enum PauseScope {
+    /// Storing incoming messages from L1, `store_message` and `store_messages`
+    StoreMessage,
+    /// Sending outgoing messages to L1, `send_message`
+    SendMessage,
 }
 
-#[derive(CandidType, Deserialize)]
-pub struct OutgoingMessageHashParams {
-    pub(crate) from: Nat,
-    pub(crate) to: Nat,
+/// An incoming message from L1, as submitted by the relayer
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StoreMessageParams {
+    pub(crate) from: Principal,
+    pub(crate) to: Principal,
+    pub(crate) nonce: Nonce,
     pub(crate) payload: Vec<Nat>,
 }
 
@@ -37,9 +108,6 @@ pub struct CallResult {
     pub(crate) r#return: Vec<u8>,
 }
 
-#[derive(Serialize, CandidType, Deserialize)]
-pub struct Message;
-
 #[derive(Serialize, Clone, CandidType, Deserialize, PartialEq, Eq, Hash)]
 pub struct OutgoingMessage {
     #[serde(with = "serde_bytes")]
@@ -52,3 +120,178 @@ pub struct OutgoingMessagePair {
     pub(crate) msg_key: String,
     pub(crate) msg_hash: String,
 }
+
+#[derive(Serialize, CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct IncomingMessage {
+    pub(crate) msg_hash: String,
+    pub(crate) count: u32,
+    pub(crate) stored_at: u64,
+}
+
+#[derive(Serialize, CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExpiredMessage {
+    pub(crate) msg_hash: String,
+    pub(crate) count: u32,
+    pub(crate) stored_at: u64,
+    pub(crate) expired_at: u64,
+}
+
+#[derive(Serialize, CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// handle_message has not succeeded yet and will be retried
+    Pending,
+    /// handle_message succeeded on the target canister
+    Delivered,
+    /// Gave up after the maximum number of attempts
+    Failed,
+}
+
+/// Delivery of an incoming message to its target canister
+#[derive(Serialize, CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct Delivery {
+    pub(crate) msg_hash: String,
+    pub(crate) from: Principal,
+    pub(crate) to: Principal,
+    pub(crate) nonce: Nonce,
+    pub(crate) payload: Vec<Nat>,
+    pub(crate) status: DeliveryStatus,
+    pub(crate) attempts: u32,
+    pub(crate) last_attempt_at: Option<u64>,
+    pub(crate) next_attempt_at: Option<u64>,
+    pub(crate) last_error: Option<TeraError>,
+}
+
+#[derive(Serialize, CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageStatus {
+    /// Stored, but not handled by its target canister yet
+    Pending,
+    /// Handled by its target canister, but not consumed yet
+    Delivered,
+    /// Consumed by its target canister
+    Consumed,
+    /// Never stored, or expired before it was consumed
+    Unknown,
+}
+
+#[derive(Serialize, CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct MessageStatusInfo {
+    pub(crate) msg_hash: String,
+    pub(crate) status: MessageStatus,
+    pub(crate) stored_at: Option<u64>,
+    pub(crate) delivered_at: Option<u64>,
+    pub(crate) consumed_at: Option<u64>,
+}
+
+#[derive(Serialize, CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum EventKind {
+    /// Incoming message from L1 was stored
+    IncomingStored,
+    /// Incoming message was handled by its target canister
+    Delivered,
+    /// Delivering the incoming message to its target canister failed
+    DeliveryFailed(TeraError),
+    /// Incoming message was consumed by its target canister
+    Consumed,
+    /// Outgoing message to L1 was sent
+    OutgoingSent,
+    /// Outgoing message to L1 was removed once relayed
+    OutgoingRemoved,
+    /// Role was granted to pid
+    Authorized { principal: Principal, role: Role },
+    /// Role was revoked from pid
+    Unauthorized { principal: Principal, role: Role },
+    /// Time to live of unconsumed incoming messages was set, in nanoseconds
+    MessageTtlSet(u64),
+    /// Incoming message was purged after its ttl and archived
+    Expired,
+    /// Operation was paused
+    Paused(PauseScope),
+    /// Operation was unpaused
+    Unpaused(PauseScope),
+}
+
+/// Entry of the append-only event log
+#[derive(Serialize, CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct Event {
+    pub(crate) id: u64,
+    pub(crate) kind: EventKind,
+    pub(crate) timestamp: u64,
+    pub(crate) caller: Principal,
+    pub(crate) msg_hash: Option<String>,
+}
+
+/// Consumed nonces, stored as a watermark plus the sparse set of nonces
+/// consumed above it. L1 nonces start at 1.
+#[derive(Serialize, CandidType, Deserialize, Clone, Default, Debug, PartialEq)]
+pub struct NonceSet {
+    /// Every nonce in 1..=watermark has been consumed
+    pub(crate) watermark: Nonce,
+    /// Consumed nonces above the watermark
+    pub(crate) sparse: BTreeSet<Nonce>,
+}
+
+/// Consumed nonces of a (from, to) pair, `None` for the ones consumed
+/// before nonces were namespaced
+#[derive(Serialize, CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct NamespaceNonces {
+    pub(crate) namespace: Option<(Principal, Principal)>,
+    pub(crate) nonces: NonceSet,
+}
+
+#[derive(Serialize, CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct NonceNamespaceInfo {
+    pub(crate) from: Principal,
+    pub(crate) to: Principal,
+    pub(crate) highest: Option<Nonce>,
+    pub(crate) watermark: Nonce,
+    pub(crate) sparse_count: u64,
+}
+
+#[derive(Serialize, CandidType, Deserialize, Clone)]
+pub struct IndexedOutgoingMessage {
+    pub(crate) index: u64,
+    pub(crate) msg_key: String,
+    pub(crate) msg_hash: String,
+}
+
+#[derive(Serialize, CandidType, Deserialize, Clone)]
+pub struct MessagesRoot {
+    pub(crate) root: String,
+    pub(crate) from_index: u64,
+    pub(crate) to_index: u64,
+    pub(crate) messages_count: u64,
+}
+
+#[derive(Serialize, CandidType, Deserialize, Clone)]
+pub struct MessageProof {
+    pub(crate) index: u64,
+    pub(crate) msg_key: String,
+    pub(crate) msg_hash: String,
+    pub(crate) leaf: String,
+    pub(crate) proof: Vec<String>,
+    pub(crate) root: MessagesRoot,
+}
+
+/// Key outgoing batches are signed with, and the L1 contract the signatures
+/// are meant for
+#[derive(Serialize, CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SigningConfig {
+    /// Threshold ECDSA master key, `key_1` on mainnet, `test_key_1` on test
+    /// subnets and `dfx_test_key` on a local replica
+    pub(crate) key_name: String,
+    pub(crate) chain_id: u64,
+    /// 0x prefixed address of the contract verifying batch signatures
+    pub(crate) l1_contract: String,
+}
+
+#[derive(Serialize, CandidType, Deserialize, Clone)]
+pub struct SignedBatch {
+    pub(crate) from_index: u64,
+    pub(crate) to_index: u64,
+    /// Index of each message, in the order of `msg_hashes`
+    pub(crate) indexes: Vec<u64>,
+    pub(crate) msg_hashes: Vec<String>,
+    pub(crate) digest: String,
+    /// 65 bytes `r || s || v` signature over `digest`, ready for `ecrecover`
+    pub(crate) signature: String,
+}
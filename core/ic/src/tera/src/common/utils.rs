@@ -1,71 +1,154 @@
-use candid::Nat;
+use candid::Principal;
 use sha3::{Digest, Keccak256};
 
-use super::types::{IncomingMessageHashParams, Message, OutgoingMessageHashParams};
+pub use terabethia_common::Keccak256HashFn;
 
-pub trait Keccak256HashFn<T> {
-    fn calculate_hash(&self, params: T) -> String;
+use super::types::{OutgoingMessage, SigningConfig};
+
+/// keccak256(abi.encodePacked(uint256 chainId, address l1Contract, uint256 canisterId,
+/// uint256 fromIndex, uint256 toIndex, uint256[] indexes, bytes32[] msgHashes))
+///
+/// The chain, contract and canister keep a signature from being replayed
+/// anywhere else, and each hash is bound to its index as ranges can have gaps.
+pub fn batch_digest(
+    config: &SigningConfig,
+    canister_id: &Principal,
+    from_index: u64,
+    to_index: u64,
+    messages: &[(u64, OutgoingMessage)],
+) -> Result<Vec<u8>, String> {
+    let mut hasher = Keccak256::new();
+
+    hasher.update(u64_to_uint256(config.chain_id));
+    hasher.update(eth_address_bytes(&config.l1_contract)?);
+    hasher.update(bytes_to_uint256(canister_id.as_slice()));
+    hasher.update(u64_to_uint256(from_index));
+    hasher.update(u64_to_uint256(to_index));
+
+    for (index, _) in messages {
+        hasher.update(u64_to_uint256(*index));
+    }
+    for (_, message) in messages {
+        let msg_hash = hex::decode(&message.msg_hash)
+            .map_err(|_| format!("Invalid message hash {}", message.msg_hash))?;
+        hasher.update(msg_hash);
+    }
+
+    Ok(hasher.finalize().to_vec())
 }
 
-impl Keccak256HashFn<IncomingMessageHashParams> for Message {
-    fn calculate_hash(&self, params: IncomingMessageHashParams) -> String {
-        let mut data = vec![
-            params.from,
-            params.to,
-            params.nonce,
-            Nat::from(params.payload.len()),
-        ];
-        data.extend(params.payload);
-
-        let data_encoded: Vec<Vec<u8>> = data
-            .clone()
-            .into_iter()
-            .map(|x| {
-                // take a slice of 32
-                let f = [0u8; 32];
-                let slice = &x.0.to_bytes_be()[..];
-                // calculate zero values padding
-                let l = 32 - slice.len();
-                [&f[..l], &slice].concat()
-            })
-            .collect();
-
-        let concated = data_encoded.concat().to_vec();
-        let mut hasher = Keccak256::new();
-
-        hasher.update(concated);
-
-        let result = hasher.finalize();
-
-        hex::encode(result.to_vec())
+/// 20 bytes of a 0x prefixed Ethereum address
+pub fn eth_address_bytes(address: &str) -> Result<[u8; 20], String> {
+    let bytes = hex::decode(address.trim_start_matches("0x"))
+        .map_err(|_| format!("Invalid Ethereum address {}", address))?;
+
+    let mut address_bytes = [0u8; 20];
+    if bytes.len() != address_bytes.len() {
+        return Err(format!("Invalid Ethereum address {}", address));
     }
+    address_bytes.copy_from_slice(&bytes);
+
+    Ok(address_bytes)
+}
+
+fn u64_to_uint256(value: u64) -> [u8; 32] {
+    bytes_to_uint256(&value.to_be_bytes())
 }
 
-impl Keccak256HashFn<OutgoingMessageHashParams> for Message {
-    fn calculate_hash(&self, params: OutgoingMessageHashParams) -> String {
-        let mut data = vec![params.from, params.to, Nat::from(params.payload.len())];
-        data.extend(params.payload);
+fn bytes_to_uint256(value: &[u8]) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[32 - value.len()..].copy_from_slice(value);
+    word
+}
 
-        let data_encoded: Vec<Vec<u8>> = data
-            .clone()
-            .into_iter()
-            .map(|x| {
-                // take a slice of 32
-                let f = [0u8; 32];
-                let slice = &x.0.to_bytes_be()[..];
-                // calculate zero values padding
-                let l = 32 - slice.len();
-                [&f[..l], &slice].concat()
-            })
-            .collect();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let concated = data_encoded.concat().to_vec();
-        let mut hasher = Keccak256::new();
+    fn config() -> SigningConfig {
+        SigningConfig {
+            key_name: String::from("dfx_test_key"),
+            chain_id: 5,
+            l1_contract: String::from("0x60dc1a8a4d7b5b5a4e13a49e8e6d2ecc5c4e0b4d"),
+        }
+    }
+
+    fn canister_id() -> Principal {
+        Principal::from_text("tcy4r-qaaaa-aaaab-qadyq-cai").unwrap()
+    }
 
-        hasher.update(concated);
+    fn messages(indexes: &[u64]) -> Vec<(u64, OutgoingMessage)> {
+        let msg_hash = "c9e23418a985892acc0fa031331080bfce112bdf841a3ae04a5181c6da1610b1";
 
-        let result = hasher.finalize();
+        indexes
+            .iter()
+            .map(|index| (*index, OutgoingMessage::new(msg_hash.to_string(), *index)))
+            .collect()
+    }
+
+    #[test]
+    fn test_batch_digest() {
+        let msg_hash = "c9e23418a985892acc0fa031331080bfce112bdf841a3ae04a5181c6da1610b1";
+
+        let digest = batch_digest(&config(), &canister_id(), 1, 1, &messages(&[1])).unwrap();
+
+        // an address is packed in 20 bytes, every other value in 32
+        let mut encoded = [0u8; 212];
+        encoded[31] = 5;
+        encoded[32..52].copy_from_slice(&eth_address_bytes(&config().l1_contract).unwrap());
+        encoded[74..84].copy_from_slice(canister_id().as_slice());
+        encoded[115] = 1;
+        encoded[147] = 1;
+        encoded[179] = 1;
+        encoded[180..].copy_from_slice(&hex::decode(msg_hash).unwrap());
+
+        assert_eq!(digest, Keccak256::digest(&encoded[..]).to_vec());
+    }
+
+    #[test]
+    fn test_batch_digest_depends_on_range() {
+        let messages = messages(&[2]);
+
+        assert_ne!(
+            batch_digest(&config(), &canister_id(), 1, 2, &messages).unwrap(),
+            batch_digest(&config(), &canister_id(), 2, 2, &messages).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_batch_digest_depends_on_domain() {
+        let messages = messages(&[1]);
+        let digest = batch_digest(&config(), &canister_id(), 1, 1, &messages).unwrap();
+
+        let other_chain = SigningConfig {
+            chain_id: 1,
+            ..config()
+        };
+        let other_contract = SigningConfig {
+            l1_contract: String::from("0x0000000000000000000000000000000000000001"),
+            ..config()
+        };
+        let other_canister = Principal::from_slice(&[1; 10]);
+
+        assert_ne!(
+            batch_digest(&other_chain, &canister_id(), 1, 1, &messages).unwrap(),
+            digest
+        );
+        assert_ne!(
+            batch_digest(&other_contract, &canister_id(), 1, 1, &messages).unwrap(),
+            digest
+        );
+        assert_ne!(
+            batch_digest(&config(), &other_canister, 1, 1, &messages).unwrap(),
+            digest
+        );
+    }
 
-        hex::encode(result.to_vec())
+    #[test]
+    fn test_batch_digest_binds_indexes() {
+        assert_ne!(
+            batch_digest(&config(), &canister_id(), 1, 3, &messages(&[1, 3])).unwrap(),
+            batch_digest(&config(), &canister_id(), 1, 3, &messages(&[1, 2])).unwrap()
+        );
     }
 }
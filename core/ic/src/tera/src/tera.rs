@@ -1,11 +1,36 @@
-use crate::common::types::{Nonce, NonceBytes, OutgoingMessage, OutgoingMessagePair};
+use crate::common::{
+    merkle::{leaf_hash, MerkleTree, INVALID_LEAF},
+    stable::{
+        get_memory, IncomingMessageEntry, Memory, StorablePrincipal, TimedMessageKey,
+        CONSUMED_MESSAGES_INDEX_MEMORY_ID, CONSUMED_MESSAGES_MEMORY_ID,
+        CONSUMED_MESSAGES_TOTAL_MEMORY_ID, DELIVERIES_MEMORY_ID, DELIVERY_QUEUE_MEMORY_ID,
+        EVENTS_DATA_MEMORY_ID, EVENTS_INDEX_MEMORY_ID, EXPIRED_MESSAGES_MEMORY_ID,
+        LOW_CYCLES_THRESHOLD_MEMORY_ID, MESSAGES_MEMORY_ID, MESSAGES_OUT_MEMORY_ID,
+        MESSAGE_OUT_INDEX_MEMORY_ID, MESSAGE_TTL_MEMORY_ID, NONCE_MEMORY_ID,
+        NONCE_NAMESPACES_MEMORY_ID, PAUSED_MEMORY_ID, ROLES_MEMORY_ID, SCHEMA_VERSION_MEMORY_ID,
+        SIGNING_CONFIG_MEMORY_ID,
+    },
+    types::{
+        Delivery, DeliveryStatus, Event, EventKind, ExpiredMessage, IncomingMessage,
+        IndexedOutgoingMessage, MessageProof, MessageStatus, MessageStatusInfo, MessagesRoot,
+        NamespaceNonces, Nonce, NonceNamespaceInfo, NonceSet, OutgoingMessage, OutgoingMessagePair,
+        PauseScope, Role, SigningConfig, TeraError,
+    },
+};
 use candid::{CandidType, Deserialize, Nat, Principal};
-use ic_kit::ic::caller;
+use ic_kit::ic::{balance, caller, time};
+use ic_stable_structures::{StableBTreeMap, StableCell, StableLog, StableVec};
 use sha2::{Digest, Sha256};
+
 use std::{
     cell::RefCell,
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::TryFrom,
+    iter::FromIterator,
+    ops::Bound,
 };
+pub use terabethia_common::ToNat;
+use terabethia_common::{CycleMetrics, DEFAULT_LOW_CYCLES_THRESHOLD};
 
 thread_local! {
     pub static STATE: TerabetiaState = TerabetiaState::default();
@@ -13,40 +38,138 @@ thread_local! {
 
 const MAX_OUTGOING_MESSAGES_COUNT: usize = 10_000;
 
-#[derive(CandidType, Deserialize)]
+const MAX_OUTGOING_MESSAGES_PAGE_SIZE: usize = 1_000;
+
+const MAX_INCOMING_MESSAGES_PAGE_SIZE: usize = 1_000;
+
+const MAX_EVENTS_PAGE_SIZE: usize = 1_000;
+
+/// Indexes below the outgoing message index searched for the index of a legacy message
+const LEGACY_INDEX_LOOKBACK: u64 = 10_000;
+
+/// Default time to live of unconsumed incoming messages, 30 days in nanoseconds
+const DEFAULT_MESSAGE_TTL: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Statuses of consumed incoming messages kept, the oldest ones are forgotten
+/// past it
+const MAX_CONSUMED_MESSAGES: u64 = 100_000;
+
+/// Attempts to deliver an incoming message before giving up on it
+const MAX_DELIVERY_ATTEMPTS: u32 = 10;
+
+/// Delay before the first retry of a failed delivery, one minute in nanoseconds,
+/// doubled by every further attempt
+const DELIVERY_RETRY_BASE_DELAY: u64 = 60 * 1_000_000_000;
+
+/// Longest delay between two delivery attempts, six hours in nanoseconds
+const DELIVERY_RETRY_MAX_DELAY: u64 = 6 * 60 * 60 * 1_000_000_000;
+
 pub struct TerabetiaState {
-    /// Incoming messages from L1
-    pub messages: RefCell<HashMap<String, u32>>,
+    /// Incoming messages from L1, with their counter and the time they were first stored at
+    pub messages: RefCell<StableBTreeMap<String, IncomingMessageEntry, Memory>>,
+
+    /// Time to live of unconsumed incoming messages in nanoseconds
+    pub message_ttl: RefCell<StableCell<u64, Memory>>,
+
+    /// Incoming messages purged after their ttl, kept for reconciliation
+    pub expired_messages: RefCell<StableVec<ExpiredMessage, Memory>>,
+
+    /// Incoming message nonces consumed before nonces were namespaced
+    pub nonce: RefCell<StableCell<NonceSet, Memory>>,
 
-    /// Incoming message nonce
-    pub nonce: RefCell<HashSet<Nonce>>,
+    /// Incoming message nonces per (from, to) pair
+    pub nonce_namespaces:
+        RefCell<StableBTreeMap<(StorablePrincipal, StorablePrincipal), NonceSet, Memory>>,
 
-    /// Outgoing messages
-    pub messages_out: RefCell<HashSet<OutgoingMessage>>,
+    /// Outgoing messages ordered by their index
+    pub messages_out: RefCell<StableBTreeMap<u64, OutgoingMessage, Memory>>,
 
     /// Outgoing message index
-    pub message_out_index: RefCell<u64>,
+    pub message_out_index: RefCell<StableCell<u64, Memory>>,
 
-    /// List of authorized pids
-    pub authorized: RefCell<Vec<Principal>>,
+    /// Merkle tree over pending outgoing messages, rebuilt from messages_out
+    pub messages_out_tree: RefCell<MerkleTree>,
+
+    /// Roles granted to each pid
+    pub roles: RefCell<StableBTreeMap<(StorablePrincipal, Role), (), Memory>>,
+
+    /// Signing key and L1 domain of outgoing batches, None until it is set
+    pub signing_config: RefCell<StableCell<Option<SigningConfig>, Memory>>,
+
+    /// Version of the layout of the stable structures
+    pub schema_version: RefCell<StableCell<u32, Memory>>,
+
+    /// Delivery of incoming messages to their target canister
+    pub deliveries: RefCell<StableBTreeMap<String, Delivery, Memory>>,
+
+    /// Undelivered messages by the time of their next delivery attempt
+    pub delivery_queue: RefCell<StableBTreeMap<TimedMessageKey, (), Memory>>,
+
+    /// Time after which failed deliveries are checked again
+    pub next_delivery_check_at: RefCell<u64>,
+
+    /// Append-only log of everything that happened to messages and roles,
+    /// kept in stable memory for good
+    pub events: RefCell<StableLog<Event, Memory, Memory>>,
+
+    /// Status of consumed incoming messages, once they left `messages`
+    pub consumed_messages: RefCell<StableBTreeMap<String, MessageStatusInfo, Memory>>,
+
+    /// Consumed messages by the time they were consumed at, oldest first
+    pub consumed_messages_index: RefCell<StableBTreeMap<TimedMessageKey, (), Memory>>,
+
+    /// Incoming messages consumed, including the ones whose status was forgotten
+    pub consumed_messages_total: RefCell<StableCell<u64, Memory>>,
+
+    /// Cycle balance under which the canister reports itself as degraded
+    pub low_cycles_threshold: RefCell<StableCell<u64, Memory>>,
+
+    /// Operations paused by an admin
+    pub paused: RefCell<StableBTreeMap<PauseScope, (), Memory>>,
 }
 
+/// Snapshot of the state as it was written to stable memory with candid,
+/// before it moved to stable structures. Only read once, on upgrade.
 #[derive(CandidType, Deserialize, Default)]
 pub struct StableTerabetiaState {
     /// Incoming messages from L1
     pub messages: HashMap<String, u32>,
 
-    /// Incoming message nonce
+    /// Time at which incoming messages were first stored
+    pub messages_stored_at: Option<HashMap<String, u64>>,
+
+    /// Time to live of unconsumed incoming messages in nanoseconds
+    pub message_ttl: Option<u64>,
+
+    /// Incoming messages purged after their ttl, kept for reconciliation
+    pub expired_messages: Option<Vec<ExpiredMessage>>,
+
+    /// Incoming message nonces consumed before nonces were namespaced (legacy)
     pub nonce: HashSet<Nonce>,
 
-    /// Outgoing messages
+    /// Incoming message nonces consumed before nonces were namespaced
+    pub compact_nonce: Option<NonceSet>,
+
+    /// Incoming message nonces per (from, to) pair
+    pub nonce_namespaces: Option<HashMap<(Principal, Principal), NonceSet>>,
+
+    /// Outgoing messages (legacy, stored without their index)
     pub messages_out: HashSet<OutgoingMessage>,
 
+    /// Outgoing messages ordered by their index
+    pub indexed_messages_out: Option<BTreeMap<u64, OutgoingMessage>>,
+
     /// Outgoing message index
     pub message_out_index: u64,
 
-    /// List of authorized pids
+    /// List of authorized pids (legacy, granted every role on upgrade)
     pub authorized: Vec<Principal>,
+
+    /// Roles granted to each pid
+    pub roles: Option<HashMap<Principal, HashSet<Role>>>,
+
+    /// Signing key and L1 domain of outgoing batches
+    pub signing_config: Option<SigningConfig>,
 }
 
 impl OutgoingMessage {
@@ -73,7 +196,7 @@ impl From<OutgoingMessagePair> for OutgoingMessage {
         let mut msg_key = [0u8; 32];
         let msg_key_slice = &hex::decode(message.msg_key).unwrap()[..];
 
-        msg_key.copy_from_slice(&msg_key_slice);
+        msg_key.copy_from_slice(msg_key_slice);
 
         OutgoingMessage {
             msg_key: msg_key.to_vec(),
@@ -91,205 +214,1021 @@ fn msg_key_bytes_to_string(message: &OutgoingMessage) -> OutgoingMessagePair {
     }
 }
 
-pub trait ToNat {
-    fn to_nat(&self) -> Nat;
+/// Snapshots taken before messages were indexed only hold the msg_key, which is
+/// sha256(index ‖ msg_hash). The index of a legacy message is recovered by hashing
+/// its msg_hash with the most recent indexes until the msg_key matches.
+fn index_legacy_messages(
+    messages: HashSet<OutgoingMessage>,
+    message_out_index: u64,
+) -> BTreeMap<u64, OutgoingMessage> {
+    let mut unindexed: Vec<OutgoingMessage> = messages.into_iter().collect();
+    let mut indexed = BTreeMap::new();
+
+    let lowest_index = message_out_index.saturating_sub(LEGACY_INDEX_LOOKBACK) + 1;
+    for index in (lowest_index..=message_out_index).rev() {
+        if unindexed.is_empty() {
+            break;
+        }
+
+        let position = unindexed.iter().position(|message| {
+            OutgoingMessage::new(message.msg_hash.clone(), index).msg_key == message.msg_key
+        });
+        if let Some(position) = position {
+            indexed.insert(index, unindexed.swap_remove(position));
+        }
+    }
+
+    // an upgrade that can not keep the order fails, so that no message is lost
+    if let Some(message) = unindexed.first() {
+        panic!(
+            "No index in {}..={} matches the legacy outgoing message {}",
+            lowest_index,
+            message_out_index,
+            hex::encode(&message.msg_key)
+        );
+    }
+
+    indexed
+}
+
+/// Position of a consumed message in the index of consumed messages
+fn consumed_message_key(status: &MessageStatusInfo) -> TimedMessageKey {
+    TimedMessageKey {
+        at: status.consumed_at.unwrap_or_default(),
+        msg_hash: status.msg_hash.clone(),
+    }
 }
 
-impl ToNat for NonceBytes {
-    fn to_nat(&self) -> Nat {
-        Nat::from(num_bigint::BigUint::from_bytes_be(&self.as_slice()[..]))
+/// Position of an undelivered message in the delivery queue, the ones without
+/// a next attempt are queued last
+fn delivery_queue_key(delivery: &Delivery) -> Option<TimedMessageKey> {
+    match delivery.status {
+        DeliveryStatus::Delivered => None,
+        _ => Some(TimedMessageKey {
+            at: delivery.next_attempt_at.unwrap_or(u64::MAX),
+            msg_hash: delivery.msg_hash.clone(),
+        }),
     }
 }
 
-impl ToNat for Principal {
-    #[inline(always)]
-    fn to_nat(&self) -> Nat {
-        Nat::from(num_bigint::BigUint::from_bytes_be(&self.as_slice()[..]))
+/// Time of the successful delivery attempt
+fn delivered_at(delivery: &Delivery) -> Option<u64> {
+    match delivery.status {
+        DeliveryStatus::Delivered => delivery.last_attempt_at,
+        _ => None,
     }
 }
 
-pub trait FromNat {
-    fn from_nat(input: Nat) -> Result<Principal, String>;
+/// Delay before the next delivery attempt, after `attempts` failed ones
+fn retry_delay(attempts: u32) -> u64 {
+    let exponent = attempts.saturating_sub(1).min(32);
+
+    DELIVERY_RETRY_BASE_DELAY
+        .saturating_mul(1u64 << exponent)
+        .min(DELIVERY_RETRY_MAX_DELAY)
 }
 
-impl FromNat for Principal {
-    #[inline(always)]
-    fn from_nat(input: Nat) -> Result<Principal, String> {
-        let be_bytes = input.0.to_bytes_be();
-        let be_bytes_len = be_bytes.len();
-        if be_bytes_len > 29 {
-            return Err("Invalid Nat".to_string());
-        }
-        let padding_bytes = if be_bytes_len > 10 && be_bytes_len < 29 {
-            29 - be_bytes_len
-        } else if be_bytes_len < 10 {
-            10 - be_bytes_len
+impl NonceSet {
+    pub fn contains(&self, nonce: &Nonce) -> bool {
+        (*nonce != 0 && *nonce <= self.watermark) || self.sparse.contains(nonce)
+    }
+
+    pub fn insert(&mut self, nonce: Nonce) {
+        if self.contains(&nonce) {
+            return;
+        }
+
+        if nonce == self.watermark.clone() + 1 {
+            self.watermark = nonce;
+            self.compact();
         } else {
-            0
-        };
-        let mut p_slice = vec![0u8; padding_bytes];
-        p_slice.extend_from_slice(&be_bytes);
-        Ok(Principal::from_slice(&p_slice))
+            self.sparse.insert(nonce);
+        }
+    }
+
+    pub fn highest(&self) -> Option<Nonce> {
+        let watermark = (self.watermark != 0).then_some(&self.watermark);
+
+        self.sparse.iter().next_back().max(watermark).cloned()
+    }
+
+    /// Number of consumed nonces
+    pub fn count(&self) -> u64 {
+        u64::try_from(&self.watermark.0)
+            .unwrap_or(u64::MAX)
+            .saturating_add(self.sparse.len() as u64)
+    }
+
+    /// Move nonces directly above the watermark into it
+    fn compact(&mut self) {
+        loop {
+            let next = self.watermark.clone() + 1;
+            if !self.sparse.remove(&next) {
+                break;
+            }
+            self.watermark = next;
+        }
+    }
+}
+
+impl FromIterator<Nonce> for NonceSet {
+    fn from_iter<I: IntoIterator<Item = Nonce>>(nonces: I) -> Self {
+        let mut nonces: Vec<Nonce> = nonces.into_iter().collect();
+        nonces.sort();
+
+        let mut set = NonceSet::default();
+        nonces.into_iter().for_each(|nonce| set.insert(nonce));
+        set
     }
 }
 
 impl Default for TerabetiaState {
     fn default() -> Self {
         TerabetiaState {
-            messages: RefCell::new(HashMap::default()),
-            nonce: RefCell::new(HashSet::default()),
-            messages_out: RefCell::new(HashSet::with_capacity(MAX_OUTGOING_MESSAGES_COUNT)),
-            message_out_index: RefCell::new(u64::default()),
-            authorized: RefCell::new(Vec::default()),
+            messages: RefCell::new(StableBTreeMap::init(get_memory(MESSAGES_MEMORY_ID))),
+            message_ttl: RefCell::new(
+                StableCell::init(get_memory(MESSAGE_TTL_MEMORY_ID), DEFAULT_MESSAGE_TTL)
+                    .expect("failed to init message ttl"),
+            ),
+            expired_messages: RefCell::new(
+                StableVec::init(get_memory(EXPIRED_MESSAGES_MEMORY_ID))
+                    .expect("failed to init expired messages"),
+            ),
+            nonce: RefCell::new(
+                StableCell::init(get_memory(NONCE_MEMORY_ID), NonceSet::default())
+                    .expect("failed to init nonce"),
+            ),
+            nonce_namespaces: RefCell::new(StableBTreeMap::init(get_memory(
+                NONCE_NAMESPACES_MEMORY_ID,
+            ))),
+            messages_out: RefCell::new(StableBTreeMap::init(get_memory(MESSAGES_OUT_MEMORY_ID))),
+            message_out_index: RefCell::new(
+                StableCell::init(get_memory(MESSAGE_OUT_INDEX_MEMORY_ID), 0)
+                    .expect("failed to init message out index"),
+            ),
+            messages_out_tree: RefCell::new(MerkleTree::default()),
+            roles: RefCell::new(StableBTreeMap::init(get_memory(ROLES_MEMORY_ID))),
+            signing_config: RefCell::new(
+                StableCell::init(get_memory(SIGNING_CONFIG_MEMORY_ID), None)
+                    .expect("failed to init signing config"),
+            ),
+            // layouts written before the version was recorded are version 1
+            schema_version: RefCell::new(
+                StableCell::init(get_memory(SCHEMA_VERSION_MEMORY_ID), 1)
+                    .expect("failed to init schema version"),
+            ),
+            deliveries: RefCell::new(StableBTreeMap::init(get_memory(DELIVERIES_MEMORY_ID))),
+            delivery_queue: RefCell::new(StableBTreeMap::init(get_memory(
+                DELIVERY_QUEUE_MEMORY_ID,
+            ))),
+            next_delivery_check_at: RefCell::new(0),
+            events: RefCell::new(
+                StableLog::init(
+                    get_memory(EVENTS_INDEX_MEMORY_ID),
+                    get_memory(EVENTS_DATA_MEMORY_ID),
+                )
+                .expect("failed to init events"),
+            ),
+            consumed_messages: RefCell::new(StableBTreeMap::init(get_memory(
+                CONSUMED_MESSAGES_MEMORY_ID,
+            ))),
+            consumed_messages_index: RefCell::new(StableBTreeMap::init(get_memory(
+                CONSUMED_MESSAGES_INDEX_MEMORY_ID,
+            ))),
+            consumed_messages_total: RefCell::new(
+                StableCell::init(get_memory(CONSUMED_MESSAGES_TOTAL_MEMORY_ID), 0)
+                    .expect("failed to init consumed messages total"),
+            ),
+            low_cycles_threshold: RefCell::new(
+                StableCell::init(
+                    get_memory(LOW_CYCLES_THRESHOLD_MEMORY_ID),
+                    DEFAULT_LOW_CYCLES_THRESHOLD,
+                )
+                .expect("failed to init low cycles threshold"),
+            ),
+            paused: RefCell::new(StableBTreeMap::init(get_memory(PAUSED_MEMORY_ID))),
         }
     }
 }
 
 impl TerabetiaState {
-    ///
-    /// Outgoing
-    ///
+    //
+    // Outgoing
+    //
 
-    /// Get outgoing messages to L1
+    /// Get outgoing messages to L1 in the order they were sent
     pub fn get_messages(&self) -> Vec<OutgoingMessagePair> {
         self.messages_out
             .borrow()
-            .iter()
-            .map(msg_key_bytes_to_string)
+            .values()
+            .map(|message| msg_key_bytes_to_string(&message))
+            .collect()
+    }
+
+    /// Get a page of outgoing messages with an index greater than `after_index`
+    pub fn get_messages_page(&self, after_index: u64, limit: usize) -> Vec<IndexedOutgoingMessage> {
+        let limit = limit.min(MAX_OUTGOING_MESSAGES_PAGE_SIZE);
+
+        self.messages_out
+            .borrow()
+            .range((Bound::Excluded(after_index), Bound::Unbounded))
+            .take(limit)
+            .map(|(index, message)| {
+                let message = msg_key_bytes_to_string(&message);
+                IndexedOutgoingMessage {
+                    index,
+                    msg_key: message.msg_key,
+                    msg_hash: message.msg_hash,
+                }
+            })
             .collect()
     }
 
     /// Store outgoing messages to L1
-    pub fn store_outgoing_message(&self, msg_hash: String) -> Result<OutgoingMessage, String> {
+    pub fn store_outgoing_message(&self, msg_hash: String) -> Result<OutgoingMessage, TeraError> {
         if self.outgoing_messages_count() >= MAX_OUTGOING_MESSAGES_COUNT {
-            return Err(String::from("Max OutgoingMessages amount reached"));
+            return Err(TeraError::OutgoingQueueFull);
         }
+        let leaf = leaf_hash(&msg_hash).map_err(TeraError::InvalidPayload)?;
+
         // we increment outgoing message counter
-        let mut index = self.message_out_index.borrow_mut();
-        *index += 1;
+        let index = *self.message_out_index.borrow().get() + 1;
+        self.message_out_index
+            .borrow_mut()
+            .set(index)
+            .expect("failed to update message out index");
 
-        let mut map = self.messages_out.borrow_mut();
-        let message_out_key = OutgoingMessage::new(msg_hash, *index);
-        map.insert(message_out_key.clone());
+        let message_out_key = OutgoingMessage::new(msg_hash, index);
+        self.messages_out
+            .borrow_mut()
+            .insert(index, message_out_key.clone());
+        self.messages_out_tree.borrow_mut().push(leaf);
 
         Ok(message_out_key)
     }
 
-    /// Remove outgoing messages to L1
-    pub fn remove_messages(&self, messages: Vec<OutgoingMessagePair>) -> Result<bool, String> {
-        let mut map = self.messages_out.borrow_mut();
-
-        messages.into_iter().for_each(|message| {
-            let key = OutgoingMessage::from(message);
-            map.remove(&key);
+    /// Remove outgoing messages to L1, returning the ones that were pending
+    pub fn take_messages(
+        &self,
+        messages: Vec<OutgoingMessagePair>,
+    ) -> Result<Vec<OutgoingMessage>, TeraError> {
+        let invalid_key = messages.iter().find(|message| {
+            hex::decode(&message.msg_key).map_or(true, |msg_key| msg_key.len() != 32)
         });
+        if let Some(message) = invalid_key {
+            return Err(TeraError::InvalidPayload(format!(
+                "Invalid message key {}",
+                message.msg_key
+            )));
+        }
 
-        Ok(true)
+        let keys: HashSet<OutgoingMessage> =
+            messages.into_iter().map(OutgoingMessage::from).collect();
+
+        let mut messages_out = self.messages_out.borrow_mut();
+        let indexes: Vec<u64> = messages_out
+            .iter()
+            .filter(|(_, message)| keys.contains(message))
+            .map(|(index, _)| index)
+            .collect();
+        let removed = indexes
+            .into_iter()
+            .filter_map(|index| messages_out.remove(&index))
+            .collect();
+        drop(messages_out);
+
+        self.rebuild_messages_out_tree();
+
+        Ok(removed)
+    }
+
+    /// Pending outgoing messages with an index in `from_index..=to_index`,
+    /// along with their index
+    pub fn get_outgoing_batch(
+        &self,
+        from_index: u64,
+        to_index: u64,
+    ) -> Result<Vec<(u64, OutgoingMessage)>, TeraError> {
+        if from_index > to_index {
+            return Err(TeraError::InvalidBatch(String::from("Invalid batch range")));
+        }
+
+        let batch: Vec<(u64, OutgoingMessage)> = self
+            .messages_out
+            .borrow()
+            .range(from_index..=to_index)
+            .take(MAX_OUTGOING_MESSAGES_PAGE_SIZE + 1)
+            .collect();
+
+        if batch.is_empty() {
+            return Err(TeraError::InvalidBatch(String::from(
+                "No outgoing messages in batch range",
+            )));
+        }
+        if batch.len() > MAX_OUTGOING_MESSAGES_PAGE_SIZE {
+            return Err(TeraError::InvalidBatch(String::from(
+                "Batch range is too big",
+            )));
+        }
+
+        Ok(batch)
+    }
+
+    /// Merkle root over all pending outgoing messages and the index range it covers
+    pub fn get_messages_root(&self) -> Option<MessagesRoot> {
+        let messages = self.messages_out.borrow();
+        let (from_index, _) = messages.first_key_value()?;
+        let (to_index, _) = messages.last_key_value()?;
+        let root = self.messages_out_tree.borrow().root()?;
+
+        Some(MessagesRoot {
+            root: hex::encode(root),
+            from_index,
+            to_index,
+            messages_count: messages.len(),
+        })
+    }
+
+    /// Inclusion proof of a pending outgoing message against `get_messages_root`
+    pub fn get_message_proof(&self, msg_key: String) -> Option<MessageProof> {
+        let msg_key = hex::decode(msg_key).ok()?;
+
+        let (position, (index, message)) = self
+            .messages_out
+            .borrow()
+            .iter()
+            .enumerate()
+            .find(|(_, (_, message))| message.msg_key == msg_key)?;
+
+        let leaf = leaf_hash(&message.msg_hash).ok()?;
+        let proof = self
+            .messages_out_tree
+            .borrow()
+            .proof(position)?
+            .into_iter()
+            .map(hex::encode)
+            .collect();
+
+        Some(MessageProof {
+            index,
+            msg_key: hex::encode(&message.msg_key),
+            msg_hash: message.msg_hash,
+            leaf: hex::encode(leaf),
+            proof,
+            root: self.get_messages_root()?,
+        })
+    }
+
+    /// The tree is kept on the heap, so it has to be rebuilt after an upgrade
+    pub fn rebuild_messages_out_tree(&self) {
+        let tree = MerkleTree::from_leaves(
+            self.messages_out
+                .borrow()
+                .values()
+                .map(|message| leaf_hash(&message.msg_hash).unwrap_or(INVALID_LEAF)),
+        );
+        self.messages_out_tree.replace(tree);
     }
 
     pub fn outgoing_messages_count(&self) -> usize {
-        self.messages_out.borrow().len()
+        self.messages_out.borrow().len() as usize
+    }
+
+    pub fn incoming_messages_count(&self) -> u64 {
+        self.messages.borrow().len()
     }
 
-    ///
-    /// Incoming
-    ///
+    pub fn consumed_messages_count(&self) -> u64 {
+        *self.consumed_messages_total.borrow().get()
+    }
+
+    //
+    // Incoming
+    //
 
-    /// Store incoming messages from L1
+    /// Store incoming messages from L1. Storing a message again does not
+    /// reset its ttl, which runs from the first time it was stored.
     pub fn store_incoming_message(&self, msg_hash: String) {
         let mut map = self.messages.borrow_mut();
-        *map.entry(msg_hash).or_insert(0) += 1;
+        let count = map.get(&msg_hash).map_or(0, |entry| entry.count);
+
+        let stored_at = map
+            .get(&msg_hash)
+            .map_or_else(time, |entry| entry.stored_at);
+
+        map.insert(
+            msg_hash,
+            IncomingMessageEntry {
+                count: count + 1,
+                stored_at,
+            },
+        );
     }
 
-    /// Check if L1 message exists
-    pub fn message_exists(&self, msg_hash: String) -> Result<bool, String> {
-        let map = self.messages.borrow();
-        let message = map.get(&msg_hash);
+    /// Decrease the incoming message counter, removing the message once it reaches zero
+    pub fn consume_incoming_message(&self, msg_hash: &str) -> Result<bool, TeraError> {
+        let mut map = self.messages.borrow_mut();
+        let msg_hash = msg_hash.to_string();
+        let mut entry = map.get(&msg_hash).ok_or(TeraError::MessageNotFound)?;
+
+        // if there is exactly 1 message, we'll remove it from the map
+        if entry.count == 1 {
+            map.remove(&msg_hash);
+            let delivery = self.remove_delivery(&msg_hash);
+
+            self.record_consumed_message(MessageStatusInfo {
+                msg_hash,
+                status: MessageStatus::Consumed,
+                stored_at: Some(entry.stored_at),
+                delivered_at: delivery.as_ref().and_then(delivered_at),
+                consumed_at: Some(time()),
+            });
+        } else {
+            entry.count -= 1;
+            map.insert(msg_hash, entry);
+        }
+
+        Ok(true)
+    }
+
+    /// Keep the status of a consumed message, forgetting the oldest ones past
+    /// `MAX_CONSUMED_MESSAGES`
+    fn record_consumed_message(&self, status: MessageStatusInfo) {
+        let key = consumed_message_key(&status);
+        let previous = self
+            .consumed_messages
+            .borrow_mut()
+            .insert(status.msg_hash.clone(), status);
+
+        {
+            let mut index = self.consumed_messages_index.borrow_mut();
+            if let Some(previous) = previous {
+                index.remove(&consumed_message_key(&previous));
+            }
+            index.insert(key, ());
+        }
+
+        let total = self.consumed_messages_count() + 1;
+        self.consumed_messages_total
+            .borrow_mut()
+            .set(total)
+            .expect("failed to update consumed messages total");
+
+        self.trim_consumed_messages(MAX_CONSUMED_MESSAGES);
+    }
+
+    /// Forget the statuses of the oldest consumed messages, down to `max`
+    fn trim_consumed_messages(&self, max: u64) {
+        let mut index = self.consumed_messages_index.borrow_mut();
+        let mut consumed_messages = self.consumed_messages.borrow_mut();
+
+        while index.len() > max {
+            match index.pop_first() {
+                Some((oldest, _)) => consumed_messages.remove(&oldest.msg_hash),
+                None => break,
+            };
+        }
+    }
+
+    /// Where an incoming message is at, from being stored to being consumed
+    pub fn get_message_status(&self, msg_hash: &str) -> MessageStatusInfo {
+        let msg_hash = msg_hash.to_string();
+
+        if let Some(entry) = self.messages.borrow().get(&msg_hash) {
+            let delivered_at = self.get_delivery(&msg_hash).as_ref().and_then(delivered_at);
+            let status = match delivered_at {
+                Some(_) => MessageStatus::Delivered,
+                None => MessageStatus::Pending,
+            };
+
+            return MessageStatusInfo {
+                msg_hash,
+                status,
+                stored_at: Some(entry.stored_at),
+                delivered_at,
+                consumed_at: None,
+            };
+        }
+
+        self.consumed_messages
+            .borrow()
+            .get(&msg_hash)
+            .unwrap_or(MessageStatusInfo {
+                msg_hash,
+                status: MessageStatus::Unknown,
+                stored_at: None,
+                delivered_at: None,
+                consumed_at: None,
+            })
+    }
+
+    pub fn get_message_ttl(&self) -> u64 {
+        *self.message_ttl.borrow().get()
+    }
+
+    pub fn set_message_ttl(&self, ttl: u64) {
+        self.message_ttl
+            .borrow_mut()
+            .set(ttl)
+            .expect("failed to update message ttl");
+    }
+
+    pub fn set_low_cycles_threshold(&self, threshold: u64) {
+        self.low_cycles_threshold
+            .borrow_mut()
+            .set(threshold)
+            .expect("failed to update low cycles threshold");
+    }
+
+    pub fn get_cycles(&self) -> CycleMetrics {
+        CycleMetrics::new(balance(), *self.low_cycles_threshold.borrow().get())
+    }
 
-        if message.is_none() {
-            return Err("Message does not exist.".to_string());
+    /// Incoming messages stored longer than the ttl ago and never consumed,
+    /// at most `limit` of them, oldest first
+    pub fn get_stale_messages(&self, now: u64, limit: usize) -> Vec<IncomingMessage> {
+        let ttl = self.get_message_ttl();
+
+        let mut stale: Vec<IncomingMessage> = self
+            .messages
+            .borrow()
+            .iter()
+            .filter(|(_, entry)| now.saturating_sub(entry.stored_at) > ttl)
+            .map(|(msg_hash, entry)| IncomingMessage {
+                msg_hash,
+                count: entry.count,
+                stored_at: entry.stored_at,
+            })
+            .collect();
+
+        stale.sort_by_key(|message| message.stored_at);
+        stale.truncate(limit.min(MAX_INCOMING_MESSAGES_PAGE_SIZE));
+        stale
+    }
+
+    /// Move at most `limit` stale incoming messages to the expired archive,
+    /// oldest first
+    pub fn purge_stale_messages(&self, now: u64, limit: usize) -> Vec<ExpiredMessage> {
+        let expired: Vec<ExpiredMessage> = self
+            .get_stale_messages(now, limit)
+            .into_iter()
+            .map(|message| ExpiredMessage {
+                msg_hash: message.msg_hash,
+                count: message.count,
+                stored_at: message.stored_at,
+                expired_at: now,
+            })
+            .collect();
+
+        let mut messages = self.messages.borrow_mut();
+        let expired_messages = self.expired_messages.borrow();
+        for message in expired.iter() {
+            messages.remove(&message.msg_hash);
+            self.remove_delivery(&message.msg_hash);
+            expired_messages
+                .push(message)
+                .expect("failed to archive expired message");
+        }
+
+        expired
+    }
+
+    /// Get a page of archived messages, starting at the `start`th one
+    pub fn get_expired_messages(&self, start: u64, limit: usize) -> Vec<ExpiredMessage> {
+        self.expired_messages
+            .borrow()
+            .iter()
+            .skip(start as usize)
+            .take(limit.min(MAX_INCOMING_MESSAGES_PAGE_SIZE))
+            .collect()
+    }
+
+    /// Check if L1 message exists
+    pub fn message_exists(&self, msg_hash: String) -> Result<bool, TeraError> {
+        if !self.messages.borrow().contains_key(&msg_hash) {
+            return Err(TeraError::MessageNotFound);
         }
 
         Ok(true)
     }
 
-    /// Update incoming message nonce
-    pub fn update_nonce(&self, nonce: Nonce) {
-        self.nonce.borrow_mut().insert(nonce);
+    /// Update incoming message nonce of the (from, to) namespace
+    pub fn update_nonce(&self, from: Principal, to: Principal, nonce: Nonce) {
+        let key = (StorablePrincipal(from), StorablePrincipal(to));
+        let mut namespaces = self.nonce_namespaces.borrow_mut();
+
+        let mut namespace = namespaces.get(&key).unwrap_or_default();
+        namespace.insert(nonce);
+        namespaces.insert(key, namespace);
+    }
+
+    /// Check if nonce has been consumed in the (from, to) namespace
+    pub fn nonce_exists(&self, from: &Principal, to: &Principal, nonce: &Nonce) -> bool {
+        self.nonce.borrow().get().contains(nonce)
+            || self
+                .nonce_namespaces
+                .borrow()
+                .get(&(StorablePrincipal(*from), StorablePrincipal(*to)))
+                .is_some_and(|namespace| namespace.contains(nonce))
+    }
+
+    /// Consumed nonces as a watermark plus the sparse set above it, so the
+    /// size does not grow with the number of nonces consumed
+    pub fn get_nonces(&self) -> Vec<NamespaceNonces> {
+        let legacy = self.nonce.borrow().get().clone();
+        let namespaces = self.nonce_namespaces.borrow();
+
+        (legacy != NonceSet::default())
+            .then_some(NamespaceNonces {
+                namespace: None,
+                nonces: legacy,
+            })
+            .into_iter()
+            .chain(
+                namespaces
+                    .iter()
+                    .map(|((from, to), nonces)| NamespaceNonces {
+                        namespace: Some((from.0, to.0)),
+                        nonces,
+                    }),
+            )
+            .collect()
+    }
+
+    /// Number of consumed nonces, across namespaces
+    pub fn nonces_count(&self) -> u64 {
+        self.nonce_namespaces
+            .borrow()
+            .values()
+            .map(|namespace| namespace.count())
+            .fold(self.nonce.borrow().get().count(), u64::saturating_add)
+    }
+
+    pub fn get_nonce_namespaces(&self) -> Vec<NonceNamespaceInfo> {
+        self.nonce_namespaces
+            .borrow()
+            .iter()
+            .map(|((from, to), namespace)| NonceNamespaceInfo {
+                from: from.0,
+                to: to.0,
+                highest: namespace.highest(),
+                watermark: namespace.watermark.clone(),
+                sparse_count: namespace.sparse.len() as u64,
+            })
+            .collect()
+    }
+
+    //
+    // Delivery
+    //
+
+    /// Start tracking the delivery of an incoming message, unless it already is
+    pub fn record_delivery(
+        &self,
+        msg_hash: String,
+        from: Principal,
+        to: Principal,
+        nonce: Nonce,
+        payload: Vec<Nat>,
+    ) {
+        if self.deliveries.borrow().contains_key(&msg_hash) {
+            return;
+        }
+
+        self.insert_delivery(Delivery {
+            msg_hash,
+            from,
+            to,
+            nonce,
+            payload,
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            last_attempt_at: None,
+            next_attempt_at: None,
+            last_error: None,
+        });
+    }
+
+    pub fn get_delivery(&self, msg_hash: &str) -> Option<Delivery> {
+        self.deliveries.borrow().get(&msg_hash.to_string())
+    }
+
+    /// Mark a delivery attempt as in flight, so that it is only retried
+    /// again once the backoff of the attempt ran out
+    pub fn start_delivery_attempt(&self, msg_hash: &str, now: u64) {
+        self.update_delivery(msg_hash, |delivery| {
+            delivery.last_attempt_at = Some(now);
+            if delivery.status == DeliveryStatus::Pending {
+                delivery.next_attempt_at = Some(now + retry_delay(delivery.attempts + 1));
+            }
+        });
     }
 
-    /// Get store nonce from unique set
-    pub fn get_nonce(&self, nonce: Nonce) -> Option<Nonce> {
-        self.nonce.borrow().get(&nonce).cloned()
+    pub fn delivery_succeeded(&self, msg_hash: &str) {
+        self.update_delivery(msg_hash, |delivery| {
+            delivery.attempts += 1;
+            delivery.status = DeliveryStatus::Delivered;
+            delivery.next_attempt_at = None;
+            delivery.last_error = None;
+        });
     }
 
-    /// Check if nonce exists in set
-    pub fn nonce_exists(&self, nonce: &Nonce) -> bool {
-        self.nonce.borrow().contains(nonce)
+    /// Schedule the next attempt with an exponential backoff, or give up
+    /// after `MAX_DELIVERY_ATTEMPTS`
+    pub fn delivery_failed(&self, msg_hash: &str, now: u64, error: TeraError) {
+        self.update_delivery(msg_hash, |delivery| {
+            delivery.attempts += 1;
+            delivery.last_error = Some(error);
+
+            if delivery.status == DeliveryStatus::Delivered {
+                return;
+            }
+            if delivery.attempts >= MAX_DELIVERY_ATTEMPTS {
+                delivery.status = DeliveryStatus::Failed;
+                delivery.next_attempt_at = None;
+            } else {
+                delivery.status = DeliveryStatus::Pending;
+                delivery.next_attempt_at = Some(now + retry_delay(delivery.attempts));
+            }
+        });
+    }
+
+    pub fn remove_delivery(&self, msg_hash: &str) -> Option<Delivery> {
+        let delivery = self.deliveries.borrow_mut().remove(&msg_hash.to_string())?;
+        if let Some(key) = delivery_queue_key(&delivery) {
+            self.delivery_queue.borrow_mut().remove(&key);
+        }
+
+        Some(delivery)
     }
 
-    /// Get all nonces from set
-    pub fn get_nonces(&self) -> Vec<Nonce> {
-        self.nonce.borrow().iter().cloned().collect()
+    /// Pending deliveries whose next attempt is due, oldest first
+    pub fn get_due_deliveries(&self, now: u64, limit: usize) -> Vec<Delivery> {
+        let deliveries = self.deliveries.borrow();
+
+        self.delivery_queue
+            .borrow()
+            .iter()
+            .take_while(|(key, _)| key.at <= now)
+            .filter_map(|(key, _)| deliveries.get(&key.msg_hash))
+            .filter(|delivery| delivery.status == DeliveryStatus::Pending)
+            .take(limit)
+            .collect()
     }
 
-    ///
-    /// Authorization
-    ///
+    /// Incoming messages that were stored but never delivered
+    pub fn get_undelivered_messages(&self) -> Vec<Delivery> {
+        let deliveries = self.deliveries.borrow();
 
-    /// Check if caller is authorized
-    pub fn is_authorized(&self) -> Result<(), String> {
-        self.authorized
+        self.delivery_queue
             .borrow()
-            .contains(&caller())
-            .then(|| ())
-            .ok_or("Caller is not authorized".to_string())
+            .iter()
+            .filter_map(|(key, _)| deliveries.get(&key.msg_hash))
+            .collect()
+    }
+
+    pub fn undelivered_messages_count(&self) -> u64 {
+        self.delivery_queue.borrow().len()
     }
 
-    /// Add new pid to list of authorized
-    pub fn authorize(&self, other: Principal) {
-        let caller = caller();
-        let caller_autorized = self.authorized.borrow().iter().any(|p| *p == caller);
-        if caller_autorized {
-            self.authorized.borrow_mut().push(other);
+    fn update_delivery(&self, msg_hash: &str, update: impl FnOnce(&mut Delivery)) {
+        let delivery = self.deliveries.borrow().get(&msg_hash.to_string());
+
+        if let Some(mut delivery) = delivery {
+            update(&mut delivery);
+            self.insert_delivery(delivery);
         }
     }
 
-    ///
-    /// Pre/Post Upgrade
-    ///
+    /// Store a delivery and move it to its place in the delivery queue, or out
+    /// of it once delivered
+    fn insert_delivery(&self, delivery: Delivery) {
+        let mut queue = self.delivery_queue.borrow_mut();
+        let previous = self
+            .deliveries
+            .borrow_mut()
+            .insert(delivery.msg_hash.clone(), delivery.clone());
+
+        if let Some(key) = previous.as_ref().and_then(delivery_queue_key) {
+            queue.remove(&key);
+        }
+        if let Some(key) = delivery_queue_key(&delivery) {
+            queue.insert(key, ());
+        }
+    }
+
+    //
+    // Events
+    //
+
+    pub fn record_event(&self, kind: EventKind, caller: Principal, msg_hash: Option<String>) {
+        let events = self.events.borrow();
+        let event = Event {
+            id: events.len(),
+            kind,
+            timestamp: time(),
+            caller,
+            msg_hash,
+        };
+
+        events.append(&event).expect("failed to record event");
+    }
+
+    /// Get a page of events starting at the event with id `start`
+    pub fn get_events(&self, start: u64, limit: usize) -> Vec<Event> {
+        let events = self.events.borrow();
+        let end = events
+            .len()
+            .min(start.saturating_add(limit.min(MAX_EVENTS_PAGE_SIZE) as u64));
+
+        (start..end).filter_map(|id| events.get(id)).collect()
+    }
+
+    pub fn events_count(&self) -> u64 {
+        self.events.borrow().len()
+    }
+
+    //
+    // Authorization
+    //
+
+    /// Check if caller has been granted the role
+    pub fn has_role(&self, role: Role) -> Result<(), TeraError> {
+        self.roles
+            .borrow()
+            .contains_key(&(StorablePrincipal(caller()), role))
+            .then_some(())
+            .ok_or(TeraError::Unauthorized)
+    }
+
+    /// Grant role to pid
+    pub fn grant_role(&self, principal: Principal, role: Role) {
+        self.roles
+            .borrow_mut()
+            .insert((StorablePrincipal(principal), role), ());
+    }
 
-    /// Return entire state
-    /// Before upgrade
-    pub fn take_all(&self) -> StableTerabetiaState {
-        StableTerabetiaState {
-            messages: self.messages.take(),
-            nonce: self.nonce.take(),
-            messages_out: self.messages_out.take(),
-            message_out_index: self.message_out_index.take(),
-            authorized: self.authorized.take(),
+    /// Revoke role from pid, the last admin can not be revoked
+    pub fn revoke_role(&self, principal: Principal, role: Role) -> Result<(), TeraError> {
+        let mut roles = self.roles.borrow_mut();
+        let key = (StorablePrincipal(principal), role);
+
+        if role == Role::Admin && roles.contains_key(&key) {
+            let admins = roles
+                .keys()
+                .filter(|(_, granted)| *granted == Role::Admin)
+                .count();
+
+            if admins == 1 {
+                return Err(TeraError::LastAdmin);
+            }
         }
+
+        roles.remove(&key);
+
+        Ok(())
     }
 
-    /// Clear/Reset State
-    /// Before upgrade
-    pub fn clear_all(&self) {
-        self.messages.borrow_mut().clear();
-        self.nonce.borrow_mut().clear();
-        self.messages_out.borrow_mut().clear();
-        self.message_out_index.replace(0);
-        self.authorized.borrow_mut().clear();
+    /// Get all pids with their granted roles
+    pub fn list_roles(&self) -> Vec<(Principal, Vec<Role>)> {
+        let mut granted: BTreeMap<StorablePrincipal, Vec<Role>> = BTreeMap::new();
+        for (principal, role) in self.roles.borrow().keys() {
+            granted.entry(principal).or_default().push(role);
+        }
+
+        granted
+            .into_iter()
+            .map(|(principal, roles)| (principal.0, roles))
+            .collect()
     }
 
-    /// Replace state with new state
-    /// After upgrade
+    //
+    // Signing
+    //
+
+    pub fn get_signing_config(&self) -> Option<SigningConfig> {
+        self.signing_config.borrow().get().clone()
+    }
+
+    pub fn set_signing_config(&self, config: SigningConfig) {
+        self.signing_config
+            .borrow_mut()
+            .set(Some(config))
+            .expect("failed to update signing config");
+    }
+
+    //
+    // Pause
+    //
+
+    pub fn pause(&self, scope: PauseScope) {
+        self.paused.borrow_mut().insert(scope, ());
+    }
+
+    pub fn unpause(&self, scope: PauseScope) {
+        self.paused.borrow_mut().remove(&scope);
+    }
+
+    /// Check that the operation has not been paused
+    pub fn ensure_not_paused(&self, scope: PauseScope) -> Result<(), TeraError> {
+        match self.paused.borrow().contains_key(&scope) {
+            true => Err(TeraError::Paused(scope)),
+            false => Ok(()),
+        }
+    }
+
+    pub fn get_pause_state(&self) -> Vec<PauseScope> {
+        self.paused.borrow().keys().collect()
+    }
+
+    //
+    // Post Upgrade
+    //
+
+    pub fn get_schema_version(&self) -> u32 {
+        *self.schema_version.borrow().get()
+    }
+
+    pub fn set_schema_version(&self, version: u32) {
+        self.schema_version
+            .borrow_mut()
+            .set(version)
+            .expect("failed to update schema version");
+    }
+
+    /// Move a candid snapshot, as written before the state lived in stable
+    /// structures, into the stable structures
     pub fn replace_all(&self, stable_tera_state: StableTerabetiaState) {
-        self.messages.replace(stable_tera_state.messages);
-        self.nonce.replace(stable_tera_state.nonce);
-        self.messages_out.replace(stable_tera_state.messages_out);
+        // messages stored before timestamps were recorded start their ttl now
+        let messages_stored_at = stable_tera_state.messages_stored_at.unwrap_or_default();
+        let now = time();
+        {
+            let mut messages = self.messages.borrow_mut();
+            for (msg_hash, count) in stable_tera_state.messages {
+                let stored_at = messages_stored_at.get(&msg_hash).cloned().unwrap_or(now);
+                messages.insert(msg_hash, IncomingMessageEntry { count, stored_at });
+            }
+        }
+
+        self.set_message_ttl(stable_tera_state.message_ttl.unwrap_or(DEFAULT_MESSAGE_TTL));
+        {
+            let expired_messages = self.expired_messages.borrow();
+            for message in stable_tera_state.expired_messages.unwrap_or_default() {
+                expired_messages
+                    .push(&message)
+                    .expect("failed to archive expired message");
+            }
+        }
+
+        let nonce = match stable_tera_state.compact_nonce {
+            Some(nonce) => nonce,
+            None => stable_tera_state.nonce.into_iter().collect(),
+        };
+        self.nonce
+            .borrow_mut()
+            .set(nonce)
+            .expect("failed to update nonce");
+        {
+            let mut namespaces = self.nonce_namespaces.borrow_mut();
+            for ((from, to), namespace) in stable_tera_state.nonce_namespaces.unwrap_or_default() {
+                namespaces.insert((StorablePrincipal(from), StorablePrincipal(to)), namespace);
+            }
+        }
+
+        let messages_out = match stable_tera_state.indexed_messages_out {
+            Some(messages_out) => messages_out,
+            None => index_legacy_messages(
+                stable_tera_state.messages_out,
+                stable_tera_state.message_out_index,
+            ),
+        };
+        {
+            let mut stable_messages_out = self.messages_out.borrow_mut();
+            for (index, message) in messages_out {
+                stable_messages_out.insert(index, message);
+            }
+        }
         self.message_out_index
-            .replace(stable_tera_state.message_out_index);
-        self.authorized.replace(stable_tera_state.authorized);
+            .borrow_mut()
+            .set(stable_tera_state.message_out_index)
+            .expect("failed to update message out index");
+        self.rebuild_messages_out_tree();
+
+        match stable_tera_state.roles {
+            Some(roles) => roles
+                .into_iter()
+                .flat_map(|(principal, roles)| roles.into_iter().map(move |role| (principal, role)))
+                .for_each(|(principal, role)| self.grant_role(principal, role)),
+            None => stable_tera_state
+                .authorized
+                .into_iter()
+                .flat_map(|principal| Role::ALL.iter().map(move |role| (principal, *role)))
+                .for_each(|(principal, role)| self.grant_role(principal, role)),
+        }
+
+        if let Some(config) = stable_tera_state.signing_config {
+            self.set_signing_config(config);
+        }
     }
 }
 
@@ -298,6 +1237,7 @@ mod tests {
     use std::str::FromStr;
 
     use crate::common::{
+        merkle::hash_pair,
         types::{IncomingMessageHashParams, Message, OutgoingMessageHashParams},
         utils::Keccak256HashFn,
     };
@@ -305,6 +1245,10 @@ mod tests {
     use super::*;
     use ic_kit::{MockContext, Principal};
 
+    fn msg_hash_from(i: u64) -> String {
+        format!("{:064x}", i)
+    }
+
     #[test]
     fn test_outgoing_message_from() {
         let index: u64 = 1;
@@ -347,6 +1291,7 @@ mod tests {
 
     #[test]
     fn test_store_incoming_message() {
+        MockContext::new().inject();
         let nonce = Nat::from(4);
 
         // receiver address ic
@@ -385,6 +1330,154 @@ mod tests {
         assert_eq!(msg_exists.unwrap(), true);
     }
 
+    #[test]
+    fn test_consume_incoming_message() {
+        MockContext::new().inject();
+        let msg_hash = msg_hash_from(1);
+
+        STATE.with(|s| s.store_incoming_message(msg_hash.clone()));
+        STATE.with(|s| s.store_incoming_message(msg_hash.clone()));
+
+        assert!(STATE
+            .with(|s| s.consume_incoming_message(&msg_hash))
+            .is_ok());
+        assert!(STATE.with(|s| s.message_exists(msg_hash.clone())).is_ok());
+
+        assert!(STATE
+            .with(|s| s.consume_incoming_message(&msg_hash))
+            .is_ok());
+        assert!(STATE.with(|s| s.message_exists(msg_hash.clone())).is_err());
+        assert!(STATE.with(|s| s.messages.borrow().is_empty()));
+
+        assert!(STATE
+            .with(|s| s.consume_incoming_message(&msg_hash))
+            .is_err());
+    }
+
+    #[test]
+    fn test_get_stale_messages() {
+        MockContext::new().inject();
+        let ttl = 1_000;
+        STATE.with(|s| s.set_message_ttl(ttl));
+        STATE.with(|s| s.store_incoming_message(msg_hash_from(1)));
+
+        let stored_at = STATE.with(|s| {
+            s.messages
+                .borrow()
+                .get(&msg_hash_from(1))
+                .unwrap()
+                .stored_at
+        });
+
+        let stale = STATE.with(|s| s.get_stale_messages(stored_at + ttl, 10));
+        assert!(stale.is_empty());
+
+        let stale = STATE.with(|s| s.get_stale_messages(stored_at + ttl + 1, 10));
+        assert_eq!(
+            stale,
+            vec![IncomingMessage {
+                msg_hash: msg_hash_from(1),
+                count: 1,
+                stored_at,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_purge_stale_messages() {
+        MockContext::new().inject();
+        let ttl = 1_000;
+        STATE.with(|s| s.set_message_ttl(ttl));
+        STATE.with(|s| s.store_incoming_message(msg_hash_from(1)));
+
+        let stored_at = STATE.with(|s| {
+            s.messages
+                .borrow()
+                .get(&msg_hash_from(1))
+                .unwrap()
+                .stored_at
+        });
+        let now = stored_at + ttl + 1;
+
+        let purged = STATE.with(|s| s.purge_stale_messages(now, 10));
+        assert_eq!(purged.len(), 1);
+        assert_eq!(purged[0].expired_at, now);
+
+        assert!(STATE.with(|s| s.message_exists(msg_hash_from(1))).is_err());
+        assert!(STATE.with(|s| s.get_stale_messages(now, 10)).is_empty());
+        assert_eq!(STATE.with(|s| s.get_expired_messages(0, 10)), purged);
+    }
+
+    #[test]
+    fn test_purge_stale_messages_limit() {
+        MockContext::new().inject();
+        STATE.with(|s| s.set_message_ttl(1_000));
+        for i in 1..=3 {
+            STATE.with(|s| {
+                s.messages.borrow_mut().insert(
+                    msg_hash_from(i),
+                    IncomingMessageEntry {
+                        count: 1,
+                        stored_at: i,
+                    },
+                );
+            });
+        }
+
+        let purged = STATE.with(|s| s.purge_stale_messages(2_000, 2));
+        let msg_hashes: Vec<String> = purged.into_iter().map(|m| m.msg_hash).collect();
+        assert_eq!(msg_hashes, vec![msg_hash_from(1), msg_hash_from(2)]);
+        assert_eq!(STATE.with(|s| s.get_stale_messages(2_000, 10)).len(), 1);
+
+        let page = STATE.with(|s| s.get_expired_messages(1, 10));
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].msg_hash, msg_hash_from(2));
+    }
+
+    #[test]
+    fn test_store_incoming_message_keeps_first_stored_at() {
+        MockContext::new().inject();
+        STATE.with(|s| {
+            s.messages.borrow_mut().insert(
+                msg_hash_from(1),
+                IncomingMessageEntry {
+                    count: 1,
+                    stored_at: 42,
+                },
+            );
+        });
+
+        STATE.with(|s| s.store_incoming_message(msg_hash_from(1)));
+
+        assert_eq!(
+            STATE.with(|s| s.messages.borrow().get(&msg_hash_from(1))),
+            Some(IncomingMessageEntry {
+                count: 2,
+                stored_at: 42,
+            })
+        );
+    }
+
+    #[test]
+    fn test_replace_all_stamps_legacy_incoming_messages() {
+        MockContext::new().inject();
+        let mut messages = HashMap::new();
+        messages.insert(msg_hash_from(1), 2);
+
+        STATE.with(|s| {
+            s.replace_all(StableTerabetiaState {
+                messages,
+                ..Default::default()
+            })
+        });
+
+        let entry = STATE.with(|s| s.messages.borrow().get(&msg_hash_from(1)).unwrap());
+        assert_eq!(entry.count, 2);
+        assert!(entry.stored_at > 0);
+        assert_eq!(STATE.with(|s| s.get_message_ttl()), DEFAULT_MESSAGE_TTL);
+        assert!(STATE.with(|s| s.get_stale_messages(time(), 10)).is_empty());
+    }
+
     #[test]
     fn test_store_outgoing_message() {
         // receiver address eth
@@ -431,90 +1524,448 @@ mod tests {
         let msg_key = hex::encode(message_out.msg_key);
         let msg_hash = message_out.msg_hash;
 
-        let remove_message =
-            STATE.with(|s| s.remove_messages(vec![OutgoingMessagePair { msg_key, msg_hash }]));
+        let removed =
+            STATE.with(|s| s.take_messages(vec![OutgoingMessagePair { msg_key, msg_hash }]));
 
-        assert_eq!(remove_message.unwrap(), true);
+        assert_eq!(removed.unwrap().len(), 1);
 
         outoging_messages = STATE.with(|s| s.get_messages());
 
         assert_eq!(outoging_messages.len(), 0);
     }
 
+    #[test]
+    fn test_get_messages_ordered() {
+        for i in 0..100 {
+            let _ = STATE.with(|s| s.store_outgoing_message(msg_hash_from(i)));
+        }
+
+        let messages = STATE.with(|s| s.get_messages());
+        let msg_hashes: Vec<String> = messages.into_iter().map(|m| m.msg_hash).collect();
+        let expected: Vec<String> = (0..100).map(msg_hash_from).collect();
+
+        assert_eq!(msg_hashes, expected);
+    }
+
+    #[test]
+    fn test_get_messages_page() {
+        for i in 1..=10 {
+            let _ = STATE.with(|s| s.store_outgoing_message(msg_hash_from(i)));
+        }
+
+        let first_page = STATE.with(|s| s.get_messages_page(0, 4));
+        assert_eq!(first_page.len(), 4);
+        assert_eq!(first_page.first().unwrap().index, 1);
+        assert_eq!(first_page.last().unwrap().index, 4);
+        assert_eq!(first_page.last().unwrap().msg_hash, msg_hash_from(4));
+
+        let checkpoint = first_page.last().unwrap().index;
+        let second_page = STATE.with(|s| s.get_messages_page(checkpoint, 100));
+        assert_eq!(second_page.len(), 6);
+        assert_eq!(second_page.first().unwrap().index, 5);
+        assert_eq!(second_page.last().unwrap().index, 10);
+
+        let empty_page = STATE.with(|s| s.get_messages_page(10, 100));
+        assert!(empty_page.is_empty());
+    }
+
+    #[test]
+    fn test_replace_all_indexes_legacy_messages() {
+        MockContext::new().inject();
+        let legacy_messages: HashSet<OutgoingMessage> = [1, 2, 4]
+            .iter()
+            .map(|i| OutgoingMessage::new(msg_hash_from(*i), *i))
+            .collect();
+
+        STATE.with(|s| {
+            s.replace_all(StableTerabetiaState {
+                messages_out: legacy_messages,
+                message_out_index: 5,
+                ..Default::default()
+            })
+        });
+
+        let _ = STATE.with(|s| s.store_outgoing_message(msg_hash_from(6)));
+
+        let page = STATE.with(|s| s.get_messages_page(0, 10));
+        let indexes: Vec<u64> = page.iter().map(|m| m.index).collect();
+
+        assert_eq!(indexes, vec![1, 2, 4, 6]);
+        for message in page {
+            assert_eq!(message.msg_hash, msg_hash_from(message.index));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "No index in 1..=5 matches the legacy outgoing message")]
+    fn test_replace_all_legacy_message_without_index() {
+        MockContext::new().inject();
+        let mut legacy_messages = HashSet::new();
+        legacy_messages.insert(OutgoingMessage::new(msg_hash_from(1), 6));
+
+        STATE.with(|s| {
+            s.replace_all(StableTerabetiaState {
+                messages_out: legacy_messages,
+                message_out_index: 5,
+                ..Default::default()
+            })
+        });
+    }
+
+    #[test]
+    fn test_store_outgoing_message_invalid_hash() {
+        let result = STATE.with(|s| s.store_outgoing_message(String::from("not a hash")));
+
+        assert!(result.is_err());
+        assert_eq!(STATE.with(|s| s.outgoing_messages_count()), 0);
+    }
+
+    #[test]
+    fn test_get_messages_root() {
+        assert!(STATE.with(|s| s.get_messages_root()).is_none());
+
+        for i in 1..=5 {
+            let _ = STATE.with(|s| s.store_outgoing_message(msg_hash_from(i)));
+        }
+
+        let root = STATE.with(|s| s.get_messages_root()).unwrap();
+        assert_eq!(root.from_index, 1);
+        assert_eq!(root.to_index, 5);
+        assert_eq!(root.messages_count, 5);
+
+        let expected_root =
+            MerkleTree::from_leaves((1..=5).map(|i| leaf_hash(&msg_hash_from(i)).unwrap()))
+                .root()
+                .unwrap();
+        assert_eq!(root.root, hex::encode(expected_root));
+    }
+
+    #[test]
+    fn test_get_message_proof_after_remove() {
+        let messages: Vec<OutgoingMessage> = (1..=6)
+            .map(|i| {
+                STATE
+                    .with(|s| s.store_outgoing_message(msg_hash_from(i)))
+                    .unwrap()
+            })
+            .collect();
+
+        let removed = messages.first().unwrap();
+        let _ = STATE.with(|s| {
+            s.take_messages(vec![OutgoingMessagePair {
+                msg_key: hex::encode(&removed.msg_key),
+                msg_hash: removed.msg_hash.clone(),
+            }])
+        });
+
+        let root = STATE.with(|s| s.get_messages_root()).unwrap();
+        assert_eq!(root.from_index, 2);
+        assert_eq!(root.to_index, 6);
+
+        let message = messages.last().unwrap();
+        let proof = STATE
+            .with(|s| s.get_message_proof(hex::encode(&message.msg_key)))
+            .unwrap();
+
+        assert_eq!(proof.index, 6);
+        assert_eq!(proof.root.root, root.root);
+
+        let leaf = leaf_hash(&message.msg_hash).unwrap();
+        let computed_root = proof.proof.iter().fold(leaf, |node, sibling| {
+            let mut sibling_node = [0u8; 32];
+            sibling_node.copy_from_slice(&hex::decode(sibling).unwrap());
+            hash_pair(&node, &sibling_node)
+        });
+        assert_eq!(hex::encode(computed_root), root.root);
+
+        let removed_proof = STATE.with(|s| s.get_message_proof(hex::encode(&removed.msg_key)));
+        assert!(removed_proof.is_none());
+    }
+
+    #[test]
+    fn test_get_message_proof_after_invalid_legacy_hash() {
+        MockContext::new().inject();
+        let mut legacy_messages = HashSet::new();
+        legacy_messages.insert(OutgoingMessage::new(String::from("not a hash"), 1));
+        legacy_messages.insert(OutgoingMessage::new(msg_hash_from(2), 2));
+
+        STATE.with(|s| {
+            s.replace_all(StableTerabetiaState {
+                messages_out: legacy_messages,
+                message_out_index: 2,
+                ..Default::default()
+            })
+        });
+        let message = STATE
+            .with(|s| s.store_outgoing_message(msg_hash_from(3)))
+            .unwrap();
+
+        let proof = STATE
+            .with(|s| s.get_message_proof(hex::encode(&message.msg_key)))
+            .unwrap();
+        assert_eq!(proof.index, 3);
+
+        let expected_root = MerkleTree::from_leaves(vec![
+            INVALID_LEAF,
+            leaf_hash(&msg_hash_from(2)).unwrap(),
+            leaf_hash(&msg_hash_from(3)).unwrap(),
+        ])
+        .root()
+        .unwrap();
+        assert_eq!(proof.root.root, hex::encode(expected_root));
+
+        let leaf = leaf_hash(&message.msg_hash).unwrap();
+        let computed_root = proof.proof.iter().fold(leaf, |node, sibling| {
+            let mut sibling_node = [0u8; 32];
+            sibling_node.copy_from_slice(&hex::decode(sibling).unwrap());
+            hash_pair(&node, &sibling_node)
+        });
+        assert_eq!(computed_root, expected_root);
+    }
+
+    #[test]
+    fn test_get_outgoing_batch() {
+        for i in 1..=5 {
+            let _ = STATE.with(|s| s.store_outgoing_message(msg_hash_from(i)));
+        }
+
+        let batch = STATE.with(|s| s.get_outgoing_batch(2, 4)).unwrap();
+        let messages: Vec<(u64, String)> = batch
+            .into_iter()
+            .map(|(index, message)| (index, message.msg_hash))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (2, msg_hash_from(2)),
+                (3, msg_hash_from(3)),
+                (4, msg_hash_from(4))
+            ]
+        );
+
+        assert!(STATE.with(|s| s.get_outgoing_batch(4, 2)).is_err());
+        assert!(STATE.with(|s| s.get_outgoing_batch(6, 10)).is_err());
+    }
+
+    fn nonce_scope() -> (Principal, Principal) {
+        (
+            Principal::from_slice(&[1, 0x00]),
+            Principal::from_slice(&[2, 0x00]),
+        )
+    }
+
     #[test]
     fn test_update_nonce() {
+        let (from, to) = nonce_scope();
         let nonce = Nat::from(1);
-        let expected_nonce = Nat::from(1);
-
-        STATE.with(|s| s.update_nonce(nonce.clone()));
 
-        let get_nonce = STATE.with(|s| s.get_nonce(nonce));
+        STATE.with(|s| s.update_nonce(from, to, nonce.clone()));
 
-        assert_eq!(get_nonce.unwrap(), expected_nonce);
+        assert!(STATE.with(|s| s.nonce_exists(&from, &to, &nonce)));
+        assert!(!STATE.with(|s| s.nonce_exists(&from, &to, &Nat::from(2))));
     }
 
     #[test]
     fn test_nonce_exists() {
+        let (from, to) = nonce_scope();
         let nonce = Nat::from(1);
 
-        STATE.with(|s| s.update_nonce(nonce.clone()));
+        STATE.with(|s| s.update_nonce(from, to, nonce.clone()));
 
-        let nonce_exists = STATE.with(|s| s.nonce_exists(&nonce));
+        let nonce_exists = STATE.with(|s| s.nonce_exists(&from, &to, &nonce));
 
         assert_eq!(nonce_exists, true);
     }
 
+    #[test]
+    fn test_nonce_namespaces_do_not_collide() {
+        let (from, to) = nonce_scope();
+        let other_from = Principal::from_slice(&[3, 0x00]);
+        let nonce = Nat::from(1);
+
+        STATE.with(|s| s.update_nonce(from, to, nonce.clone()));
+
+        assert!(STATE.with(|s| s.nonce_exists(&from, &to, &nonce)));
+        assert!(!STATE.with(|s| s.nonce_exists(&other_from, &to, &nonce)));
+        assert!(!STATE.with(|s| s.nonce_exists(&to, &from, &nonce)));
+    }
+
+    #[test]
+    fn test_legacy_nonces_apply_to_every_namespace() {
+        MockContext::new().inject();
+        let (from, to) = nonce_scope();
+        let mut nonce = HashSet::new();
+        nonce.insert(Nat::from(7));
+
+        STATE.with(|s| {
+            s.replace_all(StableTerabetiaState {
+                nonce,
+                ..Default::default()
+            })
+        });
+
+        assert!(STATE.with(|s| s.nonce_exists(&from, &to, &Nat::from(7))));
+        assert!(!STATE.with(|s| s.nonce_exists(&from, &to, &Nat::from(8))));
+
+        assert_eq!(STATE.with(|s| s.nonce.borrow().get().sparse.len()), 1);
+    }
+
+    #[test]
+    fn test_nonce_set_compacts_contiguous_nonces() {
+        let mut set = NonceSet::default();
+
+        for i in [2, 3, 1, 5, 7] {
+            set.insert(Nat::from(i));
+        }
+
+        assert_eq!(set.watermark, Nat::from(3));
+        assert_eq!(
+            set.sparse.iter().cloned().collect::<Vec<Nonce>>(),
+            vec![Nat::from(5), Nat::from(7)]
+        );
+        assert_eq!(set.highest(), Some(Nat::from(7)));
+
+        set.insert(Nat::from(4));
+        assert_eq!(set.watermark, Nat::from(5));
+        assert!(set.contains(&Nat::from(1)));
+        assert!(!set.contains(&Nat::from(0)));
+        assert!(!set.contains(&Nat::from(6)));
+
+        let nonces: Vec<Nonce> = [7, 5, 4, 3, 2, 1].iter().map(|i| Nat::from(*i)).collect();
+        assert_eq!(nonces.into_iter().collect::<NonceSet>(), set);
+    }
+
+    #[test]
+    fn test_get_nonce_namespaces() {
+        let (from, to) = nonce_scope();
+
+        for i in [1, 2, 3, 5, 7] {
+            STATE.with(|s| s.update_nonce(from, to, Nat::from(i)));
+        }
+
+        let namespaces = STATE.with(|s| s.get_nonce_namespaces());
+        assert_eq!(
+            namespaces,
+            vec![NonceNamespaceInfo {
+                from,
+                to,
+                highest: Some(Nat::from(7)),
+                watermark: Nat::from(3),
+                sparse_count: 2,
+            }]
+        );
+    }
+
     #[test]
     fn test_get_nonces() {
+        let (from, to) = nonce_scope();
         let nonce1 = Nat::from(1);
         let nonce2 = Nat::from(2);
 
-        STATE.with(|s| s.update_nonce(nonce1.clone()));
-        STATE.with(|s| s.update_nonce(nonce2.clone()));
+        STATE.with(|s| s.update_nonce(from, to, nonce1.clone()));
+        STATE.with(|s| s.update_nonce(from, to, nonce2.clone()));
+        STATE.with(|s| s.update_nonce(from, to, Nat::from(4)));
 
         let nonces = STATE.with(|s| s.get_nonces());
 
-        assert_eq!(nonces.len(), 2);
+        assert_eq!(
+            nonces,
+            vec![NamespaceNonces {
+                namespace: Some((from, to)),
+                nonces: NonceSet {
+                    watermark: nonce2,
+                    sparse: [Nat::from(4)].iter().cloned().collect(),
+                },
+            }]
+        );
     }
 
     #[test]
-    fn test_is_authorized() {
-        let is_authorized = STATE.with(|s| s.is_authorized());
+    fn test_has_role() {
+        let admin_pid = Principal::from_slice(&[1, 0x00]);
+        let relayer_pid = Principal::from_slice(&[2, 0x00]);
+        let mock_env = MockContext::new().with_caller(relayer_pid).inject();
+
+        STATE.with(|s| s.grant_role(admin_pid, Role::Admin));
+        STATE.with(|s| s.grant_role(relayer_pid, Role::Relayer));
 
-        assert!(is_authorized.is_ok());
+        assert!(STATE.with(|s| s.has_role(Role::Relayer)).is_ok());
+        assert!(STATE.with(|s| s.has_role(Role::Poller)).is_err());
+        assert!(STATE.with(|s| s.has_role(Role::Admin)).is_err());
+
+        mock_env.update_caller(admin_pid);
+        assert!(STATE.with(|s| s.has_role(Role::Admin)).is_ok());
+        assert!(STATE.with(|s| s.has_role(Role::Relayer)).is_err());
     }
 
     #[test]
     fn test_not_authorized() {
-        let controller_pid = Principal::from_slice(&[1, 0x00]);
         let not_authorized_pid = Principal::from_slice(&[2, 0x00]);
-        let mock_env = MockContext::new().with_caller(controller_pid).inject();
-        let is_authorized = STATE.with(|s| s.is_authorized());
+        MockContext::new().with_caller(not_authorized_pid).inject();
+
+        for role in Role::ALL {
+            assert!(STATE.with(|s| s.has_role(role)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_revoke_role() {
+        let admin_pid = Principal::from_slice(&[1, 0x00]);
+        let relayer_pid = Principal::from_slice(&[2, 0x00]);
+        MockContext::new().with_caller(relayer_pid).inject();
+
+        STATE.with(|s| s.grant_role(admin_pid, Role::Admin));
+        STATE.with(|s| s.grant_role(relayer_pid, Role::Relayer));
+        assert!(STATE.with(|s| s.has_role(Role::Relayer)).is_ok());
+
+        let result = STATE.with(|s| s.revoke_role(relayer_pid, Role::Relayer));
+        assert!(result.is_ok());
+        assert!(STATE.with(|s| s.has_role(Role::Relayer)).is_err());
+        assert_eq!(STATE.with(|s| s.list_roles()).len(), 1);
+    }
+
+    #[test]
+    fn test_revoke_last_admin() {
+        let admin_pid = Principal::from_slice(&[1, 0x00]);
+        let other_admin_pid = Principal::from_slice(&[2, 0x00]);
+        MockContext::new().with_caller(admin_pid).inject();
 
-        mock_env.update_caller(not_authorized_pid);
-        assert!(is_authorized.is_err());
+        STATE.with(|s| s.grant_role(admin_pid, Role::Admin));
+        let result = STATE.with(|s| s.revoke_role(admin_pid, Role::Admin));
+        assert!(result.is_err());
+
+        STATE.with(|s| s.grant_role(other_admin_pid, Role::Admin));
+        let result = STATE.with(|s| s.revoke_role(admin_pid, Role::Admin));
+        assert!(result.is_ok());
+        assert!(STATE.with(|s| s.has_role(Role::Admin)).is_err());
     }
 
     #[test]
-    fn test_authorize() {
+    fn test_replace_all_grants_legacy_authorized_every_role() {
         let controller_pid = Principal::from_slice(&[1, 0x00]);
-        let new_controller_pid = Principal::from_slice(&[2, 0x00]);
-        let mock_env = MockContext::new().with_caller(controller_pid).inject();
+        MockContext::new().with_caller(controller_pid).inject();
 
-        STATE.with(|s| s.authorize(new_controller_pid));
+        STATE.with(|s| {
+            s.replace_all(StableTerabetiaState {
+                authorized: vec![controller_pid],
+                ..StableTerabetiaState::default()
+            })
+        });
 
-        mock_env.update_caller(new_controller_pid);
-        let is_authorized = STATE.with(|s| s.is_authorized());
-        assert!(is_authorized.is_ok());
+        for role in Role::ALL {
+            assert!(STATE.with(|s| s.has_role(role)).is_ok());
+        }
+
+        assert_eq!(
+            STATE.with(|s| s.list_roles()),
+            vec![(controller_pid, Role::ALL.to_vec())]
+        );
     }
 
     #[test]
     fn store_message_with_max_limit_reached() {
-        let capacity = STATE.with(|s| s.messages_out.borrow().capacity());
-        assert!(capacity >= MAX_OUTGOING_MESSAGES_COUNT);
-
         for i in 0..MAX_OUTGOING_MESSAGES_COUNT {
-            let result = STATE.with(|s| s.store_outgoing_message(i.to_string()));
+            let result = STATE.with(|s| s.store_outgoing_message(msg_hash_from(i as u64)));
             assert!(result.is_ok());
         }
         assert_eq!(
@@ -525,17 +1976,143 @@ mod tests {
         // when reach max it returns error
         let result = STATE.with(|s| s.store_outgoing_message("0x00".to_string()));
         assert!(result.is_err());
-        assert_eq!(result.err().unwrap(), "Max OutgoingMessages amount reached");
+        assert_eq!(result.err().unwrap(), TeraError::OutgoingQueueFull);
     }
 
     #[test]
-    fn test_take_all() {
-        // ToDo
+    fn test_delivery_backoff_until_max_attempts() {
+        MockContext::new().inject();
+        let (from, to) = nonce_scope();
+        let msg_hash = msg_hash_from(1);
+        let error = TeraError::CallFailed {
+            code: 5,
+            msg: String::from("trapped"),
+        };
+
+        STATE.with(|s| s.record_delivery(msg_hash.clone(), from, to, Nat::from(1), vec![]));
+
+        let mut previous_delay = 0;
+        for attempt in 1..MAX_DELIVERY_ATTEMPTS {
+            STATE.with(|s| s.delivery_failed(&msg_hash, 0, error.clone()));
+
+            let delivery = STATE.with(|s| s.get_delivery(&msg_hash).unwrap());
+            let delay = delivery.next_attempt_at.unwrap();
+            assert_eq!(delivery.attempts, attempt);
+            assert_eq!(delivery.status, DeliveryStatus::Pending);
+            assert!(delay >= previous_delay && delay <= DELIVERY_RETRY_MAX_DELAY);
+            previous_delay = delay;
+        }
+        assert_eq!(retry_delay(1), DELIVERY_RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(2), 2 * DELIVERY_RETRY_BASE_DELAY);
+
+        STATE.with(|s| s.delivery_failed(&msg_hash, 0, error.clone()));
+
+        let delivery = STATE.with(|s| s.get_delivery(&msg_hash).unwrap());
+        assert_eq!(delivery.status, DeliveryStatus::Failed);
+        assert_eq!(delivery.next_attempt_at, None);
+        assert_eq!(delivery.last_error, Some(error));
+        assert!(STATE
+            .with(|s| s.get_due_deliveries(u64::MAX, 10))
+            .is_empty());
+        assert_eq!(STATE.with(|s| s.get_undelivered_messages()).len(), 1);
     }
 
     #[test]
-    fn test_clear_all() {
-        // ToDo
+    fn test_trim_consumed_messages() {
+        MockContext::new().inject();
+
+        for i in 1..=3 {
+            STATE.with(|s| {
+                s.record_consumed_message(MessageStatusInfo {
+                    msg_hash: msg_hash_from(i),
+                    status: MessageStatus::Consumed,
+                    stored_at: Some(i),
+                    delivered_at: None,
+                    consumed_at: Some(i * 10),
+                })
+            });
+        }
+        STATE.with(|s| s.trim_consumed_messages(2));
+
+        let status = |i| STATE.with(|s| s.get_message_status(&msg_hash_from(i)).status);
+        assert_eq!(status(1), MessageStatus::Unknown);
+        assert_eq!(status(2), MessageStatus::Consumed);
+        assert_eq!(status(3), MessageStatus::Consumed);
+        assert_eq!(STATE.with(|s| s.consumed_messages_count()), 3);
+    }
+
+    #[test]
+    fn test_delivery_removed_once_consumed() {
+        MockContext::new().inject();
+        let (from, to) = nonce_scope();
+        let msg_hash = msg_hash_from(1);
+
+        STATE.with(|s| {
+            s.store_incoming_message(msg_hash.clone());
+            s.record_delivery(msg_hash.clone(), from, to, Nat::from(1), vec![]);
+            s.delivery_succeeded(&msg_hash);
+        });
+        assert!(STATE.with(|s| s.get_undelivered_messages()).is_empty());
+
+        assert!(STATE
+            .with(|s| s.consume_incoming_message(&msg_hash))
+            .is_ok());
+        assert!(STATE.with(|s| s.get_delivery(&msg_hash)).is_none());
+    }
+
+    #[test]
+    fn test_get_due_deliveries_in_attempt_order() {
+        MockContext::new().inject();
+        let (from, to) = nonce_scope();
+        let error = TeraError::CallFailed {
+            code: 5,
+            msg: String::from("trapped"),
+        };
+
+        for (i, now) in [(1, 30), (2, 10), (3, 20)] {
+            STATE.with(|s| {
+                s.record_delivery(msg_hash_from(i), from, to, Nat::from(i), vec![]);
+                s.delivery_failed(&msg_hash_from(i), now, error.clone());
+            });
+        }
+        assert_eq!(STATE.with(|s| s.undelivered_messages_count()), 3);
+
+        let due_at = 20 + DELIVERY_RETRY_BASE_DELAY;
+        let due: Vec<String> = STATE
+            .with(|s| s.get_due_deliveries(due_at, 10))
+            .into_iter()
+            .map(|delivery| delivery.msg_hash)
+            .collect();
+        assert_eq!(due, vec![msg_hash_from(2), msg_hash_from(3)]);
+
+        STATE.with(|s| s.delivery_succeeded(&msg_hash_from(2)));
+        let due = STATE.with(|s| s.get_due_deliveries(due_at, 10));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].msg_hash, msg_hash_from(3));
+        assert_eq!(STATE.with(|s| s.undelivered_messages_count()), 2);
+    }
+
+    #[test]
+    fn test_get_events_page() {
+        let caller = Principal::from_slice(&[1, 0x00]);
+        MockContext::new().with_caller(caller).inject();
+
+        for i in 0..5 {
+            STATE.with(|s| {
+                s.record_event(EventKind::IncomingStored, caller, Some(msg_hash_from(i)))
+            });
+        }
+
+        assert_eq!(STATE.with(|s| s.events_count()), 5);
+
+        let page = STATE.with(|s| s.get_events(3, 10));
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].id, 3);
+        assert_eq!(page[0].msg_hash, Some(msg_hash_from(3)));
+        assert_eq!(page[0].caller, caller);
+
+        assert_eq!(STATE.with(|s| s.get_events(0, 2)).len(), 2);
+        assert!(STATE.with(|s| s.get_events(5, 10)).is_empty());
     }
 
     #[test]
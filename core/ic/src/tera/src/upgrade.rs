@@ -1,41 +1,210 @@
 use ic_cdk::storage;
-use ic_cdk_macros::{post_upgrade, pre_upgrade};
+use ic_cdk_macros::post_upgrade;
 
-use crate::tera::{StableTerabetiaState, STATE};
+use crate::{
+    common::stable::{has_candid_snapshot, STABLE_SCHEMA_VERSION},
+    tera::{StableTerabetiaState, TerabetiaState, STATE},
+};
 
-#[pre_upgrade]
-fn pre_upgrade() {
-    let stable_tera_state = STATE.with(|s| s.take_all());
-
-    storage::stable_save((stable_tera_state,)).expect("failed to save tera state");
-}
+/// Migrations of the stable structures, `MIGRATIONS[v - 1]` moves the layout
+/// from version `v` to `v + 1`. Bumping `STABLE_SCHEMA_VERSION` without adding
+/// a migration does not compile.
+const MIGRATIONS: [fn(&TerabetiaState); STABLE_SCHEMA_VERSION as usize - 1] = [];
 
+/// The state lives in stable structures and survives upgrades as is, only
+/// its layout is migrated up to `STABLE_SCHEMA_VERSION`.
 #[post_upgrade]
 fn post_upgrade() {
-    STATE.with(|s| s.clear_all());
+    // checked before STATE is touched, the memory manager would claim the snapshot
+    let snapshot = has_candid_snapshot().then(|| {
+        let (stable_tera_state,): (StableTerabetiaState,) =
+            storage::stable_restore().expect("failed to restore stable tera state");
+        stable_tera_state
+    });
+
+    STATE.with(|s| migrate(s, snapshot));
+    STATE.with(|s| s.rebuild_messages_out_tree());
+}
+
+fn migrate(state: &TerabetiaState, snapshot: Option<StableTerabetiaState>) {
+    let version = match snapshot {
+        // version 0, written by a release predating the stable structures
+        Some(stable_tera_state) => {
+            state.replace_all(stable_tera_state);
+            1
+        }
+        None => state.get_schema_version(),
+    };
+
+    if version > STABLE_SCHEMA_VERSION {
+        panic!(
+            "stable schema version {} is newer than {}",
+            version, STABLE_SCHEMA_VERSION
+        );
+    }
 
-    let (stable_tera_state,): (StableTerabetiaState,) =
-        storage::stable_restore().expect("failed to restore stable tera state");
+    for migration in MIGRATIONS.iter().skip(version as usize - 1) {
+        migration(state);
+    }
 
-    STATE.with(|s| s.replace_all(stable_tera_state));
+    state.set_schema_version(STABLE_SCHEMA_VERSION);
 }
 
-// Approach #1
-// ToDo {Botch}
-// #[pre_upgrade]
-// fn pre_upgrade() {
-//     let stable_tera_state = STATE.with(|state| candid::encode_one(&state.borrow()).unwrap());
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap, HashSet};
+
+    use candid::{CandidType, Decode, Deserialize, Encode, Nat, Principal};
+    use ic_kit::MockContext;
+
+    use super::*;
+    use crate::common::types::{ExpiredMessage, NonceSet, OutgoingMessage, Role, SigningConfig};
+
+    /// Snapshot written by the first release, before any field was optional
+    #[derive(CandidType, Deserialize)]
+    struct StableTerabetiaStateV0Baseline {
+        messages: HashMap<String, u32>,
+        nonce: HashSet<Nat>,
+        messages_out: HashSet<OutgoingMessage>,
+        message_out_index: u64,
+        authorized: Vec<Principal>,
+    }
+
+    fn msg_hash_from(i: u64) -> String {
+        format!("{:064x}", i)
+    }
+
+    fn load_fixture<T: CandidType>(fixture: T) -> StableTerabetiaState {
+        let bytes = Encode!(&fixture).unwrap();
+        Decode!(&bytes, StableTerabetiaState).unwrap()
+    }
 
-//     storage::stable_save((stable_tera_state,)).expect("failed to save tera state");
-// }
+    #[test]
+    fn test_migrate_v0_baseline_snapshot() {
+        let controller = Principal::from_slice(&[1, 0x00]);
+        MockContext::new().with_caller(controller).inject();
 
-// #[post_upgrade]
-// fn post_upgrade() {
-//     let (stable_tera_state,): (Vec<u8>,) = storage::stable_restore().expect("failed to restore stable tera state");
+        let mut messages = HashMap::new();
+        messages.insert(msg_hash_from(1), 2);
+        let mut messages_out = HashSet::new();
+        messages_out.insert(OutgoingMessage::new(msg_hash_from(2), 1));
+        messages_out.insert(OutgoingMessage::new(msg_hash_from(3), 2));
 
-//     STATE.with(|state| {
-//         let data = candid::decode_one(&stable_tera_state).expect("failed to deserialize tera state");
+        let snapshot = load_fixture(StableTerabetiaStateV0Baseline {
+            messages,
+            nonce: [Nat::from(1), Nat::from(2), Nat::from(4)]
+                .iter()
+                .cloned()
+                .collect(),
+            messages_out,
+            message_out_index: 2,
+            authorized: vec![controller],
+        });
 
-//         *state.borrow_mut() = data
-//     });
-// }
+        STATE.with(|s| {
+            migrate(s, Some(snapshot));
+            s.rebuild_messages_out_tree();
+        });
+
+        STATE.with(|s| {
+            assert_eq!(s.get_schema_version(), STABLE_SCHEMA_VERSION);
+            assert_eq!(s.messages.borrow().get(&msg_hash_from(1)).unwrap().count, 2);
+            assert_eq!(s.nonce.borrow().get().watermark, Nat::from(2));
+            assert_eq!(s.outgoing_messages_count(), 2);
+            assert_eq!(*s.message_out_index.borrow().get(), 2);
+            assert!(s.get_messages_root().is_some());
+            for role in Role::ALL {
+                assert!(s.has_role(role).is_ok());
+            }
+        });
+    }
+
+    #[test]
+    fn test_migrate_v0_latest_snapshot() {
+        let admin = Principal::from_slice(&[1, 0x00]);
+        let from = Principal::from_slice(&[2, 0x00]);
+        let to = Principal::from_slice(&[3, 0x00]);
+        MockContext::new().with_caller(admin).inject();
+
+        let mut messages = HashMap::new();
+        messages.insert(msg_hash_from(1), 1);
+        let mut messages_stored_at = HashMap::new();
+        messages_stored_at.insert(msg_hash_from(1), 42);
+        let mut indexed_messages_out = BTreeMap::new();
+        indexed_messages_out.insert(7, OutgoingMessage::new(msg_hash_from(2), 7));
+        let namespace: NonceSet = [1, 2, 5].iter().map(|i| Nat::from(*i)).collect();
+        let mut nonce_namespaces = HashMap::new();
+        nonce_namespaces.insert((from, to), namespace);
+        let mut roles = HashMap::new();
+        roles.insert(admin, [Role::Admin].iter().cloned().collect());
+
+        let snapshot = load_fixture(StableTerabetiaState {
+            messages,
+            messages_stored_at: Some(messages_stored_at),
+            message_ttl: Some(1_000),
+            expired_messages: Some(vec![ExpiredMessage {
+                msg_hash: msg_hash_from(3),
+                count: 1,
+                stored_at: 1,
+                expired_at: 2,
+            }]),
+            nonce: HashSet::default(),
+            compact_nonce: Some(NonceSet::default()),
+            nonce_namespaces: Some(nonce_namespaces),
+            messages_out: HashSet::default(),
+            indexed_messages_out: Some(indexed_messages_out),
+            message_out_index: 7,
+            authorized: Vec::default(),
+            roles: Some(roles),
+            signing_config: Some(SigningConfig {
+                key_name: String::from("dfx_test_key"),
+                chain_id: 5,
+                l1_contract: String::from("0x60dc1a8a4d7b5b5a4e13a49e8e6d2ecc5c4e0b4d"),
+            }),
+        });
+
+        STATE.with(|s| migrate(s, Some(snapshot)));
+
+        STATE.with(|s| {
+            assert_eq!(s.get_schema_version(), STABLE_SCHEMA_VERSION);
+            assert_eq!(
+                s.messages
+                    .borrow()
+                    .get(&msg_hash_from(1))
+                    .unwrap()
+                    .stored_at,
+                42
+            );
+            assert_eq!(s.get_message_ttl(), 1_000);
+            assert_eq!(s.get_expired_messages(0, 10).len(), 1);
+            assert!(s.nonce_exists(&from, &to, &Nat::from(2)));
+            assert!(!s.nonce_exists(&from, &to, &Nat::from(3)));
+            assert_eq!(s.get_messages_page(0, 10)[0].index, 7);
+            assert!(s.has_role(Role::Admin).is_ok());
+            assert!(s.has_role(Role::Relayer).is_err());
+            assert_eq!(s.get_signing_config().unwrap().chain_id, 5);
+        });
+    }
+
+    #[test]
+    fn test_migrate_stable_structures_without_version() {
+        MockContext::new().inject();
+
+        STATE.with(|s| {
+            s.store_incoming_message(msg_hash_from(1));
+            migrate(s, None);
+
+            assert_eq!(s.get_schema_version(), STABLE_SCHEMA_VERSION);
+            assert!(s.message_exists(msg_hash_from(1)).is_ok());
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_migrate_newer_schema_version() {
+        STATE.with(|s| {
+            s.set_schema_version(STABLE_SCHEMA_VERSION + 1);
+            migrate(s, None);
+        });
+    }
+}
@@ -1,22 +1,29 @@
-use std::str::FromStr;
-
 use ic_kit::candid::candid_method;
 use ic_kit::{ic, macros::update};
 
 use crate::common::cap::insert_claimable_asset;
 use crate::common::tera::Tera;
 use crate::common::weth::Weth;
-use crate::proxy::{ToNat, STATE, TERA_ADDRESS, WETH_ADDRESS_ETH, WETH_ADDRESS_IC};
-use ic_cdk::export::candid::{Nat, Principal};
+use crate::proxy::STATE;
+use ic_cdk::export::candid::Nat;
 
-use crate::common::types::{ClaimableMessage, EthereumAddr, OperationFailure, TxError, TxFlag};
+use crate::common::types::{
+    ClaimableMessage, EthereumAddr, OperationFailure, PauseScope, TxError, TxFlag, WithdrawPayload,
+};
+use payload_codec::Payload;
 
 #[update(name = "burn")]
 #[candid_method(update, rename = "burn")]
 async fn burn(eth_addr: EthereumAddr, amount: Nat) -> Result<Nat, OperationFailure> {
+    if STATE.with(|s| s.is_paused(PauseScope::Burn)) {
+        return Err(OperationFailure::Paused(PauseScope::Burn));
+    }
+
     let caller = ic::caller();
     let self_id = ic::id();
-    let weth_ic_addr_pid = Principal::from_str(WETH_ADDRESS_IC).unwrap();
+
+    let config = STATE.with(|s| s.get_config());
+    let weth_ic_addr_pid = config.weth_ic;
 
     if (weth_ic_addr_pid.name().await).is_err() {
         return Err(OperationFailure::DIP20NotResponding(Some(TxError::Other(
@@ -36,6 +43,13 @@ async fn burn(eth_addr: EthereumAddr, amount: Nat) -> Result<Nat, OperationFailu
         ))));
     }
 
+    // counted as bridged once sent to L1, released if the burn stops before,
+    // withdrawing the left over balance counts it again
+    if let Err(limit) = STATE.with(|s| s.reserve_volume(caller, amount.clone())) {
+        STATE.with(|s| s.remove_user_flag(caller));
+        return Err(OperationFailure::OverLimit(limit));
+    }
+
     let transfer_from = weth_ic_addr_pid
         .transfer_from(caller, self_id, amount.clone())
         .await;
@@ -47,13 +61,25 @@ async fn burn(eth_addr: EthereumAddr, amount: Nat) -> Result<Nat, OperationFailu
             let burn = weth_ic_addr_pid.burn(amount.clone()).await;
 
             match burn {
+                Ok(burn_txn_id) if STATE.with(|s| s.holds_withdrawal(&amount)) => {
+                    STATE.with(|s| {
+                        s.remove_balance(caller, eth_addr, amount.clone());
+                        s.remove_user_flag(caller);
+                        s.hold_withdrawal(caller, eth_addr, amount);
+                    });
+
+                    // sent to L1 once approved
+                    return Ok(burn_txn_id);
+                }
                 Ok(burn_txn_id) => {
-                    let tera_id = Principal::from_text(TERA_ADDRESS).unwrap();
-                    let payload = [eth_addr.clone().to_nat(), amount.clone()].to_vec();
+                    let tera_id = config.tera;
+                    let payload = WithdrawPayload {
+                        eth_addr,
+                        amount: amount.clone(),
+                    }
+                    .encode_payload();
 
-                    let weth_addr_hex = WETH_ADDRESS_ETH.trim_start_matches("0x");
-                    let weth_eth_addr_pid =
-                        Principal::from_slice(&hex::decode(weth_addr_hex).unwrap());
+                    let weth_eth_addr_pid = config.weth_eth;
 
                     let send_message = tera_id.send_message(weth_eth_addr_pid, payload).await;
                     match send_message {
@@ -77,7 +103,10 @@ async fn burn(eth_addr: EthereumAddr, amount: Nat) -> Result<Nat, OperationFailu
                         }
                         // send_message to Tera error
                         Err(_) => {
-                            STATE.with(|s| s.remove_user_flag(caller));
+                            STATE.with(|s| {
+                                s.release_volume(caller, &amount);
+                                s.remove_user_flag(caller);
+                            });
                             return Err(OperationFailure::SendMessage(Some(TxError::Other(
                                 format!(
                                     "Sending message to L1 failed with caller {:?}!",
@@ -89,14 +118,20 @@ async fn burn(eth_addr: EthereumAddr, amount: Nat) -> Result<Nat, OperationFailu
                 }
                 // burn error
                 Err(error) => {
-                    STATE.with(|s| s.remove_user_flag(caller));
+                    STATE.with(|s| {
+                        s.release_volume(caller, &amount);
+                        s.remove_user_flag(caller);
+                    });
                     return Err(OperationFailure::Burn(Some(error)));
                 }
             };
         }
         // transfer_from error
         Err(error) => {
-            STATE.with(|s| s.remove_user_flag(caller));
+            STATE.with(|s| {
+                s.release_volume(caller, &amount);
+                s.remove_user_flag(caller);
+            });
             Err(OperationFailure::TransferFrom(Some(error)))
         }
     }
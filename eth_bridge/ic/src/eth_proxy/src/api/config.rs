@@ -0,0 +1,48 @@
+use candid::candid_method;
+use ic_cdk_macros::{query, update};
+
+use crate::api::admin::is_authorized;
+use crate::common::types::ProxyConfig;
+use crate::proxy::STATE;
+
+/// Point the proxy to other canisters, as set at init
+#[update(name = "set_config", guard = "is_authorized")]
+#[candid_method(update, rename = "set_config")]
+fn set_config(config: ProxyConfig) {
+    STATE.with(|s| s.set_config(config))
+}
+
+#[query(name = "get_config")]
+#[candid_method(query, rename = "get_config")]
+fn get_config() -> ProxyConfig {
+    STATE.with(|s| s.get_config())
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_kit::{mock_principals, MockContext};
+
+    use super::*;
+    use crate::api::init::init;
+
+    #[test]
+    fn test_init_config() {
+        MockContext::new().inject();
+
+        let config = ProxyConfig {
+            tera: mock_principals::alice(),
+            cap: mock_principals::bob(),
+            weth_ic: mock_principals::john(),
+            weth_eth: mock_principals::xtc(),
+        };
+        init(config.clone());
+        assert_eq!(get_config(), config);
+
+        let config = ProxyConfig {
+            tera: mock_principals::bob(),
+            ..config
+        };
+        set_config(config.clone());
+        assert_eq!(get_config(), config);
+    }
+}
@@ -4,23 +4,21 @@ use ic_kit::macros::update;
 
 use ic_cdk::export::candid::Nat;
 
-use crate::common::types::{EthereumAddr, Nonce, TxError, TxReceipt};
-use crate::proxy::WETH_ADDRESS_ETH;
+use crate::common::types::{EthereumAddr, Nonce, OperationFailure, TxError};
+use crate::proxy::STATE;
 
 #[update(name = "handle_message")]
 #[candid_method(update, rename = "handle_message")]
-async fn handler(eth_addr: EthereumAddr, nonce: Nonce, payload: Vec<Nat>) -> TxReceipt {
-    let eth_addr_hex = hex::encode(eth_addr);
-
-    if !(eth_addr_hex
-        == WETH_ADDRESS_ETH
-            .trim_start_matches("0x")
-            .to_ascii_lowercase())
-    {
-        return Err(TxError::Other(format!(
+async fn handler(
+    eth_addr: EthereumAddr,
+    nonce: Nonce,
+    payload: Vec<Nat>,
+) -> Result<Nat, OperationFailure> {
+    if eth_addr != STATE.with(|s| s.get_config().weth_eth) {
+        return Err(OperationFailure::Mint(Some(TxError::Other(format!(
             "Eth Contract Address is inccorrect: {}",
-            eth_addr_hex
-        )));
+            hex::encode(eth_addr)
+        )))));
     }
 
     mint(nonce, payload).await
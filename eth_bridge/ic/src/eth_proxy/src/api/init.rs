@@ -1,8 +1,13 @@
-use ic_kit::{ic, macros::*};
+use ic_kit::{candid::candid_method, ic, macros::*};
 
+use crate::common::types::ProxyConfig;
 use crate::proxy::STATE;
 
 #[init]
-pub fn init() {
-    STATE.with(|s| s.controllers.borrow_mut().push(ic::caller()));
+#[candid_method(init)]
+pub fn init(config: ProxyConfig) {
+    STATE.with(|s| {
+        s.controllers.borrow_mut().push(ic::caller());
+        s.set_config(config);
+    });
 }
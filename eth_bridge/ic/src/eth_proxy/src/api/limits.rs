@@ -0,0 +1,103 @@
+use candid::{candid_method, Nat};
+use ic_cdk_macros::{query, update};
+
+use crate::api::admin::is_authorized;
+use crate::api::mint::process_mint;
+use crate::common::types::{Limits, MessageHash, OperationFailure, PendingMint, TxError};
+use crate::proxy::STATE;
+
+/// Set the caps on mints and burns, a mint over them is queued for
+/// approval and a burn over them fails
+#[update(name = "set_limits", guard = "is_authorized")]
+#[candid_method(update, rename = "set_limits")]
+fn set_limits(limits: Limits) {
+    STATE.with(|s| s.set_limits(limits))
+}
+
+#[query(name = "get_limits")]
+#[candid_method(query, rename = "get_limits")]
+fn get_limits() -> Limits {
+    STATE.with(|s| s.get_limits())
+}
+
+/// Mints over the limits, oldest first
+#[query(name = "get_pending_mints")]
+#[candid_method(query, rename = "get_pending_mints")]
+fn get_pending_mints() -> Vec<(MessageHash, PendingMint)> {
+    STATE.with(|s| s.get_pending_mints())
+}
+
+/// Mint a queued deposit whatever the limits, it stays queued if the mint fails
+#[update(name = "approve_mint", guard = "is_authorized")]
+#[candid_method(update, rename = "approve_mint")]
+async fn approve_mint(msg_hash: MessageHash) -> Result<Nat, OperationFailure> {
+    let mint = STATE
+        .with(|s| s.get_pending_mint(&msg_hash))
+        .ok_or_else(|| {
+            OperationFailure::Mint(Some(TxError::Other(format!(
+                "Mint {} is not pending approval",
+                msg_hash
+            ))))
+        })?;
+
+    process_mint(mint.nonce, mint.payload, false).await
+}
+
+/// Drop a queued deposit, the message stays unconsumed on tera
+#[update(name = "reject_mint", guard = "is_authorized")]
+#[candid_method(update, rename = "reject_mint")]
+fn reject_mint(msg_hash: MessageHash) -> Result<PendingMint, String> {
+    STATE
+        .with(|s| s.remove_pending_mint(&msg_hash))
+        .ok_or_else(|| format!("Mint {} is not pending approval", msg_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_kit::candid::Nat;
+    use ic_kit::{mock_principals, MockContext};
+
+    use super::*;
+    use crate::common::types::Limit;
+
+    fn pending_mint() -> PendingMint {
+        PendingMint {
+            nonce: Nat::from(1),
+            payload: vec![],
+            to: mock_principals::bob(),
+            amount: Nat::from(1_000),
+            limit: Limit::PerTransaction,
+            queued_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_set_limits() {
+        MockContext::new().inject();
+
+        let limits = Limits {
+            max_per_transaction: Some(Nat::from(1_000)),
+            daily_volume: Some(Nat::from(10_000)),
+            daily_volume_per_principal: None,
+        };
+        set_limits(limits.clone());
+
+        assert_eq!(get_limits(), limits);
+    }
+
+    #[test]
+    fn test_reject_mint() {
+        MockContext::new().inject();
+
+        let msg_hash = String::from("a");
+        STATE.with(|s| s.queue_mint(msg_hash.clone(), pending_mint()));
+        assert_eq!(
+            get_pending_mints(),
+            vec![(msg_hash.clone(), pending_mint())]
+        );
+
+        assert_eq!(reject_mint(msg_hash.clone()), Ok(pending_mint()));
+        assert!(get_pending_mints().is_empty());
+        assert!(reject_mint(msg_hash).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use candid::candid_method;
+use ic_cdk_macros::query;
+use terabethia_common::{serve_metrics, HttpRequest, HttpResponse};
+
+use crate::common::types::MessageStatus;
+use crate::proxy::STATE;
+
+/// Metrics in the Prometheus text format, served on `/metrics`
+#[query(name = "http_request")]
+#[candid_method(query, rename = "http_request")]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    serve_metrics(&request, |encoder| {
+        STATE.with(|s| {
+            encoder
+                .gauge(
+                    "incoming_messages_pending",
+                    s.incoming_messages.borrow().len() as u64,
+                    "Incoming messages being consumed or not minted yet",
+                )
+                .gauge(
+                    "incoming_messages_not_minted",
+                    s.count_incoming_messages(MessageStatus::ConsumedNotMinted),
+                    "Incoming messages consumed on tera but not minted",
+                )
+                .gauge(
+                    "outgoing_messages_pending",
+                    s.messages_unclaimed
+                        .borrow()
+                        .values()
+                        .map(|messages| messages.len() as u64)
+                        .sum(),
+                    "Outgoing messages not claimed on L1 yet",
+                )
+                .gauge(
+                    "users_with_pending_balance",
+                    s.balances
+                        .borrow()
+                        .values()
+                        .filter(|balances| !balances.is_empty())
+                        .count() as u64,
+                    "Users with a balance left to withdraw",
+                )
+                .gauge(
+                    "flagged_users",
+                    s.user_actions.borrow().len() as u64,
+                    "Users with a burn or withdraw in flight",
+                )
+                .gauge(
+                    "pending_mints",
+                    s.pending_mints.borrow().len() as u64,
+                    "Mints over the limits waiting for approval",
+                )
+                .gauge(
+                    "pending_withdrawals",
+                    s.pending_withdrawals.borrow().len() as u64,
+                    "Burns held for approval",
+                )
+                .canister_metrics(&s.get_cycles());
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::{Nat, Principal};
+    use ic_kit::MockContext;
+
+    use super::*;
+    use crate::common::types::TxFlag;
+
+    fn request(url: &str) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    #[test]
+    fn test_http_request() {
+        MockContext::new().inject();
+
+        let user = Principal::from_slice(&[1, 0x00]);
+        STATE.with(|s| {
+            s.store_incoming_message("a".to_string());
+            s.update_incoming_message_status("b".to_string(), MessageStatus::ConsumedNotMinted);
+            s.add_balance(user, Principal::from_slice(&[2, 0x00]), Nat::from(100));
+            s.set_user_flag(user, TxFlag::Burning).unwrap();
+        });
+
+        let response = http_request(request("/metrics"));
+        let body = String::from_utf8(response.body).unwrap();
+
+        assert_eq!(response.status_code, 200);
+        assert!(body.contains("\nincoming_messages_pending 2\n"));
+        assert!(body.contains("\nincoming_messages_not_minted 1\n"));
+        assert!(body.contains("\nusers_with_pending_balance 1\n"));
+        assert!(body.contains("\nflagged_users 1\n"));
+    }
+}
@@ -1,32 +1,55 @@
-use std::str::FromStr;
-
 use ic_kit::candid::candid_method;
 use ic_kit::{ic, macros::update};
 
 use crate::common::tera::Tera;
 use crate::common::utils::{GweiToWei, Keccak256HashFn};
 use crate::common::weth::Weth;
-use crate::proxy::{
-    FromNat, ToBytes, ToNat, STATE, TERA_ADDRESS, WETH_ADDRESS_ETH, WETH_ADDRESS_IC,
-};
-use ic_cdk::export::candid::{Nat, Principal};
+use crate::proxy::{ToBytes, ToNat, STATE};
+use ic_cdk::export::candid::Nat;
+use payload_codec::Payload;
 
 use crate::common::types::{
-    IncomingMessageHashParams, Message, MessageStatus, Nonce, TxError, TxReceipt,
+    DepositPayload, IncomingMessageHashParams, Message, MessageStatus, Nonce, OperationFailure,
+    PauseScope, PendingMint, TeraMessageStatus, TxError, TxReceipt,
 };
 
 #[update(name = "mint")]
 #[candid_method(update, rename = "mint")]
-pub async fn mint(nonce: Nonce, payload: Vec<Nat>) -> TxReceipt {
-    let eth_addr_hex = WETH_ADDRESS_ETH.trim_start_matches("0x");
-    let weth_ic_addr_pid = Principal::from_str(WETH_ADDRESS_IC).unwrap();
-    let weth_eth_addr_pid = Principal::from_slice(&hex::decode(eth_addr_hex).unwrap());
-
-    let to = match Principal::from_nat(payload[0].clone()) {
-        Ok(canister) => canister,
-        Err(msg) => return Err(TxError::Other(msg)),
+pub async fn mint(nonce: Nonce, payload: Vec<Nat>) -> Result<Nat, OperationFailure> {
+    process_mint(nonce, payload, true).await
+}
+
+/// Mint a deposit, a new one over the limits is queued for approval
+/// unless `check_limits` is false
+pub async fn process_mint(
+    nonce: Nonce,
+    payload: Vec<Nat>,
+    check_limits: bool,
+) -> Result<Nat, OperationFailure> {
+    if STATE.with(|s| s.is_paused(PauseScope::Mint)) {
+        return Err(OperationFailure::Paused(PauseScope::Mint));
+    }
+
+    mint_deposit(nonce, payload, check_limits)
+        .await
+        .map_err(|error| OperationFailure::Mint(Some(error)))
+}
+
+/// Consume the deposit message from L1 and mint its amount to the receiver
+async fn mint_deposit(nonce: Nonce, payload: Vec<Nat>, check_limits: bool) -> TxReceipt {
+    let config = STATE.with(|s| s.get_config());
+    let weth_ic_addr_pid = config.weth_ic;
+    let weth_eth_addr_pid = config.weth_eth;
+
+    let deposit = match DepositPayload::decode_payload(&payload) {
+        Ok(deposit) => deposit,
+        Err(error) => return Err(TxError::Other(error.to_string())),
     };
 
+    // ETH_PROXY contract on Ethereum performs a division of the amount by / 1 gwei (1e9) in order to remove 0s.
+    // We add those 0s back to the amount to get the correct amount of ETH to be sent(minted) to the WETH contract.
+    let amount = deposit.amount.as_gwei_to_wei();
+
     if (weth_ic_addr_pid.name().await).is_err() {
         return Err(TxError::Other(format!(
             "Token {} canister is not responding!",
@@ -55,12 +78,49 @@ pub async fn mint(nonce: Nonce, payload: Vec<Nat>) -> TxReceipt {
             }
         }
     } else {
-        let tera_id = Principal::from_text(TERA_ADDRESS).unwrap();
+        let tera_id = config.tera;
+
+        // counted as bridged once consumed, released if consuming fails
+        if !check_limits {
+            STATE.with(|s| s.record_volume(deposit.to.0, amount.clone()));
+        } else if let Err(limit) = STATE.with(|s| s.reserve_volume(deposit.to.0, amount.clone())) {
+            // only a deposit tera holds and nobody consumed yet can be approved later
+            match tera_id.get_message_status(msg_hash.clone()).await {
+                Ok(TeraMessageStatus::Pending | TeraMessageStatus::Delivered) => (),
+                Ok(status) => {
+                    return Err(TxError::Other(format!(
+                        "Message {} is {:?} on tera, not queued for approval",
+                        msg_hash, status
+                    )))
+                }
+                Err(error) => return Err(error),
+            }
+
+            STATE.with(|s| {
+                s.queue_mint(
+                    msg_hash.clone(),
+                    PendingMint {
+                        nonce,
+                        payload,
+                        to: deposit.to.0,
+                        amount,
+                        limit,
+                        queued_at: ic::time(),
+                    },
+                )
+            });
+            return Err(TxError::Other(format!(
+                "Mint {} is over the {:?} limit, queued for approval",
+                msg_hash, limit
+            )));
+        }
+
         if tera_id
             .consume_message(weth_eth_addr_pid, nonce.to_nonce_bytes(), payload.clone())
             .await
             .is_err()
         {
+            STATE.with(|s| s.release_volume(deposit.to.0, &amount));
             return Err(TxError::Other(format!(
                 "Consuming message from L1 failed with message {:?}!",
                 msg_hash,
@@ -71,13 +131,12 @@ pub async fn mint(nonce: Nonce, payload: Vec<Nat>) -> TxReceipt {
 
     STATE.with(|s| s.update_incoming_message_status(msg_hash.clone(), MessageStatus::Consuming));
 
-    // ETH_PROXY contract on Ethereum performs a division of the amount by / 1 gwei (1e9) in order to remove 0s.
-    // We add those 0s back to the amount to get the correct amount of ETH to be sent(minted) to the WETH contract.
-    let amount_gweis = Nat::from(payload[1].0.clone());
-    let amount = amount_gweis.as_gwei_to_wei();
-
-    match weth_ic_addr_pid.mint(to, amount).await {
+    match weth_ic_addr_pid.mint(deposit.to.0, amount).await {
         Ok(txn_id) => {
+            STATE.with(|s| {
+                s.remove_pending_mint(&msg_hash);
+                s.remove_stuck_mint(&msg_hash);
+            });
             if STATE
                 .with(|s| s.remove_incoming_message(msg_hash.clone()))
                 .is_some()
@@ -91,7 +150,11 @@ pub async fn mint(nonce: Nonce, payload: Vec<Nat>) -> TxReceipt {
         }
         Err(error) => {
             STATE.with(|s| {
-                s.update_incoming_message_status(msg_hash.clone(), MessageStatus::ConsumedNotMinted)
+                s.update_incoming_message_status(
+                    msg_hash.clone(),
+                    MessageStatus::ConsumedNotMinted,
+                );
+                s.mint_failed(msg_hash.clone(), nonce, payload, ic::time(), error.clone());
             });
             Err(error)
         }
@@ -2,9 +2,17 @@ mod admin;
 mod burn;
 mod cap;
 mod claimable_assets;
+mod config;
+mod cycles;
 mod get_balance;
 mod handle_message;
 mod init;
+mod inspect_message;
+mod limits;
+mod metrics;
 mod mint;
+mod pause;
+mod stuck_mints;
 mod upgrade;
 mod withdraw;
+mod withdrawals;
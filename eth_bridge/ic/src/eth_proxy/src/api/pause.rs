@@ -0,0 +1,81 @@
+use candid::candid_method;
+use ic_cdk_macros::{query, update};
+
+use crate::api::admin::is_authorized;
+use crate::common::types::PauseScope;
+use crate::proxy::STATE;
+
+/// Scope pausing a method, None if it can not be paused
+pub fn paused_scope(method_name: &str) -> Option<PauseScope> {
+    match method_name {
+        "mint" | "handle_message" => Some(PauseScope::Mint),
+        "burn" => Some(PauseScope::Burn),
+        "withdraw" => Some(PauseScope::Withdraw),
+        _ => None,
+    }
+}
+
+#[update(name = "pause", guard = "is_authorized")]
+#[candid_method(update, rename = "pause")]
+fn pause(scope: PauseScope) {
+    STATE.with(|s| s.pause(scope))
+}
+
+#[update(name = "unpause", guard = "is_authorized")]
+#[candid_method(update, rename = "unpause")]
+fn unpause(scope: PauseScope) {
+    STATE.with(|s| s.unpause(scope))
+}
+
+/// Operations currently paused
+#[query(name = "get_pause_state")]
+#[candid_method(query, rename = "get_pause_state")]
+fn get_pause_state() -> Vec<PauseScope> {
+    STATE.with(|s| s.get_pause_state())
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_cdk::export::candid::Nat;
+    use ic_kit::{async_test, MockContext};
+
+    use super::*;
+    use crate::api::mint::mint;
+    use crate::common::types::OperationFailure;
+
+    #[test]
+    fn test_pause_and_unpause() {
+        MockContext::new().inject();
+
+        pause(PauseScope::Withdraw);
+        pause(PauseScope::Mint);
+        assert_eq!(
+            get_pause_state(),
+            vec![PauseScope::Mint, PauseScope::Withdraw]
+        );
+        assert!(STATE.with(|s| s.is_paused(PauseScope::Mint)));
+        assert!(!STATE.with(|s| s.is_paused(PauseScope::Burn)));
+
+        unpause(PauseScope::Mint);
+        assert_eq!(get_pause_state(), vec![PauseScope::Withdraw]);
+    }
+
+    #[async_test]
+    async fn test_paused_mint() {
+        MockContext::new().inject();
+        pause(PauseScope::Mint);
+
+        assert!(matches!(
+            mint(Nat::from(1), vec![]).await,
+            Err(OperationFailure::Paused(PauseScope::Mint))
+        ));
+    }
+
+    #[test]
+    fn test_paused_scope() {
+        assert_eq!(paused_scope("handle_message"), Some(PauseScope::Mint));
+        assert_eq!(paused_scope("burn"), Some(PauseScope::Burn));
+        assert_eq!(paused_scope("withdraw"), Some(PauseScope::Withdraw));
+        assert_eq!(paused_scope("get_balance"), None);
+    }
+}
@@ -0,0 +1,122 @@
+use candid::{candid_method, Nat};
+use ic_cdk_macros::{heartbeat, query, update};
+use ic_kit::ic::{spawn, time};
+
+use crate::api::admin::is_authorized;
+use crate::api::mint::process_mint;
+use crate::common::types::{MessageHash, OperationFailure, PauseScope, StuckMint, TxError};
+use crate::proxy::STATE;
+
+/// Stuck mints retried by a single heartbeat
+const MAX_RETRIES_PER_HEARTBEAT: usize = 10;
+
+/// Interval between two checks for due mints, ten seconds in nanoseconds
+const MINT_RETRY_CHECK_INTERVAL: u64 = 10 * 1_000_000_000;
+
+#[heartbeat]
+fn heartbeat() {
+    let now = time();
+    let next_check_at = STATE.with(|s| *s.next_mint_retry_check_at.borrow());
+    if now < next_check_at || STATE.with(|s| s.is_paused(PauseScope::Mint)) {
+        return;
+    }
+
+    STATE.with(|s| {
+        s.next_mint_retry_check_at
+            .replace(now + MINT_RETRY_CHECK_INTERVAL)
+    });
+    retry_due_mints(now);
+}
+
+/// Retry stuck mints whose backoff ran out
+fn retry_due_mints(now: u64) {
+    let due = STATE.with(|s| s.get_due_mints(now, MAX_RETRIES_PER_HEARTBEAT));
+
+    for (msg_hash, mint) in due {
+        // marked before spawning, so the next heartbeat does not pick it up while in flight
+        STATE.with(|s| s.start_mint_attempt(&msg_hash, now));
+
+        spawn(async move {
+            let _ = process_mint(mint.nonce, mint.payload, false).await;
+        });
+    }
+}
+
+/// Mints that failed after their message was consumed, least recently tried first
+#[query(name = "list_stuck_mints")]
+#[candid_method(query, rename = "list_stuck_mints")]
+fn list_stuck_mints() -> Vec<(MessageHash, StuckMint)> {
+    STATE.with(|s| s.get_stuck_mints())
+}
+
+/// Retry a stuck mint right away, also once the heartbeat gave up on it
+#[update(name = "force_retry", guard = "is_authorized")]
+#[candid_method(update, rename = "force_retry")]
+async fn force_retry(msg_hash: MessageHash) -> Result<Nat, OperationFailure> {
+    let mint = STATE.with(|s| s.get_stuck_mint(&msg_hash)).ok_or_else(|| {
+        OperationFailure::Mint(Some(TxError::Other(format!(
+            "Mint {} is not stuck",
+            msg_hash
+        ))))
+    })?;
+
+    STATE.with(|s| s.start_mint_attempt(&msg_hash, time()));
+    process_mint(mint.nonce, mint.payload, false).await
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_kit::candid::Nat;
+    use ic_kit::{mock_principals, MockContext, RawHandler, RejectionCode};
+
+    use super::*;
+    use crate::common::types::ProxyConfig;
+
+    fn msg_hash() -> MessageHash {
+        String::from("c9e23418a985892acc0fa031331080bfce112bdf841a3ae04a5181c6da1610b1")
+    }
+
+    #[test]
+    fn test_retry_due_mints() {
+        MockContext::new()
+            .with_handler(RawHandler::raw(Box::new(|_, _, _, _| {
+                Err((RejectionCode::CanisterError, "trapped".to_string()))
+            })))
+            .inject();
+
+        let now = time();
+        STATE.with(|s| {
+            s.set_config(ProxyConfig {
+                tera: mock_principals::alice(),
+                cap: mock_principals::bob(),
+                weth_ic: mock_principals::john(),
+                weth_eth: mock_principals::xtc(),
+            });
+            s.mint_failed(
+                msg_hash(),
+                Nat::from(1),
+                vec![Nat::from(2)],
+                now,
+                TxError::Other(String::from("mint failed")),
+            );
+        });
+        let next_attempt_at = STATE
+            .with(|s| s.get_stuck_mint(&msg_hash()))
+            .unwrap()
+            .next_attempt_at
+            .unwrap();
+
+        retry_due_mints(next_attempt_at - 1);
+        assert_eq!(
+            STATE.with(|s| s.get_stuck_mint(&msg_hash()).unwrap().last_attempt_at),
+            now
+        );
+
+        retry_due_mints(next_attempt_at);
+
+        let mint = STATE.with(|s| s.get_stuck_mint(&msg_hash())).unwrap();
+        assert_eq!(mint.last_attempt_at, next_attempt_at);
+        assert!(mint.next_attempt_at.unwrap() > next_attempt_at);
+        assert_eq!(list_stuck_mints(), vec![(msg_hash(), mint)]);
+    }
+}
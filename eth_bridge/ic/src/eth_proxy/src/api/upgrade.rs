@@ -1,28 +1,293 @@
+use std::collections::{HashMap, HashSet};
+
 use cap_sdk::{archive, from_archive, Archive};
+use ic_kit::candid::Nat;
 use ic_kit::ic;
 use ic_kit::macros::{post_upgrade, pre_upgrade};
 
-use crate::common::types::StableProxyState;
+use crate::common::types::{
+    Limits, ProxyConfig, StableProxyState, StableProxyStateV0, StableProxyStateV1,
+    VersionedStableProxyState, WithdrawalPolicy,
+};
 use crate::proxy::STATE;
 
+impl From<StableProxyStateV0> for StableProxyStateV1 {
+    fn from(state: StableProxyStateV0) -> Self {
+        let balances = state
+            .balances
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(caller, txs)| {
+                let mut balances = HashMap::new();
+                for (eth_addr, amount) in txs {
+                    *balances.entry(eth_addr).or_insert_with(Nat::default) += amount;
+                }
+                (caller, balances)
+            })
+            .collect();
+
+        StableProxyStateV1 {
+            incoming_messages: state.incoming_messages,
+            balances,
+            controllers: state.controllers,
+            messages_unclaimed: state.messages_unclaimed,
+            user_actions: state.user_actions.unwrap_or_default(),
+            low_cycles_threshold: None,
+            paused: HashSet::new(),
+            limits: Limits::default(),
+            bridged: Vec::new(),
+            pending_mints: HashMap::new(),
+            withdrawal_policy: WithdrawalPolicy::default(),
+            pending_withdrawals: HashMap::new(),
+            next_withdrawal_id: 0,
+            config: None,
+            stuck_mints: HashMap::new(),
+        }
+    }
+}
+
+impl VersionedStableProxyState {
+    /// Migrate the state one version at a time up to the current one
+    pub fn migrate(self) -> StableProxyState {
+        match self {
+            VersionedStableProxyState::V0(state) => {
+                VersionedStableProxyState::V1(state.into()).migrate()
+            }
+            VersionedStableProxyState::V1(state) => state,
+        }
+    }
+}
+
+/// Restore the state from stable memory, falling back to the snapshot
+/// written before it was wrapped in a versioned envelope
+fn restore() -> (VersionedStableProxyState, Option<Archive>) {
+    ic::stable_restore::<(VersionedStableProxyState, Option<Archive>)>()
+        .or_else(|_| {
+            ic::stable_restore::<(StableProxyStateV0, Option<Archive>)>()
+                .map(|(state, cap)| (VersionedStableProxyState::V0(state), cap))
+        })
+        .expect("failed to restore stable messsage state")
+}
+
 #[pre_upgrade]
 fn pre_upgrade() {
     let cap = archive();
 
-    let stable_proxy_state = STATE.with(|s| s.take_all());
+    let stable_proxy_state = VersionedStableProxyState::V1(STATE.with(|s| s.take_all()));
     ic::stable_store((stable_proxy_state, cap)).expect("failed to messsage state");
 }
 
+/// Takes the config when upgrading from a state without one, and replaces
+/// the kept one when given
 #[post_upgrade]
-fn post_upgrade() {
+fn post_upgrade(config: Option<ProxyConfig>) {
     STATE.with(|s| s.clear_all());
 
-    let (stable_proxy_state, cap): (StableProxyState, Option<Archive>) =
-        ic::stable_restore().expect("failed to restore stable messsage state");
+    let (stable_proxy_state, cap) = restore();
+
+    let mut state = stable_proxy_state.migrate();
+    if config.is_some() {
+        state.config = config;
+    }
+    if state.config.is_none() {
+        ic::trap("The proxy config is required to upgrade from a state without one");
+    }
 
-    STATE.with(|s| s.replace_all(stable_proxy_state));
+    STATE.with(|s| s.replace_all(state));
 
     if cap.is_some() {
         from_archive(cap.unwrap())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ic_kit::candid::{CandidType, Deserialize, Nat, Principal};
+    use ic_kit::{mock_principals, MockContext};
+
+    use super::*;
+    use crate::common::types::{
+        ClaimableMessage, EthereumAddr, MessageHash, MessageStatus, PauseScope, TxError, TxFlag,
+    };
+
+    /// Snapshot written before balances and user flags were tracked
+    #[derive(CandidType, Deserialize)]
+    struct StableProxyStateV0Initial {
+        incoming_messages: HashMap<MessageHash, MessageStatus>,
+        controllers: Vec<Principal>,
+        messages_unclaimed: HashMap<EthereumAddr, Vec<ClaimableMessage>>,
+    }
+
+    fn msg_hash() -> MessageHash {
+        String::from("c9e23418a985892acc0fa031331080bfce112bdf841a3ae04a5181c6da1610b1")
+    }
+
+    fn config() -> ProxyConfig {
+        ProxyConfig {
+            tera: mock_principals::alice(),
+            cap: mock_principals::bob(),
+            weth_ic: mock_principals::john(),
+            weth_eth: mock_principals::xtc(),
+        }
+    }
+
+    #[test]
+    fn test_post_upgrade_from_v0_initial() {
+        MockContext::new().inject();
+
+        let mut incoming_messages = HashMap::new();
+        incoming_messages.insert(msg_hash(), MessageStatus::ConsumedNotMinted);
+
+        let fixture = StableProxyStateV0Initial {
+            incoming_messages,
+            controllers: vec![mock_principals::alice()],
+            messages_unclaimed: HashMap::new(),
+        };
+        ic::stable_store((fixture, Option::<Archive>::None)).unwrap();
+
+        post_upgrade(Some(config()));
+
+        STATE.with(|s| {
+            assert_eq!(
+                s.get_message(&msg_hash()),
+                Some(MessageStatus::ConsumedNotMinted)
+            );
+            assert_eq!(
+                s.controllers.borrow().clone(),
+                vec![mock_principals::alice()]
+            );
+            assert!(s.balances.borrow().is_empty());
+            assert!(s.user_actions.borrow().is_empty());
+            assert_eq!(*s.low_cycles_threshold.borrow(), None);
+            assert!(s.get_pause_state().is_empty());
+            assert_eq!(s.get_limits(), Limits::default());
+            assert_eq!(s.get_withdrawal_policy(), WithdrawalPolicy::default());
+            assert!(s.get_pending_withdrawals().is_empty());
+            assert!(s.get_stuck_mints().is_empty());
+            assert_eq!(s.get_config(), config());
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_post_upgrade_from_v0_without_config() {
+        MockContext::new().inject();
+
+        let fixture = StableProxyStateV0::default();
+        ic::stable_store((fixture, Option::<Archive>::None)).unwrap();
+
+        post_upgrade(None);
+    }
+
+    #[test]
+    fn test_post_upgrade_from_v0_latest() {
+        MockContext::new().inject();
+
+        let mut balances = HashMap::new();
+        balances.insert(
+            mock_principals::bob(),
+            vec![
+                (mock_principals::john(), Nat::from(100)),
+                (mock_principals::alice(), Nat::from(50)),
+                (mock_principals::john(), Nat::from(300)),
+            ],
+        );
+        let mut user_actions = HashMap::new();
+        user_actions.insert(mock_principals::bob(), TxFlag::Burning);
+
+        let fixture = StableProxyStateV0 {
+            incoming_messages: HashMap::new(),
+            balances: Some(balances),
+            controllers: vec![mock_principals::alice()],
+            messages_unclaimed: HashMap::new(),
+            user_actions: Some(user_actions),
+        };
+        ic::stable_store((fixture, Option::<Archive>::None)).unwrap();
+
+        post_upgrade(Some(config()));
+
+        STATE.with(|s| {
+            assert_eq!(
+                s.get_balance(mock_principals::bob(), mock_principals::john()),
+                Some(Nat::from(400))
+            );
+            assert_eq!(
+                s.get_balance(mock_principals::bob(), mock_principals::alice()),
+                Some(Nat::from(50))
+            );
+            assert_eq!(
+                s.user_actions.borrow().get(&mock_principals::bob()),
+                Some(&TxFlag::Burning)
+            );
+        });
+    }
+
+    #[test]
+    fn test_post_upgrade_from_v1() {
+        MockContext::new().inject();
+
+        let limits = Limits {
+            max_per_transaction: Some(Nat::from(100)),
+            ..Default::default()
+        };
+        STATE.with(|s| {
+            s.store_incoming_message(msg_hash());
+            s.controllers.borrow_mut().push(mock_principals::alice());
+            s.set_low_cycles_threshold(5_000);
+            s.pause(PauseScope::Burn);
+            s.set_limits(limits.clone());
+            s.record_volume(mock_principals::bob(), Nat::from(50));
+            s.hold_withdrawal(
+                mock_principals::bob(),
+                mock_principals::john(),
+                Nat::from(50),
+            );
+            s.add_balance(
+                mock_principals::bob(),
+                mock_principals::john(),
+                Nat::from(100),
+            );
+            s.set_config(config());
+            s.mint_failed(
+                msg_hash(),
+                Nat::from(1),
+                vec![Nat::from(2)],
+                10,
+                TxError::Other(String::from("mint failed")),
+            );
+        });
+        let state = VersionedStableProxyState::V1(STATE.with(|s| s.take_all()));
+        ic::stable_store((state, Option::<Archive>::None)).unwrap();
+
+        post_upgrade(None);
+
+        STATE.with(|s| {
+            assert_eq!(s.get_message(&msg_hash()), Some(MessageStatus::Consuming));
+            assert_eq!(
+                s.controllers.borrow().clone(),
+                vec![mock_principals::alice()]
+            );
+            assert_eq!(*s.low_cycles_threshold.borrow(), Some(5_000));
+            assert_eq!(s.get_pause_state(), vec![PauseScope::Burn]);
+            assert_eq!(s.get_limits(), limits);
+            assert_eq!(s.bridged.borrow().len(), 1);
+            assert_eq!(s.get_pending_withdrawals().len(), 1);
+            assert_eq!(
+                s.hold_withdrawal(
+                    mock_principals::bob(),
+                    mock_principals::john(),
+                    Nat::from(1)
+                ),
+                1
+            );
+            assert_eq!(
+                s.get_balance(mock_principals::bob(), mock_principals::john()),
+                Some(Nat::from(100))
+            );
+            assert_eq!(s.get_config(), config());
+            let mint = s.get_stuck_mint(&msg_hash()).unwrap();
+            assert_eq!(mint.payload, vec![Nat::from(2)]);
+            assert_eq!(mint.attempts, 1);
+        });
+    }
+}
@@ -1,43 +1,42 @@
-use std::str::FromStr;
-
 use ic_kit::{
     candid::{candid_method, Nat},
     ic::{self},
     macros::update,
-    Principal,
 };
+use payload_codec::Payload;
 
 use crate::{
     common::{
         cap::insert_claimable_asset,
         tera::Tera,
-        types::{ClaimableMessage, EthereumAddr, OperationFailure, TxError, TxFlag},
+        types::{
+            ClaimableMessage, EthereumAddr, OperationFailure, PauseScope, TxError, TxFlag,
+            WithdrawPayload,
+        },
         weth::Weth,
     },
-    proxy::{ToNat, STATE, TERA_ADDRESS, WETH_ADDRESS_ETH, WETH_ADDRESS_IC},
+    proxy::STATE,
 };
 
 /// withdraw left over balance if burn/mint fails
-/// this will attempt to bridge the leftover balance
-/// todo withdraw specific balance
+/// this will attempt to bridge `amount` of the leftover balance,
+/// all of it if None, in a single message
 #[update(name = "withdraw")]
 #[candid_method(update, rename = "withdraw")]
-pub async fn withdraw(eth_addr: EthereumAddr, amount: Nat) -> Result<Nat, OperationFailure> {
-    let caller = ic::caller();
-    let weth_ic_addr_pid = Principal::from_str(WETH_ADDRESS_IC).unwrap();
-    let tera_id = Principal::from_text(TERA_ADDRESS).unwrap();
-
-    if (weth_ic_addr_pid.name().await).is_err() {
-        return Err(OperationFailure::DIP20NotResponding(Some(TxError::Other(
-            format!(
-                "Token {} canister is not responding!",
-                weth_ic_addr_pid.to_string(),
-            ),
-        ))));
+pub async fn withdraw(
+    eth_addr: EthereumAddr,
+    amount: Option<Nat>,
+) -> Result<Nat, OperationFailure> {
+    if STATE.with(|s| s.is_paused(PauseScope::Withdraw)) {
+        return Err(OperationFailure::Paused(PauseScope::Withdraw));
     }
 
-    let eth_addr_hex = WETH_ADDRESS_ETH.trim_start_matches("0x");
-    let weth_eth_addr_pid = Principal::from_slice(&hex::decode(eth_addr_hex).unwrap());
+    let caller = ic::caller();
+    let config = STATE.with(|s| s.get_config());
+    let weth_ic_addr_pid = config.weth_ic;
+    let tera_id = config.tera;
+
+    let weth_eth_addr_pid = config.weth_eth;
 
     let set_flag = STATE.with(|s| s.set_user_flag(caller, TxFlag::Withdrawing));
     if set_flag.is_err() {
@@ -48,14 +47,59 @@ pub async fn withdraw(eth_addr: EthereumAddr, amount: Nat) -> Result<Nat, Operat
         ))));
     }
 
-    let get_balance = STATE.with(|s| s.get_balance(caller, eth_addr, amount.clone()));
+    let get_balance = STATE.with(|s| s.get_balance(caller, eth_addr));
     if let Some(balance) = get_balance {
-        let payload = [eth_addr.clone().to_nat(), balance.clone()].to_vec();
+        let amount = amount.unwrap_or_else(|| balance.clone());
+        if amount == 0 || amount > balance {
+            STATE.with(|s| s.remove_user_flag(caller));
+            return Err(OperationFailure::UserHasNotBalanceToWithdraw(Some(
+                TxError::Other(format!(
+                    "Can not withdraw {} out of a balance of {}!",
+                    amount, balance
+                )),
+            )));
+        }
+
+        // counted as bridged once sent to L1, released if sending fails
+        if let Err(limit) = STATE.with(|s| s.reserve_volume(caller, amount.clone())) {
+            STATE.with(|s| s.remove_user_flag(caller));
+            return Err(OperationFailure::OverLimit(limit));
+        }
+
+        if STATE.with(|s| s.holds_withdrawal(&amount)) {
+            STATE.with(|s| {
+                s.remove_balance(caller, eth_addr, amount.clone());
+                s.remove_user_flag(caller);
+                s.hold_withdrawal(caller, eth_addr, amount.clone());
+            });
+
+            // sent to L1 once approved
+            return Ok(amount);
+        }
+
+        if (weth_ic_addr_pid.name().await).is_err() {
+            STATE.with(|s| {
+                s.release_volume(caller, &amount);
+                s.remove_user_flag(caller);
+            });
+            return Err(OperationFailure::DIP20NotResponding(Some(TxError::Other(
+                format!(
+                    "Token {} canister is not responding!",
+                    weth_ic_addr_pid.to_string(),
+                ),
+            ))));
+        }
+
+        let payload = WithdrawPayload {
+            eth_addr,
+            amount: amount.clone(),
+        }
+        .encode_payload();
 
         match tera_id.send_message(weth_eth_addr_pid, payload).await {
             Ok(outgoing_message) => {
                 STATE.with(|s| {
-                    s.remove_balance(caller, eth_addr, amount);
+                    s.remove_balance(caller, eth_addr, amount.clone());
                     s.remove_user_flag(caller);
                 });
 
@@ -65,12 +109,15 @@ pub async fn withdraw(eth_addr: EthereumAddr, amount: Nat) -> Result<Nat, Operat
                     msg_hash: outgoing_message.msg_hash.clone(),
                     msg_key: outgoing_message.msg_key.clone(),
                     token: weth_ic_addr_pid.clone(),
-                    amount: balance.clone(),
+                    amount: amount.clone(),
                 });
-                return Ok(balance);
+                return Ok(amount);
             }
             Err(_) => {
-                STATE.with(|s| s.remove_user_flag(caller));
+                STATE.with(|s| {
+                    s.release_volume(caller, &amount);
+                    s.remove_user_flag(caller);
+                });
                 return Err(OperationFailure::SendMessage(Some(TxError::Other(
                     format!("Sending message to L1 failed!"),
                 ))));
@@ -87,3 +134,52 @@ pub async fn withdraw(eth_addr: EthereumAddr, amount: Nat) -> Result<Nat, Operat
         )),
     )))
 }
+
+#[cfg(test)]
+mod tests {
+    use ic_kit::{async_test, mock_principals, MockContext};
+
+    use super::*;
+    use crate::common::types::{ProxyConfig, WithdrawalPolicy};
+
+    #[async_test]
+    async fn test_withdraw_held() {
+        MockContext::new()
+            .with_caller(mock_principals::alice())
+            .inject();
+        STATE.with(|s| {
+            s.set_config(ProxyConfig {
+                tera: mock_principals::bob(),
+                cap: mock_principals::bob(),
+                weth_ic: mock_principals::bob(),
+                weth_eth: mock_principals::bob(),
+            })
+        });
+
+        // left on the balance by a burn whose message was not sent
+        let eth_addr = mock_principals::john();
+        STATE.with(|s| {
+            s.set_withdrawal_policy(WithdrawalPolicy {
+                threshold: Some(Nat::from(100)),
+                delay: 60,
+            });
+            s.add_balance(mock_principals::alice(), eth_addr, Nat::from(500));
+        });
+
+        assert_eq!(
+            withdraw(eth_addr, Some(Nat::from(500))).await.ok(),
+            Some(Nat::from(500))
+        );
+
+        let pending = STATE.with(|s| s.get_pending_withdrawals());
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].from, mock_principals::alice());
+        assert_eq!(pending[0].amount, Nat::from(500));
+        assert!(STATE
+            .with(|s| s.get_balance(mock_principals::alice(), eth_addr))
+            .is_none());
+        assert!(STATE
+            .with(|s| s.get_user_flag(mock_principals::alice()))
+            .is_none());
+    }
+}
@@ -2,7 +2,9 @@ use async_trait::async_trait;
 use ic_cdk::call;
 use ic_cdk::export::candid::{Nat, Principal};
 
-use crate::common::types::{OutgoingMessage, TxError};
+use crate::common::types::{
+    OutgoingMessage, TeraError, TeraMessageStatus, TeraMessageStatusInfo, TxError,
+};
 
 use super::types::NonceBytes;
 
@@ -19,6 +21,7 @@ pub trait Tera {
         erc20_addr_pid: Principal,
         payload: Vec<Nat>,
     ) -> Result<OutgoingMessage, TxError>;
+    async fn get_message_status(&self, msg_hash: String) -> Result<TeraMessageStatus, TxError>;
 }
 
 #[async_trait]
@@ -29,7 +32,7 @@ impl Tera for Principal {
         nonce: NonceBytes,
         payload: Vec<Nat>,
     ) -> Result<bool, TxError> {
-        let consume: (Result<bool, String>,) = match call(
+        let consume: (Result<bool, TeraError>,) = match call(
             *self,
             "consume_message",
             (&erc20_addr_pid, &nonce, &payload),
@@ -47,7 +50,7 @@ impl Tera for Principal {
 
         match consume {
             (Ok(_),) => Ok(true),
-            (Err(error),) => Err(TxError::Other(format!("Consume Message: {:?}", error))),
+            (Err(error),) => Err(error.into()),
         }
     }
 
@@ -56,7 +59,7 @@ impl Tera for Principal {
         erc20_addr_pid: Principal,
         payload: Vec<Nat>,
     ) -> Result<OutgoingMessage, TxError> {
-        let send: (Result<OutgoingMessage, String>,) =
+        let send: (Result<OutgoingMessage, TeraError>,) =
             match call(*self, "send_message", (&erc20_addr_pid, &payload)).await {
                 Ok(res) => res,
                 Err((code, err)) => {
@@ -69,7 +72,22 @@ impl Tera for Principal {
 
         match send {
             (Ok(outgoing_message),) => Ok(outgoing_message),
-            (Err(error),) => Err(TxError::Other(format!("Send Message: {:?}", error))),
+            (Err(error),) => Err(error.into()),
         }
     }
+
+    async fn get_message_status(&self, msg_hash: String) -> Result<TeraMessageStatus, TxError> {
+        let (status,): (TeraMessageStatusInfo,) =
+            match call(*self, "get_message_status_by_hash", (&msg_hash,)).await {
+                Ok(res) => res,
+                Err((code, err)) => {
+                    return Err(TxError::Other(format!(
+                        "RejectionCode: {:?}\n{}",
+                        code, err
+                    )))
+                }
+            };
+
+        Ok(status.status)
+    }
 }
@@ -1,29 +1,40 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use ic_kit::candid::{CandidType, Deserialize, Nat, Principal};
-use serde::Serialize;
+use payload_codec::{impl_payload, SizedPrincipal};
+pub use terabethia_common::{IncomingMessageHashParams, Message, MessageHash, NonceBytes};
 
 pub type Nonce = Nat;
 
-pub type NonceBytes = [u8; 32];
-
 pub type TokendId = Principal;
 
-pub type MessageHash = String;
-
 pub type EthereumAddr = Principal;
 
 pub type MsgHashKey = [u8; 32];
 
 pub type TxReceipt = Result<Nat, TxError>;
 
+/// Payload of a deposit message from `EthProxy.sol`
+pub struct DepositPayload {
+    pub to: SizedPrincipal,
+    /// in gwei, the L1 contract divides the deposit by 1e9
+    pub amount: Nat,
+}
+
+impl_payload!(DepositPayload { to, amount });
+
+/// Payload of a withdrawal message to `EthProxy.sol`
+pub struct WithdrawPayload {
+    pub eth_addr: EthereumAddr,
+    pub amount: Nat,
+}
+
+impl_payload!(WithdrawPayload { eth_addr, amount });
+
 #[derive(CandidType, Deserialize)]
 pub struct WithdrawableBalance(pub Vec<(String, Nat)>);
 
-#[derive(Serialize, CandidType, Deserialize)]
-pub struct Message;
-
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct ClaimableMessage {
     pub owner: EthereumAddr,
@@ -55,43 +66,134 @@ pub enum TxFlag {
     Withdrawing,
 }
 
-#[derive(CandidType, Deserialize)]
-pub struct IncomingMessageHashParams {
-    pub from: Nat,
-    pub to: Nat,
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq, Hash)]
+pub struct OutgoingMessage {
+    pub msg_key: [u8; 32],
+    pub msg_hash: String,
+}
+
+/// Canisters and L1 contract the proxy talks to, set at init
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ProxyConfig {
+    pub tera: Principal,
+    pub cap: Principal,
+    /// WETH token canister
+    pub weth_ic: Principal,
+    /// `EthProxy.sol` contract
+    pub weth_eth: EthereumAddr,
+}
+
+/// Caps on the amounts bridged, in wei, None when unlimited
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Limits {
+    /// largest amount of a single mint or burn
+    pub max_per_transaction: Option<Nat>,
+    /// volume bridged over the last 24 hours
+    pub daily_volume: Option<Nat>,
+    /// volume bridged by a single principal over the last 24 hours
+    pub daily_volume_per_principal: Option<Nat>,
+}
+
+/// Limit a mint or a burn went over
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    PerTransaction,
+    DailyVolume,
+    DailyVolumePerPrincipal,
+}
+
+/// Amount of a mint or a burn, counted against the daily volume caps
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct BridgedAmount {
+    pub time: u64,
+    pub principal: Principal,
+    pub amount: Nat,
+}
+
+/// Mint over the limits, waiting for a controller to approve it
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingMint {
     pub nonce: Nonce,
     pub payload: Vec<Nat>,
+    pub to: Principal,
+    /// in wei
+    pub amount: Nat,
+    pub limit: Limit,
+    pub queued_at: u64,
 }
 
-#[derive(CandidType, Deserialize)]
-pub struct OutgoingMessageHashParams {
-    pub from: Nat,
-    pub to: Nat,
+/// Mint that failed after its message was consumed on tera, kept along
+/// with the message to retry it
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct StuckMint {
+    pub nonce: Nonce,
     pub payload: Vec<Nat>,
+    /// failed mint attempts
+    pub attempts: u32,
+    pub last_attempt_at: u64,
+    /// None once the retries gave up, only `force_retry` retries it then
+    pub next_attempt_at: Option<u64>,
+    pub last_error: Option<TxError>,
 }
 
-#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq, Hash)]
-pub struct OutgoingMessage {
-    pub msg_key: [u8; 32],
-    pub msg_hash: String,
+/// Burns above `threshold` are held for approval before they are sent to L1
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct WithdrawalPolicy {
+    /// in wei, None sends every burn right away
+    pub threshold: Option<Nat>,
+    /// nanoseconds a held burn waits before it can be approved
+    pub delay: u64,
+}
+
+/// Burn held for approval, its amount is out of the user balances meanwhile
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingWithdrawal {
+    pub id: u64,
+    pub from: Principal,
+    pub eth_addr: EthereumAddr,
+    pub amount: Nat,
+    pub queued_at: u64,
+    /// time from which it can be approved
+    pub release_at: u64,
 }
 
 #[derive(CandidType, Deserialize, Default)]
 pub struct ProxyState {
     /// store incoming messages against status locks
     pub incoming_messages: RefCell<HashMap<MessageHash, MessageStatus>>,
-    /// user balances
-    pub balances: RefCell<HashMap<Principal, Vec<(Principal, Nat)>>>,
+    /// user balances, summed by destination
+    pub balances: RefCell<HashMap<Principal, HashMap<EthereumAddr, Nat>>>,
     /// authorized principals
     pub controllers: RefCell<Vec<Principal>>,
     // store outgoing massages waiting to be claimed
     pub messages_unclaimed: RefCell<HashMap<EthereumAddr, Vec<ClaimableMessage>>>,
     // user flags
     pub user_actions: RefCell<HashMap<Principal, TxFlag>>,
+    /// cycle balance under which the canister is degraded, None until set
+    pub low_cycles_threshold: RefCell<Option<u64>>,
+    /// operations paused by a controller
+    pub paused: RefCell<HashSet<PauseScope>>,
+    /// caps on mints and burns
+    pub limits: RefCell<Limits>,
+    /// mints and burns of the last 24 hours
+    pub bridged: RefCell<Vec<BridgedAmount>>,
+    /// mints over the limits, waiting for approval
+    pub pending_mints: RefCell<HashMap<MessageHash, PendingMint>>,
+    /// burns held for approval
+    pub withdrawal_policy: RefCell<WithdrawalPolicy>,
+    pub pending_withdrawals: RefCell<HashMap<u64, PendingWithdrawal>>,
+    pub next_withdrawal_id: RefCell<u64>,
+    pub config: RefCell<Option<ProxyConfig>>,
+    /// mints that failed after their message was consumed
+    pub stuck_mints: RefCell<HashMap<MessageHash, StuckMint>>,
+    /// next time the heartbeat looks for stuck mints to retry, not kept across upgrades
+    pub next_mint_retry_check_at: RefCell<u64>,
 }
 
+/// Proxy state as written to stable memory before it was versioned,
+/// fields added along the way are optional
 #[derive(CandidType, Deserialize, Default)]
-pub struct StableProxyState {
+pub struct StableProxyStateV0 {
     /// store incoming messages against status locks
     pub incoming_messages: HashMap<MessageHash, MessageStatus>,
     /// user balances
@@ -104,13 +206,56 @@ pub struct StableProxyState {
     pub user_actions: Option<HashMap<Principal, TxFlag>>,
 }
 
+#[derive(CandidType, Deserialize, Default)]
+pub struct StableProxyStateV1 {
+    /// store incoming messages against status locks
+    pub incoming_messages: HashMap<MessageHash, MessageStatus>,
+    /// user balances, summed by destination
+    pub balances: HashMap<Principal, HashMap<EthereumAddr, Nat>>,
+    /// authorized principals
+    pub controllers: Vec<Principal>,
+    // store outgoing massages waiting to be claimed
+    pub messages_unclaimed: HashMap<EthereumAddr, Vec<ClaimableMessage>>,
+    // user flags
+    pub user_actions: HashMap<Principal, TxFlag>,
+    /// cycle balance under which the canister is degraded
+    pub low_cycles_threshold: Option<u64>,
+    /// operations paused by a controller
+    pub paused: HashSet<PauseScope>,
+    /// caps on mints and burns
+    pub limits: Limits,
+    /// mints and burns of the last 24 hours
+    pub bridged: Vec<BridgedAmount>,
+    /// mints over the limits, waiting for approval
+    pub pending_mints: HashMap<MessageHash, PendingMint>,
+    /// burns held for approval
+    pub withdrawal_policy: WithdrawalPolicy,
+    pub pending_withdrawals: HashMap<u64, PendingWithdrawal>,
+    pub next_withdrawal_id: u64,
+    /// canisters the proxy talks to, None in a state upgraded from V0
+    pub config: Option<ProxyConfig>,
+    /// mints that failed after their message was consumed
+    pub stuck_mints: HashMap<MessageHash, StuckMint>,
+}
+
+/// Current version of the proxy state in stable memory
+pub type StableProxyState = StableProxyStateV1;
+
+/// Envelope the proxy state is written to stable memory in. A new version
+/// adds a variant along with the migration from the previous one.
+#[derive(CandidType, Deserialize)]
+pub enum VersionedStableProxyState {
+    V0(StableProxyStateV0),
+    V1(StableProxyStateV1),
+}
+
 #[derive(CandidType, Deserialize, Clone, Copy)]
 pub enum TokenType {
     DIP20,
     DIP721,
 }
 
-#[derive(Deserialize, CandidType, Debug, PartialEq)]
+#[derive(Deserialize, CandidType, Clone, Debug, PartialEq)]
 pub enum TxError {
     InsufficientBalance,
     InsufficientAllowance,
@@ -123,6 +268,62 @@ pub enum TxError {
     Other(String),
 }
 
+/// Errors returned by the tera canister
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum TeraError {
+    NonceAlreadyConsumed,
+    MessageNotFound,
+    OutgoingQueueFull,
+    Unauthorized,
+    CallFailed { code: u8, msg: String },
+    InvalidPayload(String),
+    InvalidBatch(String),
+    SigningFailed(String),
+    LastAdmin,
+    Paused(TeraPauseScope),
+}
+
+/// Operation paused on the tera canister
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TeraPauseScope {
+    StoreMessage,
+    SendMessage,
+}
+
+/// Where an incoming message is at on the tera canister
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TeraMessageStatus {
+    Pending,
+    Delivered,
+    Consumed,
+    Unknown,
+}
+
+/// Status of an incoming message on the tera canister, only the fields the
+/// proxy reads
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TeraMessageStatusInfo {
+    pub status: TeraMessageStatus,
+}
+
+/// Operation a controller can pause, to stop the bridge without an upgrade
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PauseScope {
+    /// `mint`, along with `handle_message`
+    Mint,
+    Burn,
+    Withdraw,
+}
+
+impl From<TeraError> for TxError {
+    fn from(error: TeraError) -> Self {
+        match error {
+            TeraError::Unauthorized => TxError::Unauthorized,
+            error => TxError::Other(format!("Tera: {:?}", error)),
+        }
+    }
+}
+
 #[derive(CandidType, Deserialize, PartialEq)]
 pub enum OperationFailure {
     Burn(Option<TxError>),
@@ -132,4 +333,8 @@ pub enum OperationFailure {
     SendMessage(Option<TxError>),
     TokenCanisterIdNotFound(Option<TxError>),
     TransferFrom(Option<TxError>),
+    Paused(PauseScope),
+    Mint(Option<TxError>),
+    OverLimit(Limit),
+    PendingWithdrawal(Option<TxError>),
 }
@@ -11,6 +11,7 @@ fn main() {
     use ic_kit::candid;
     use ic_kit::candid::Nat;
     use ic_kit::Principal;
+    use terabethia_common::{CycleMetrics, HttpRequest, HttpResponse};
 
     candid::export_service!();
     std::print!("{}", __export_service());
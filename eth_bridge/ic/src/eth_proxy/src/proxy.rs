@@ -3,21 +3,41 @@ use std::collections::HashMap;
 use cap_sdk::{DetailsBuilder, IndefiniteEvent, IndefiniteEventBuilder};
 use ic_cdk::export::candid::{Nat, Principal};
 use ic_kit::ic;
+use terabethia_common::{CycleMetrics, DEFAULT_LOW_CYCLES_THRESHOLD};
+pub use terabethia_common::{ToBytes, ToNat};
 
 use crate::common::types::{
-    ClaimableMessage, EthereumAddr, MessageHash, MessageStatus, NonceBytes, ProxyState,
-    StableProxyState, TxFlag, WithdrawableBalance,
+    BridgedAmount, ClaimableMessage, EthereumAddr, Limit, Limits, MessageHash, MessageStatus,
+    Nonce, PauseScope, PendingMint, PendingWithdrawal, ProxyConfig, ProxyState, StableProxyState,
+    StuckMint, TxError, TxFlag, WithdrawableBalance, WithdrawalPolicy,
 };
 
-pub const CAP_ADDRESS: &str = "lj532-6iaaa-aaaah-qcc7a-cai";
-pub const TERA_ADDRESS: &str = "timop-6qaaa-aaaab-qaeea-cai";
-pub const WETH_ADDRESS_IC: &str = "tgodh-faaaa-aaaab-qaefa-cai";
-pub const WETH_ADDRESS_ETH: &str = "0x2e130e57021bb4dfb95eb4dd0dd8cfceb936148a";
+/// Window of the daily volume caps
+const DAY_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Attempts to mint a stuck deposit before the heartbeat gives up on it
+const MAX_MINT_ATTEMPTS: u32 = 10;
+
+/// Delay before the first retry of a stuck mint, one minute in nanoseconds,
+/// doubled by every further attempt
+const MINT_RETRY_BASE_DELAY: u64 = 60 * 1_000_000_000;
+
+/// Longest delay between two mint attempts, six hours in nanoseconds
+const MINT_RETRY_MAX_DELAY: u64 = 6 * 60 * 60 * 1_000_000_000;
 
 thread_local! {
     pub static STATE: ProxyState = ProxyState::default();
 }
 
+/// Delay before the next mint attempt, after `attempts` failed ones
+fn retry_delay(attempts: u32) -> u64 {
+    let exponent = attempts.saturating_sub(1).min(32);
+
+    MINT_RETRY_BASE_DELAY
+        .saturating_mul(1u64 << exponent)
+        .min(MINT_RETRY_MAX_DELAY)
+}
+
 impl ProxyState {
     pub fn store_incoming_message(&self, msg_hash: MessageHash) {
         self.incoming_messages
@@ -34,29 +54,24 @@ impl ProxyState {
         self.incoming_messages.borrow_mut().insert(msg_hash, status);
     }
 
+    pub fn count_incoming_messages(&self, status: MessageStatus) -> u64 {
+        self.incoming_messages
+            .borrow()
+            .values()
+            .filter(|message_status| **message_status == status)
+            .count() as u64
+    }
+
     pub fn remove_incoming_message(&self, msg_hash: MessageHash) -> Option<MessageStatus> {
         self.incoming_messages.borrow_mut().remove(&msg_hash)
     }
 
-    pub fn get_balance(
-        &self,
-        caller: Principal,
-        eth_address: EthereumAddr,
-        amount: Nat,
-    ) -> Option<Nat> {
-        if let Some(balance) = self
-            .balances
+    pub fn get_balance(&self, caller: Principal, eth_address: EthereumAddr) -> Option<Nat> {
+        self.balances
             .borrow()
             .get(&caller)
-            .unwrap_or(&Vec::new())
-            .into_iter()
-            .find(|m| m.0 == eth_address && m.1 == amount)
+            .and_then(|balances| balances.get(&eth_address))
             .cloned()
-        {
-            return Some(balance.1);
-        } else {
-            return None;
-        }
     }
 
     pub fn get_all_balances(&self, caller: Principal) -> Result<WithdrawableBalance, String> {
@@ -64,8 +79,8 @@ impl ProxyState {
 
         if let Some(balances) = token_balances {
             let mut destination = Vec::default();
-            for tx in balances {
-                destination.push((tx.0.to_string(), tx.1));
+            for (eth_address, amount) in balances {
+                destination.push((eth_address.to_string(), amount));
             }
             return Ok(WithdrawableBalance(destination));
         }
@@ -74,17 +89,18 @@ impl ProxyState {
 
     pub fn add_balance(&self, caller: Principal, to: Principal, amount: Nat) {
         let mut binding = self.balances.borrow_mut();
-        let caller_txs: Vec<(Principal, Nat)> = Vec::new();
-        let user_tx = binding.entry(caller).or_insert(caller_txs);
-        user_tx.push((to, amount))
+        *binding.entry(caller).or_default().entry(to).or_default() += amount;
     }
 
-    /// Panics if theres no balance for the caller or destination
+    /// Panics if the caller's balance for the destination is lower than `amount`
     pub fn remove_balance(&self, caller: Principal, to: Principal, amount: Nat) {
         let mut binding = self.balances.borrow_mut();
-        let txs = binding.get_mut(&caller).unwrap();
-        let index = txs.into_iter().position(|tx| tx.0 == to && tx.1 == amount);
-        txs.remove(index.unwrap());
+        let balances = binding.get_mut(&caller).unwrap();
+        let balance = balances.get_mut(&to).unwrap();
+        *balance -= amount;
+        if *balance == 0 {
+            balances.remove(&to);
+        }
     }
 
     pub fn get_claimable_messages(&self, eth_address: EthereumAddr) -> Vec<ClaimableMessage> {
@@ -102,7 +118,7 @@ impl ProxyState {
         eth_address: EthereumAddr,
         amount: Nat,
     ) -> Result<(), String> {
-        let eth_addr_pid = Principal::from_text(WETH_ADDRESS_IC).unwrap();
+        let eth_addr_pid = self.get_config().weth_ic;
 
         let mut map = self.messages_unclaimed.borrow_mut();
         let messages = map
@@ -159,13 +175,297 @@ impl ProxyState {
             .ok_or("Caller is not authorized".to_string())
     }
 
+    pub fn set_low_cycles_threshold(&self, threshold: u64) {
+        self.low_cycles_threshold.replace(Some(threshold));
+    }
+
+    pub fn get_cycles(&self) -> CycleMetrics {
+        let threshold = self
+            .low_cycles_threshold
+            .borrow()
+            .unwrap_or(DEFAULT_LOW_CYCLES_THRESHOLD);
+        CycleMetrics::new(ic::balance(), threshold)
+    }
+
+    pub fn pause(&self, scope: PauseScope) {
+        self.paused.borrow_mut().insert(scope);
+    }
+
+    pub fn unpause(&self, scope: PauseScope) {
+        self.paused.borrow_mut().remove(&scope);
+    }
+
+    pub fn is_paused(&self, scope: PauseScope) -> bool {
+        self.paused.borrow().contains(&scope)
+    }
+
+    pub fn get_pause_state(&self) -> Vec<PauseScope> {
+        let mut paused: Vec<PauseScope> = self.paused.borrow().iter().cloned().collect();
+        paused.sort();
+        paused
+    }
+
+    pub fn set_limits(&self, limits: Limits) {
+        self.limits.replace(limits);
+    }
+
+    pub fn get_limits(&self) -> Limits {
+        self.limits.borrow().clone()
+    }
+
+    /// Count `amount` bridged by `principal` against the daily volume,
+    /// unless it goes over one of the limits
+    pub fn reserve_volume(&self, principal: Principal, amount: Nat) -> Result<(), Limit> {
+        let limits = self.limits.borrow();
+        if limits
+            .max_per_transaction
+            .as_ref()
+            .is_some_and(|max| amount > *max)
+        {
+            return Err(Limit::PerTransaction);
+        }
+
+        let since = ic::time().saturating_sub(DAY_NANOS);
+        self.bridged
+            .borrow_mut()
+            .retain(|bridged| bridged.time > since);
+
+        let bridged = self.bridged.borrow();
+        let total = bridged.iter().fold(amount.clone(), |total, bridged| {
+            total + bridged.amount.clone()
+        });
+        if limits.daily_volume.as_ref().is_some_and(|cap| total > *cap) {
+            return Err(Limit::DailyVolume);
+        }
+
+        let principal_total = bridged
+            .iter()
+            .filter(|bridged| bridged.principal == principal)
+            .fold(amount.clone(), |total, bridged| {
+                total + bridged.amount.clone()
+            });
+        if limits
+            .daily_volume_per_principal
+            .as_ref()
+            .is_some_and(|cap| principal_total > *cap)
+        {
+            return Err(Limit::DailyVolumePerPrincipal);
+        }
+
+        drop(bridged);
+        self.record_volume(principal, amount);
+        Ok(())
+    }
+
+    /// Count `amount` bridged by `principal` against the daily volume,
+    /// whatever the limits
+    pub fn record_volume(&self, principal: Principal, amount: Nat) {
+        self.bridged.borrow_mut().push(BridgedAmount {
+            time: ic::time(),
+            principal,
+            amount,
+        });
+    }
+
+    /// Stop counting `amount` reserved for `principal`, once the mint or burn
+    /// it was reserved for failed
+    pub fn release_volume(&self, principal: Principal, amount: &Nat) {
+        let mut bridged = self.bridged.borrow_mut();
+        let reserved = bridged
+            .iter()
+            .rposition(|bridged| bridged.principal == principal && bridged.amount == *amount);
+
+        if let Some(index) = reserved {
+            bridged.remove(index);
+        }
+    }
+
+    pub fn queue_mint(&self, msg_hash: MessageHash, mint: PendingMint) {
+        self.pending_mints.borrow_mut().insert(msg_hash, mint);
+    }
+
+    pub fn get_pending_mint(&self, msg_hash: &MessageHash) -> Option<PendingMint> {
+        self.pending_mints.borrow().get(msg_hash).cloned()
+    }
+
+    pub fn remove_pending_mint(&self, msg_hash: &MessageHash) -> Option<PendingMint> {
+        self.pending_mints.borrow_mut().remove(msg_hash)
+    }
+
+    pub fn get_pending_mints(&self) -> Vec<(MessageHash, PendingMint)> {
+        let mut pending: Vec<(MessageHash, PendingMint)> = self
+            .pending_mints
+            .borrow()
+            .iter()
+            .map(|(msg_hash, mint)| (msg_hash.clone(), mint.clone()))
+            .collect();
+        pending.sort_by_key(|(_, mint)| mint.queued_at);
+        pending
+    }
+
+    pub fn set_withdrawal_policy(&self, policy: WithdrawalPolicy) {
+        self.withdrawal_policy.replace(policy);
+    }
+
+    pub fn get_withdrawal_policy(&self) -> WithdrawalPolicy {
+        self.withdrawal_policy.borrow().clone()
+    }
+
+    /// Whether a burn of `amount` is held for approval
+    pub fn holds_withdrawal(&self, amount: &Nat) -> bool {
+        self.withdrawal_policy
+            .borrow()
+            .threshold
+            .as_ref()
+            .is_some_and(|threshold| amount > threshold)
+    }
+
+    /// Hold a burn for approval, returns its id
+    pub fn hold_withdrawal(&self, from: Principal, eth_addr: EthereumAddr, amount: Nat) -> u64 {
+        let id = self.next_withdrawal_id.replace_with(|id| *id + 1);
+        let queued_at = ic::time();
+        let release_at = queued_at.saturating_add(self.withdrawal_policy.borrow().delay);
+
+        self.insert_pending_withdrawal(PendingWithdrawal {
+            id,
+            from,
+            eth_addr,
+            amount,
+            queued_at,
+            release_at,
+        });
+        id
+    }
+
+    pub fn insert_pending_withdrawal(&self, withdrawal: PendingWithdrawal) {
+        self.pending_withdrawals
+            .borrow_mut()
+            .insert(withdrawal.id, withdrawal);
+    }
+
+    pub fn get_pending_withdrawal(&self, id: u64) -> Option<PendingWithdrawal> {
+        self.pending_withdrawals.borrow().get(&id).cloned()
+    }
+
+    pub fn remove_pending_withdrawal(&self, id: u64) -> Option<PendingWithdrawal> {
+        self.pending_withdrawals.borrow_mut().remove(&id)
+    }
+
+    pub fn get_pending_withdrawals(&self) -> Vec<PendingWithdrawal> {
+        let mut pending: Vec<PendingWithdrawal> = self
+            .pending_withdrawals
+            .borrow()
+            .values()
+            .cloned()
+            .collect();
+        pending.sort_by_key(|withdrawal| withdrawal.id);
+        pending
+    }
+
+    /// Keep a mint that failed after its message was consumed, and schedule
+    /// its retry with an exponential backoff, or give up after `MAX_MINT_ATTEMPTS`
+    pub fn mint_failed(
+        &self,
+        msg_hash: MessageHash,
+        nonce: Nonce,
+        payload: Vec<Nat>,
+        now: u64,
+        error: TxError,
+    ) {
+        let mut stuck_mints = self.stuck_mints.borrow_mut();
+        let stuck_mint = stuck_mints.entry(msg_hash).or_insert(StuckMint {
+            nonce,
+            payload,
+            attempts: 0,
+            last_attempt_at: now,
+            next_attempt_at: None,
+            last_error: None,
+        });
+
+        stuck_mint.attempts += 1;
+        stuck_mint.last_attempt_at = now;
+        stuck_mint.last_error = Some(error);
+        stuck_mint.next_attempt_at = if stuck_mint.attempts >= MAX_MINT_ATTEMPTS {
+            None
+        } else {
+            Some(now + retry_delay(stuck_mint.attempts))
+        };
+    }
+
+    /// Mark a mint attempt as in flight, so that the heartbeat only retries
+    /// it again once the backoff of the attempt ran out
+    pub fn start_mint_attempt(&self, msg_hash: &MessageHash, now: u64) {
+        if let Some(stuck_mint) = self.stuck_mints.borrow_mut().get_mut(msg_hash) {
+            stuck_mint.last_attempt_at = now;
+            if stuck_mint.next_attempt_at.is_some() {
+                stuck_mint.next_attempt_at = Some(now + retry_delay(stuck_mint.attempts + 1));
+            }
+        }
+    }
+
+    pub fn get_stuck_mint(&self, msg_hash: &MessageHash) -> Option<StuckMint> {
+        self.stuck_mints.borrow().get(msg_hash).cloned()
+    }
+
+    pub fn remove_stuck_mint(&self, msg_hash: &MessageHash) -> Option<StuckMint> {
+        self.stuck_mints.borrow_mut().remove(msg_hash)
+    }
+
+    /// Stuck mints whose next attempt is due, oldest first
+    pub fn get_due_mints(&self, now: u64, limit: usize) -> Vec<(MessageHash, StuckMint)> {
+        let mut due: Vec<(MessageHash, StuckMint)> = self
+            .stuck_mints
+            .borrow()
+            .iter()
+            .filter(|(_, mint)| mint.next_attempt_at.is_some_and(|at| at <= now))
+            .map(|(msg_hash, mint)| (msg_hash.clone(), mint.clone()))
+            .collect();
+
+        due.sort_by_key(|(_, mint)| mint.next_attempt_at);
+        due.truncate(limit);
+        due
+    }
+
+    pub fn get_stuck_mints(&self) -> Vec<(MessageHash, StuckMint)> {
+        let mut stuck: Vec<(MessageHash, StuckMint)> = self
+            .stuck_mints
+            .borrow()
+            .iter()
+            .map(|(msg_hash, mint)| (msg_hash.clone(), mint.clone()))
+            .collect();
+        stuck.sort_by_key(|(_, mint)| mint.last_attempt_at);
+        stuck
+    }
+
+    pub fn set_config(&self, config: ProxyConfig) {
+        self.config.replace(Some(config));
+    }
+
+    /// Canisters the proxy talks to, set at init and kept across upgrades
+    pub fn get_config(&self) -> ProxyConfig {
+        self.config
+            .borrow()
+            .clone()
+            .expect("the proxy config is set at init")
+    }
+
     pub fn take_all(&self) -> StableProxyState {
         StableProxyState {
-            balances: Some(self.balances.take()),
+            balances: self.balances.take(),
             controllers: self.controllers.take(),
             incoming_messages: self.incoming_messages.take(),
             messages_unclaimed: self.messages_unclaimed.take(),
-            user_actions: Some(self.user_actions.take()),
+            user_actions: self.user_actions.take(),
+            low_cycles_threshold: self.low_cycles_threshold.take(),
+            paused: self.paused.take(),
+            limits: self.limits.take(),
+            bridged: self.bridged.take(),
+            pending_mints: self.pending_mints.take(),
+            withdrawal_policy: self.withdrawal_policy.take(),
+            pending_withdrawals: self.pending_withdrawals.take(),
+            next_withdrawal_id: self.next_withdrawal_id.take(),
+            config: self.config.take(),
+            stuck_mints: self.stuck_mints.take(),
         }
     }
 
@@ -175,76 +475,41 @@ impl ProxyState {
         self.incoming_messages.borrow_mut().clear();
         self.messages_unclaimed.borrow_mut().clear();
         self.user_actions.borrow_mut().clear();
+        self.low_cycles_threshold.replace(None);
+        self.paused.borrow_mut().clear();
+        self.limits.take();
+        self.bridged.borrow_mut().clear();
+        self.pending_mints.borrow_mut().clear();
+        self.withdrawal_policy.take();
+        self.pending_withdrawals.borrow_mut().clear();
+        self.next_withdrawal_id.replace(0);
+        self.config.take();
+        self.stuck_mints.borrow_mut().clear();
     }
 
     pub fn replace_all(&self, stable_message_state: StableProxyState) {
-        self.balances
-            .replace(stable_message_state.balances.unwrap());
+        self.balances.replace(stable_message_state.balances);
         self.controllers.replace(stable_message_state.controllers);
         self.incoming_messages
             .replace(stable_message_state.incoming_messages);
         self.messages_unclaimed
             .replace(stable_message_state.messages_unclaimed);
-        self.user_actions
-            .replace(stable_message_state.user_actions.unwrap_or_default());
-    }
-}
-
-pub trait ToNat {
-    fn to_nat(&self) -> Nat;
-}
-
-impl ToNat for Principal {
-    fn to_nat(&self) -> Nat {
-        Nat::from(num_bigint::BigUint::from_bytes_be(&self.as_slice()[..]))
-    }
-}
-
-impl ToNat for [u8; 32] {
-    fn to_nat(&self) -> Nat {
-        Nat::from(num_bigint::BigUint::from_bytes_be(&self.as_slice()[..]))
-    }
-}
-
-pub trait FromNat {
-    fn from_nat(input: Nat) -> Result<Principal, String>;
-}
-
-impl FromNat for Principal {
-    #[inline(always)]
-    fn from_nat(input: Nat) -> Result<Principal, String> {
-        let be_bytes = input.0.to_bytes_be();
-        let be_bytes_len = be_bytes.len();
-        if be_bytes_len > 29 {
-            return Err("Invalid Nat".to_string());
-        }
-        let padding_bytes = if be_bytes_len > 10 && be_bytes_len < 29 {
-            29 - be_bytes_len
-        } else if be_bytes_len < 10 {
-            10 - be_bytes_len
-        } else {
-            0
-        };
-        let mut p_slice = vec![0u8; padding_bytes];
-        p_slice.extend_from_slice(&be_bytes);
-        Ok(Principal::from_slice(&p_slice))
-    }
-}
-
-pub trait ToBytes {
-    fn to_nonce_bytes(&self) -> NonceBytes;
-}
-impl ToBytes for Nat {
-    fn to_nonce_bytes(&self) -> NonceBytes {
-        let be_bytes = self.0.to_bytes_be();
-        let be_bytes_len = be_bytes.len();
-        let padding_bytes = 32 - be_bytes_len;
-
-        let mut p_slice = vec![0u8; padding_bytes];
-        p_slice.extend_from_slice(&be_bytes);
-
-        let nonce_bytes: [u8; 32] = p_slice.as_slice()[..].try_into().unwrap();
-        nonce_bytes
+        self.user_actions.replace(stable_message_state.user_actions);
+        self.low_cycles_threshold
+            .replace(stable_message_state.low_cycles_threshold);
+        self.paused.replace(stable_message_state.paused);
+        self.limits.replace(stable_message_state.limits);
+        self.bridged.replace(stable_message_state.bridged);
+        self.pending_mints
+            .replace(stable_message_state.pending_mints);
+        self.withdrawal_policy
+            .replace(stable_message_state.withdrawal_policy);
+        self.pending_withdrawals
+            .replace(stable_message_state.pending_withdrawals);
+        self.next_withdrawal_id
+            .replace(stable_message_state.next_withdrawal_id);
+        self.config.replace(stable_message_state.config);
+        self.stuck_mints.replace(stable_message_state.stuck_mints);
     }
 }
 
@@ -308,7 +573,7 @@ mod tests {
     };
 
     use super::*;
-    use ic_kit::mock_principals;
+    use ic_kit::{mock_principals, MockContext};
 
     #[test]
     fn test_message_status_new_message() {
@@ -383,8 +648,7 @@ mod tests {
 
         // add amount_1 for eth_address_1
         STATE.with(|s| s.add_balance(caller.clone(), eth_address_1.clone(), amount_1.clone()));
-        let current_balance_1 =
-            STATE.with(|s| s.get_balance(caller, eth_address_1.clone(), amount_1.clone()));
+        let current_balance_1 = STATE.with(|s| s.get_balance(caller, eth_address_1.clone()));
         assert_eq!(current_balance_1.unwrap(), amount_1.clone());
 
         // add amount_2 for eth_address_2
@@ -393,15 +657,121 @@ mod tests {
             STATE.with(|s| s.balances.borrow().get(&caller).unwrap().len());
         assert_eq!(withdraw_address_count, 2);
 
-        // add amount_3 for eth_address_1 (100, 300)
+        // add amount_3 for eth_address_1 (100 + 300)
         STATE.with(|s| s.add_balance(caller, eth_address_1.clone(), amount_3.clone()));
-        let current_balance_1 =
-            STATE.with(|s| s.get_balance(caller, eth_address_1.clone(), amount_3.clone()));
-        assert_eq!(current_balance_1.unwrap(), amount_3);
+        let current_balance_1 = STATE.with(|s| s.get_balance(caller, eth_address_1.clone()));
+        assert_eq!(current_balance_1.unwrap(), amount_1 + amount_3);
 
         let withdraw_address_count =
             STATE.with(|s| s.balances.borrow().get(&caller).unwrap().len());
-        assert_eq!(withdraw_address_count, 3);
+        assert_eq!(withdraw_address_count, 2);
+    }
+
+    #[test]
+    fn test_reserve_volume() {
+        MockContext::new().inject();
+
+        let alice = mock_principals::alice();
+        let bob = mock_principals::bob();
+        STATE.with(|s| {
+            s.set_limits(Limits {
+                max_per_transaction: Some(Nat::from(100)),
+                daily_volume: Some(Nat::from(250)),
+                daily_volume_per_principal: Some(Nat::from(150)),
+            });
+
+            assert_eq!(
+                s.reserve_volume(alice, Nat::from(101)),
+                Err(Limit::PerTransaction)
+            );
+            assert_eq!(s.reserve_volume(alice, Nat::from(100)), Ok(()));
+            assert_eq!(
+                s.reserve_volume(alice, Nat::from(100)),
+                Err(Limit::DailyVolumePerPrincipal)
+            );
+            assert_eq!(s.reserve_volume(bob, Nat::from(100)), Ok(()));
+            assert_eq!(
+                s.reserve_volume(bob, Nat::from(100)),
+                Err(Limit::DailyVolume)
+            );
+            assert_eq!(s.bridged.borrow().len(), 2);
+        });
+    }
+
+    #[test]
+    fn test_reserve_volume_after_a_day() {
+        MockContext::new().inject();
+
+        let alice = mock_principals::alice();
+        STATE.with(|s| {
+            s.set_limits(Limits {
+                daily_volume: Some(Nat::from(100)),
+                ..Default::default()
+            });
+            s.bridged.borrow_mut().push(BridgedAmount {
+                time: ic::time() - DAY_NANOS - 1,
+                principal: alice,
+                amount: Nat::from(100),
+            });
+
+            assert_eq!(s.reserve_volume(alice, Nat::from(100)), Ok(()));
+            assert_eq!(s.bridged.borrow().len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_release_volume() {
+        MockContext::new().inject();
+
+        let alice = mock_principals::alice();
+        let bob = mock_principals::bob();
+        STATE.with(|s| {
+            s.set_limits(Limits {
+                daily_volume: Some(Nat::from(100)),
+                ..Default::default()
+            });
+
+            assert_eq!(s.reserve_volume(alice, Nat::from(100)), Ok(()));
+            s.release_volume(bob, &Nat::from(100));
+            assert_eq!(
+                s.reserve_volume(bob, Nat::from(100)),
+                Err(Limit::DailyVolume)
+            );
+
+            s.release_volume(alice, &Nat::from(100));
+            assert_eq!(s.reserve_volume(bob, Nat::from(100)), Ok(()));
+            assert_eq!(s.bridged.borrow().len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_mint_failed_backoff() {
+        let msg_hash = String::from("stuck");
+        let error = TxError::Other(String::from("mint failed"));
+
+        STATE.with(|s| {
+            s.mint_failed(msg_hash.clone(), Nat::from(1), vec![], 0, error.clone());
+            assert_eq!(
+                s.get_stuck_mint(&msg_hash).unwrap().next_attempt_at,
+                Some(MINT_RETRY_BASE_DELAY)
+            );
+            assert!(s.get_due_mints(MINT_RETRY_BASE_DELAY - 1, 10).is_empty());
+            assert_eq!(s.get_due_mints(MINT_RETRY_BASE_DELAY, 10).len(), 1);
+
+            s.mint_failed(msg_hash.clone(), Nat::from(1), vec![], 0, error.clone());
+            assert_eq!(
+                s.get_stuck_mint(&msg_hash).unwrap().next_attempt_at,
+                Some(2 * MINT_RETRY_BASE_DELAY)
+            );
+
+            for _ in 2..MAX_MINT_ATTEMPTS {
+                s.mint_failed(msg_hash.clone(), Nat::from(1), vec![], 0, error.clone());
+            }
+            let mint = s.get_stuck_mint(&msg_hash).unwrap();
+            assert_eq!(mint.attempts, MAX_MINT_ATTEMPTS);
+            assert_eq!(mint.next_attempt_at, None);
+            assert!(s.get_due_mints(u64::MAX, 10).is_empty());
+        });
     }
 
     #[test]
@@ -415,7 +785,7 @@ mod tests {
 
         /*
         caller: {
-            eth_address_1= 100 ; 300 ; 100
+            eth_address_1= 100 + 300 + 100
             eth_address_2= 200
         }
         */
@@ -428,21 +798,12 @@ mod tests {
 
         let all_balances = balances.unwrap().0;
 
-        let w = (eth_address_1.clone().to_string(), Nat::from(300));
-        let y = (eth_address_1.clone().to_string(), Nat::from(100));
+        let w = (eth_address_1.clone().to_string(), Nat::from(500));
         let x = (eth_address_2.clone().to_string(), Nat::from(200));
 
-        assert!(
-            all_balances
-                .clone()
-                .into_iter()
-                .filter(|m| m.0 == y.0 && m.1 == y.1)
-                .count()
-                == 2
-        );
+        assert_eq!(all_balances.len(), 2);
         assert!(all_balances.clone().into_iter().any(|e| e == w));
         assert!(all_balances.clone().into_iter().any(|e| e == x));
-        assert!(all_balances.clone().into_iter().any(|e| e == y));
     }
 
     #[test]
@@ -475,12 +836,10 @@ mod tests {
 
         assert!(STATE.with(|s| s.balances.borrow().clone().into_iter().count() == 1));
 
-        assert!(STATE.with(|s| s
-            .get_balance(caller, eth_address_1.clone(), amount_1.clone())
-            .is_none()));
+        assert!(STATE.with(|s| s.get_balance(caller, eth_address_1.clone()).is_none()));
 
         let current_balance = STATE
-            .with(|s| s.get_balance(caller, eth_address_2.clone(), amount_2.clone()))
+            .with(|s| s.get_balance(caller, eth_address_2.clone()))
             .unwrap();
 
         assert_eq!(current_balance, amount_2.clone());
@@ -501,6 +860,20 @@ mod tests {
         assert!(balances_final.0.len() == 0);
     }
 
+    #[test]
+    fn test_remove_part_of_balance() {
+        let caller = mock_principals::bob();
+        let eth_address = mock_principals::alice();
+
+        STATE.with(|s| {
+            s.add_balance(caller, eth_address, Nat::from(100));
+            s.add_balance(caller, eth_address, Nat::from(300));
+            s.remove_balance(caller, eth_address, Nat::from(150));
+
+            assert_eq!(s.get_balance(caller, eth_address), Some(Nat::from(250)));
+        });
+    }
+
     #[test]
     fn test_store_incoming_message() {
         let nonce = Nat::from(4_u32);
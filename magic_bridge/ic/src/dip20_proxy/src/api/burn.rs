@@ -6,10 +6,12 @@ use crate::common::dip20::Dip20;
 use crate::common::magic::Magic;
 use crate::common::tera::Tera;
 use crate::common::types::{
-    ClaimableMessage, EthereumAddr, OperationFailure, OutgoingMessage, TokenId, TxError, TxFlag,
+    ClaimableMessage, EthereumAddr, OperationFailure, OutgoingMessage, PauseScope, TokenId,
+    TxError, TxFlag, WithdrawPayload,
 };
-use crate::proxy::{ToNat, ERC20_ADDRESS_ETH, MAGIC_ADDRESS_IC, STATE, TERA_ADDRESS};
+use crate::proxy::STATE;
 use ic_cdk::export::candid::{Nat, Principal};
+use payload_codec::Payload;
 
 #[update(name = "burn")]
 #[candid_method(update, rename = "burn")]
@@ -18,10 +20,15 @@ async fn burn(
     eth_addr: EthereumAddr,
     amount: Nat,
 ) -> Result<Nat, OperationFailure> {
+    if STATE.with(|s| s.is_paused(PauseScope::Burn)) {
+        return Err(OperationFailure::Paused(PauseScope::Burn));
+    }
+
     let caller = ic::caller();
     let self_id = ic::id();
 
-    let magic_bridge = Principal::from_text(MAGIC_ADDRESS_IC).unwrap();
+    let config = STATE.with(|s| s.get_config());
+    let magic_bridge = config.magic_ic;
 
     let token_id: Principal = match magic_bridge
         .get_canister(eth_contract_as_principal.clone())
@@ -39,8 +46,7 @@ async fn burn(
     }
     let token_name_str = token_name.unwrap();
 
-    let erc20_addr_hex = ERC20_ADDRESS_ETH.trim_start_matches("0x");
-    let erc20_addr_pid = Principal::from_slice(&hex::decode(erc20_addr_hex).unwrap());
+    let erc20_addr_pid = config.erc20_eth;
 
     // One user cannot make multiple tx at the same time for the same token
     let set_flag = STATE.with(|s| s.set_user_flag(caller, token_id, TxFlag::Burning));
@@ -65,15 +71,39 @@ async fn burn(
             let burn = token_id.burn(amount.clone()).await;
 
             match burn {
+                Ok(burn_txn_id)
+                    if STATE.with(|s| s.holds_withdrawal(eth_contract_as_principal, &amount)) =>
+                {
+                    STATE.with(|s| {
+                        s.remove_balance(
+                            caller,
+                            eth_addr,
+                            eth_contract_as_principal,
+                            amount.clone(),
+                        );
+                        s.remove_user_flag(caller, token_id);
+                        s.hold_withdrawal(
+                            caller,
+                            eth_addr,
+                            eth_contract_as_principal,
+                            token_id,
+                            token_name_str,
+                            amount,
+                        );
+                    });
+
+                    // sent to L1 once approved
+                    return Ok(burn_txn_id);
+                }
                 Ok(burn_txn_id) => {
-                    let tera_id = Principal::from_text(TERA_ADDRESS).unwrap();
-
-                    let payload = [
-                        eth_contract_as_principal.to_nat(),
-                        eth_addr.clone().to_nat(),
-                        amount.clone(),
-                    ]
-                    .to_vec();
+                    let tera_id = config.tera;
+
+                    let payload = WithdrawPayload {
+                        token: eth_contract_as_principal,
+                        eth_addr,
+                        amount: amount.clone(),
+                    }
+                    .encode_payload();
 
                     let send_message: Result<OutgoingMessage, TxError> =
                         tera_id.send_message(erc20_addr_pid, payload.clone()).await;
@@ -0,0 +1,16 @@
+use ic_cdk::api::call::{accept_message, method_name};
+use ic_cdk_macros::inspect_message;
+
+use super::pause::paused_scope;
+use crate::proxy::STATE;
+
+/// Reject ingress calls to paused operations before they reach the canister
+#[inspect_message]
+fn inspect_message() {
+    let paused =
+        paused_scope(&method_name()).is_some_and(|scope| STATE.with(|s| s.is_paused(scope)));
+
+    if !paused {
+        accept_message()
+    }
+}
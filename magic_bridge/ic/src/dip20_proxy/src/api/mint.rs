@@ -2,18 +2,36 @@ use ic_kit::candid::candid_method;
 use ic_kit::{ic, macros::update};
 
 use crate::common::dip20::Dip20;
+use crate::common::magic::Magic;
 use crate::common::tera::Tera;
 use crate::common::utils::Keccak256HashFn;
-use crate::proxy::{FromNat, ToBytes, ToNat, ERC20_ADDRESS_ETH, STATE, TERA_ADDRESS};
-use ic_cdk::export::candid::{Nat, Principal};
+use crate::proxy::{ToBytes, ToNat, STATE};
+use ic_cdk::export::candid::Nat;
+use payload_codec::Payload;
 
 use crate::common::types::{
-    IncomingMessageHashParams, Message, MessageStatus, Nonce, TokenId, TxError, TxReceipt,
+    DepositPayload, IncomingMessageHashParams, Message, MessageStatus, Nonce, OperationFailure,
+    PauseScope, TokenId, TxError, TxReceipt,
 };
 
 #[update(name = "mint")]
 #[candid_method(update, rename = "mint")]
-pub async fn mint(token_id: TokenId, nonce: Nonce, payload: Vec<Nat>) -> TxReceipt {
+pub async fn mint(
+    token_id: TokenId,
+    nonce: Nonce,
+    payload: Vec<Nat>,
+) -> Result<Nat, OperationFailure> {
+    if STATE.with(|s| s.is_paused(PauseScope::Mint)) {
+        return Err(OperationFailure::Paused(PauseScope::Mint));
+    }
+
+    mint_deposit(token_id, nonce, payload)
+        .await
+        .map_err(|error| OperationFailure::Mint(Some(error)))
+}
+
+/// Consume the deposit message from L1 and mint its amount on the token canister
+async fn mint_deposit(token_id: TokenId, nonce: Nonce, payload: Vec<Nat>) -> TxReceipt {
     if (token_id.name().await).is_err() {
         return Err(TxError::Other(format!(
             "Token {} canister is not responding!",
@@ -22,14 +40,26 @@ pub async fn mint(token_id: TokenId, nonce: Nonce, payload: Vec<Nat>) -> TxRecei
     }
 
     let self_id = ic::id();
-    let erc20_addr_hex = ERC20_ADDRESS_ETH.trim_start_matches("0x");
-    let erc20_addr_pid = Principal::from_slice(&hex::decode(erc20_addr_hex).unwrap());
+    let config = STATE.with(|s| s.get_config());
+    let erc20_addr_pid = config.erc20_eth;
 
-    let to = match Principal::from_nat(payload[0].clone()) {
-        Ok(canister) => canister,
-        Err(msg) => return Err(TxError::Other(msg)),
+    let deposit = match DepositPayload::decode_payload(&payload) {
+        Ok(deposit) => deposit,
+        Err(error) => return Err(TxError::Other(error.to_string())),
     };
 
+    // the deposit names its token, only the canister magic bridge maps it to mints it
+    match config.magic_ic.get_canister(deposit.token).await {
+        Ok(canister_id) if canister_id == token_id => (),
+        Ok(canister_id) => {
+            return Err(TxError::Other(format!(
+                "Token {} is minted by {}, not {}!",
+                deposit.token, canister_id, token_id
+            )))
+        }
+        Err(error) => return Err(error),
+    }
+
     let msg_hash = Message.calculate_hash(IncomingMessageHashParams {
         from: erc20_addr_pid.to_nat(),
         to: self_id.to_nat(),
@@ -50,7 +80,7 @@ pub async fn mint(token_id: TokenId, nonce: Nonce, payload: Vec<Nat>) -> TxRecei
             }
         }
     } else {
-        let tera_id = Principal::from_text(TERA_ADDRESS).unwrap();
+        let tera_id = config.tera;
         if tera_id
             .consume_message(erc20_addr_pid, nonce.to_nonce_bytes(), payload.clone())
             .await
@@ -66,10 +96,9 @@ pub async fn mint(token_id: TokenId, nonce: Nonce, payload: Vec<Nat>) -> TxRecei
 
     STATE.with(|s| s.update_incoming_message_status(msg_hash.clone(), MessageStatus::Consuming));
 
-    let amount = Nat::from(payload[2].0.clone());
-
-    match token_id.mint(to, amount).await {
+    match token_id.mint(deposit.to.0, deposit.amount).await {
         Ok(txn_id) => {
+            STATE.with(|s| s.remove_stuck_mint(&msg_hash));
             if STATE
                 .with(|s| s.remove_incoming_message(msg_hash.clone()))
                 .is_some()
@@ -83,7 +112,18 @@ pub async fn mint(token_id: TokenId, nonce: Nonce, payload: Vec<Nat>) -> TxRecei
         }
         Err(error) => {
             STATE.with(|s| {
-                s.update_incoming_message_status(msg_hash.clone(), MessageStatus::ConsumedNotMinted)
+                s.update_incoming_message_status(
+                    msg_hash.clone(),
+                    MessageStatus::ConsumedNotMinted,
+                );
+                s.mint_failed(
+                    msg_hash.clone(),
+                    token_id,
+                    nonce,
+                    payload,
+                    ic::time(),
+                    error.clone(),
+                );
             });
             Err(error)
         }
@@ -0,0 +1,100 @@
+use candid::{candid_method, Nat};
+use ic_cdk_macros::{heartbeat, query, update};
+use ic_kit::ic::{spawn, time};
+
+use crate::api::admin::is_authorized;
+use crate::api::mint::mint;
+use crate::common::types::{MessageHash, OperationFailure, PauseScope, StuckMint, TxError};
+use crate::proxy::STATE;
+
+/// Stuck mints retried by a single heartbeat
+const MAX_RETRIES_PER_HEARTBEAT: usize = 10;
+
+/// Interval between two checks for due mints, ten seconds in nanoseconds
+const MINT_RETRY_CHECK_INTERVAL: u64 = 10 * 1_000_000_000;
+
+#[heartbeat]
+fn heartbeat() {
+    let now = time();
+    let next_check_at = STATE.with(|s| *s.next_mint_retry_check_at.borrow());
+    if now < next_check_at || STATE.with(|s| s.is_paused(PauseScope::Mint)) {
+        return;
+    }
+
+    STATE.with(|s| {
+        s.next_mint_retry_check_at
+            .replace(now + MINT_RETRY_CHECK_INTERVAL)
+    });
+    retry_due_mints(now);
+}
+
+/// Retry stuck mints whose backoff ran out
+fn retry_due_mints(now: u64) {
+    let due = STATE.with(|s| s.get_due_mints(now, MAX_RETRIES_PER_HEARTBEAT));
+
+    for (msg_hash, stuck_mint) in due {
+        // marked before spawning, so the next heartbeat does not pick it up while in flight
+        STATE.with(|s| s.start_mint_attempt(&msg_hash, now));
+
+        spawn(async move {
+            let _ = mint(stuck_mint.token_id, stuck_mint.nonce, stuck_mint.payload).await;
+        });
+    }
+}
+
+/// Mints that failed after their message was consumed, least recently tried first
+#[query(name = "list_stuck_mints")]
+#[candid_method(query, rename = "list_stuck_mints")]
+fn list_stuck_mints() -> Vec<(MessageHash, StuckMint)> {
+    STATE.with(|s| s.get_stuck_mints())
+}
+
+/// Retry a stuck mint right away, also once the heartbeat gave up on it
+#[update(name = "force_retry", guard = "is_authorized")]
+#[candid_method(update, rename = "force_retry")]
+async fn force_retry(msg_hash: MessageHash) -> Result<Nat, OperationFailure> {
+    let stuck_mint = STATE.with(|s| s.get_stuck_mint(&msg_hash)).ok_or_else(|| {
+        OperationFailure::Mint(Some(TxError::Other(format!(
+            "Mint {} is not stuck",
+            msg_hash
+        ))))
+    })?;
+
+    STATE.with(|s| s.start_mint_attempt(&msg_hash, time()));
+    mint(stuck_mint.token_id, stuck_mint.nonce, stuck_mint.payload).await
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_kit::candid::Nat;
+    use ic_kit::{mock_principals, MockContext};
+
+    use super::*;
+
+    fn msg_hash() -> MessageHash {
+        String::from("c9e23418a985892acc0fa031331080bfce112bdf841a3ae04a5181c6da1610b1")
+    }
+
+    #[test]
+    fn test_heartbeat_skips_paused_mints() {
+        MockContext::new().inject();
+
+        STATE.with(|s| {
+            s.mint_failed(
+                msg_hash(),
+                mock_principals::xtc(),
+                Nat::from(1),
+                vec![Nat::from(2)],
+                0,
+                TxError::Other(String::from("mint failed")),
+            );
+            s.pause(PauseScope::Mint);
+        });
+        let stuck_mint = STATE.with(|s| s.get_stuck_mint(&msg_hash())).unwrap();
+
+        heartbeat();
+
+        assert_eq!(list_stuck_mints(), vec![(msg_hash(), stuck_mint)]);
+        assert_eq!(STATE.with(|s| *s.next_mint_retry_check_at.borrow()), 0);
+    }
+}
@@ -4,6 +4,7 @@ use ic_kit::{
     ic::{self},
     macros::update,
 };
+use payload_codec::Payload;
 
 use crate::{
     common::{
@@ -11,24 +12,32 @@ use crate::{
         dip20::Dip20,
         magic::Magic,
         tera::Tera,
-        types::{ClaimableMessage, EthereumAddr, OperationFailure, TokenId, TxError, TxFlag},
+        types::{
+            ClaimableMessage, EthereumAddr, OperationFailure, PauseScope, TokenId, TxError, TxFlag,
+            WithdrawPayload,
+        },
     },
-    proxy::{ToNat, ERC20_ADDRESS_ETH, MAGIC_ADDRESS_IC, STATE, TERA_ADDRESS},
+    proxy::STATE,
 };
 
 /// withdraw left over balance if burn/mint fails
-/// this will attempt to bridge the leftover balance
-/// todo withdraw specific balance
+/// this will attempt to bridge `amount` of the leftover balance,
+/// all of it if None, in a single message
 #[update(name = "withdraw")]
 #[candid_method(update, rename = "withdraw")]
 pub async fn withdraw(
     eth_contract_as_principal: TokenId,
     eth_addr: EthereumAddr,
-    amount: Nat,
+    amount: Option<Nat>,
 ) -> Result<Nat, OperationFailure> {
+    if STATE.with(|s| s.is_paused(PauseScope::Withdraw)) {
+        return Err(OperationFailure::Paused(PauseScope::Withdraw));
+    }
+
     let caller = ic::caller();
-    let tera_id = Principal::from_text(TERA_ADDRESS).unwrap();
-    let magic_bridge = Principal::from_text(MAGIC_ADDRESS_IC).unwrap();
+    let config = STATE.with(|s| s.get_config());
+    let tera_id = config.tera;
+    let magic_bridge = config.magic_ic;
 
     let token_id: Principal = match magic_bridge
         .get_canister(eth_contract_as_principal.clone())
@@ -56,23 +65,50 @@ pub async fn withdraw(
         ))));
     };
 
-    let erc20_addr_hex = ERC20_ADDRESS_ETH.trim_start_matches("0x");
-    let erc20_addr_pid = Principal::from_slice(&hex::decode(erc20_addr_hex).unwrap());
+    let erc20_addr_pid = config.erc20_eth;
 
-    let get_balance =
-        STATE.with(|s| s.get_balance(caller, eth_contract_as_principal, eth_addr, amount.clone()));
+    let get_balance = STATE.with(|s| s.get_balance(caller, eth_contract_as_principal, eth_addr));
     if let Some(balance) = get_balance {
-        let payload = [
-            eth_contract_as_principal.to_nat(),
-            eth_addr.clone().to_nat(),
-            balance.clone(),
-        ]
-        .to_vec();
+        let amount = amount.unwrap_or_else(|| balance.clone());
+        if amount == 0 || amount > balance {
+            STATE.with(|s| s.remove_user_flag(caller, token_id));
+            return Err(OperationFailure::UserHasNotBalanceToWithdraw(Some(
+                TxError::Other(format!(
+                    "Can not withdraw {} out of a balance of {}!",
+                    amount, balance
+                )),
+            )));
+        }
+
+        if STATE.with(|s| s.holds_withdrawal(eth_contract_as_principal, &amount)) {
+            STATE.with(|s| {
+                s.remove_balance(caller, eth_addr, eth_contract_as_principal, amount.clone());
+                s.remove_user_flag(caller, token_id);
+                s.hold_withdrawal(
+                    caller,
+                    eth_addr,
+                    eth_contract_as_principal,
+                    token_id,
+                    token_name,
+                    amount.clone(),
+                );
+            });
+
+            // sent to L1 once approved
+            return Ok(amount);
+        }
+
+        let payload = WithdrawPayload {
+            token: eth_contract_as_principal,
+            eth_addr,
+            amount: amount.clone(),
+        }
+        .encode_payload();
 
         match tera_id.send_message(erc20_addr_pid, payload).await {
             Ok(outgoing_message) => {
                 STATE.with(|s| {
-                    s.remove_balance(caller, eth_addr, eth_contract_as_principal, amount);
+                    s.remove_balance(caller, eth_addr, eth_contract_as_principal, amount.clone());
                     s.remove_user_flag(caller, token_id);
                 });
 
@@ -83,9 +119,9 @@ pub async fn withdraw(
                     msg_key: Some(outgoing_message.msg_key.clone()),
                     token_name: token_name,
                     token: token_id.clone(),
-                    amount: balance.clone(),
+                    amount: amount.clone(),
                 });
-                return Ok(balance);
+                return Ok(amount);
             }
             Err(_) => {
                 STATE.with(|s| s.remove_user_flag(caller, token_id));
@@ -0,0 +1,177 @@
+use ic_cdk::export::candid::Nat;
+use ic_kit::{
+    candid::candid_method,
+    ic,
+    macros::{query, update},
+};
+use payload_codec::Payload;
+
+use crate::{
+    api::admin::is_authorized,
+    common::{
+        cap::insert_claimable_asset,
+        tera::Tera,
+        types::{
+            ClaimableMessage, OperationFailure, PauseScope, PendingWithdrawal, TokenId, TxError,
+            WithdrawPayload, WithdrawalPolicy,
+        },
+    },
+    proxy::STATE,
+};
+
+/// Set the amount of `token` above which burns are held for approval, and
+/// how long they wait before they can be approved
+#[update(name = "set_withdrawal_policy", guard = "is_authorized")]
+#[candid_method(update, rename = "set_withdrawal_policy")]
+fn set_withdrawal_policy(token: TokenId, policy: WithdrawalPolicy) {
+    STATE.with(|s| s.set_withdrawal_policy(token, policy))
+}
+
+#[query(name = "get_withdrawal_policy")]
+#[candid_method(query, rename = "get_withdrawal_policy")]
+fn get_withdrawal_policy(token: TokenId) -> WithdrawalPolicy {
+    STATE.with(|s| s.get_withdrawal_policy(token))
+}
+
+#[query(name = "get_pending_withdrawals")]
+#[candid_method(query, rename = "get_pending_withdrawals")]
+fn get_pending_withdrawals() -> Vec<PendingWithdrawal> {
+    STATE.with(|s| s.get_pending_withdrawals())
+}
+
+/// Send a held burn to L1, it stays held if sending fails
+#[update(name = "approve_withdrawal", guard = "is_authorized")]
+#[candid_method(update, rename = "approve_withdrawal")]
+async fn approve_withdrawal(id: u64) -> Result<Nat, OperationFailure> {
+    // approving sends the burn to L1, which either pause holds back
+    for scope in [PauseScope::Burn, PauseScope::Withdraw] {
+        if STATE.with(|s| s.is_paused(scope)) {
+            return Err(OperationFailure::Paused(scope));
+        }
+    }
+
+    let withdrawal = STATE
+        .with(|s| s.get_pending_withdrawal(id))
+        .ok_or_else(|| {
+            OperationFailure::PendingWithdrawal(Some(TxError::Other(format!(
+                "Withdrawal {} is not pending approval",
+                id
+            ))))
+        })?;
+
+    if ic::time() < withdrawal.release_at {
+        return Err(OperationFailure::PendingWithdrawal(Some(TxError::Other(
+            format!(
+                "Withdrawal {} can not be approved before {}",
+                id, withdrawal.release_at
+            ),
+        ))));
+    }
+
+    // taken out before sending, so it can not be approved twice meanwhile
+    STATE.with(|s| s.remove_pending_withdrawal(id));
+
+    let config = STATE.with(|s| s.get_config());
+    let tera_id = config.tera;
+    let erc20_addr_pid = config.erc20_eth;
+
+    let payload = WithdrawPayload {
+        token: withdrawal.token,
+        eth_addr: withdrawal.eth_addr,
+        amount: withdrawal.amount.clone(),
+    }
+    .encode_payload();
+
+    match tera_id.send_message(erc20_addr_pid, payload).await {
+        Ok(outgoing_message) => {
+            insert_claimable_asset(ClaimableMessage {
+                from: withdrawal.from,
+                owner: withdrawal.eth_addr,
+                msg_hash: outgoing_message.msg_hash,
+                msg_key: Some(outgoing_message.msg_key),
+                token_name: withdrawal.token_name,
+                token: withdrawal.token_canister,
+                amount: withdrawal.amount.clone(),
+            });
+            Ok(withdrawal.amount)
+        }
+        Err(_) => {
+            STATE.with(|s| s.insert_pending_withdrawal(withdrawal));
+            Err(OperationFailure::SendMessage(Some(TxError::Other(
+                String::from("Sending message to L1 failed!"),
+            ))))
+        }
+    }
+}
+
+/// Give a held burn back to its owner's balance, to be withdrawn
+#[update(name = "reject_withdrawal", guard = "is_authorized")]
+#[candid_method(update, rename = "reject_withdrawal")]
+fn reject_withdrawal(id: u64) -> Result<(), String> {
+    let withdrawal = STATE
+        .with(|s| s.remove_pending_withdrawal(id))
+        .ok_or_else(|| format!("Withdrawal {} is not pending approval", id))?;
+
+    STATE.with(|s| {
+        s.add_balance(
+            withdrawal.from,
+            withdrawal.eth_addr,
+            withdrawal.token,
+            withdrawal.amount,
+        )
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_kit::{candid::Principal, mock_principals, MockContext};
+
+    use super::*;
+
+    #[test]
+    fn test_holds_withdrawal() {
+        MockContext::new().inject();
+
+        let token = mock_principals::alice();
+        set_withdrawal_policy(
+            token,
+            WithdrawalPolicy {
+                threshold: Some(Nat::from(100)),
+                delay: 0,
+            },
+        );
+
+        assert!(!STATE.with(|s| s.holds_withdrawal(token, &Nat::from(100))));
+        assert!(STATE.with(|s| s.holds_withdrawal(token, &Nat::from(101))));
+        assert!(!STATE.with(|s| s.holds_withdrawal(mock_principals::bob(), &Nat::from(101))));
+    }
+
+    #[test]
+    fn test_reject_withdrawal() {
+        MockContext::new().inject();
+
+        let from = mock_principals::bob();
+        let eth_addr = mock_principals::john();
+        let token = mock_principals::alice();
+        let id = STATE.with(|s| {
+            s.hold_withdrawal(
+                from,
+                eth_addr,
+                token,
+                Principal::anonymous(),
+                String::from("Token"),
+                Nat::from(500),
+            )
+        });
+        assert_eq!(get_pending_withdrawals().len(), 1);
+
+        assert_eq!(reject_withdrawal(id), Ok(()));
+        assert!(get_pending_withdrawals().is_empty());
+        assert_eq!(
+            STATE.with(|s| s.get_balance(from, token, eth_addr)),
+            Some(Nat::from(500))
+        );
+        assert!(reject_withdrawal(id).is_err());
+    }
+}
@@ -2,7 +2,7 @@ use async_trait::async_trait;
 use ic_cdk::call;
 use ic_cdk::export::candid::{Nat, Principal};
 
-use crate::common::types::{OutgoingMessage, TxError};
+use crate::common::types::{OutgoingMessage, TeraError, TxError};
 
 use super::types::NonceBytes;
 
@@ -29,7 +29,7 @@ impl Tera for Principal {
         nonce: NonceBytes,
         payload: Vec<Nat>,
     ) -> Result<bool, TxError> {
-        let consume: (Result<bool, String>,) = match call(
+        let consume: (Result<bool, TeraError>,) = match call(
             *self,
             "consume_message",
             (&erc20_addr_pid, &nonce, &payload),
@@ -47,7 +47,7 @@ impl Tera for Principal {
 
         match consume {
             (Ok(_),) => Ok(true),
-            (Err(error),) => Err(TxError::Other(format!("Consume Message: {:?}", error))),
+            (Err(error),) => Err(error.into()),
         }
     }
 
@@ -56,7 +56,7 @@ impl Tera for Principal {
         erc20_addr_pid: Principal,
         payload: Vec<Nat>,
     ) -> Result<OutgoingMessage, TxError> {
-        let send: (Result<OutgoingMessage, String>,) =
+        let send: (Result<OutgoingMessage, TeraError>,) =
             match call(*self, "send_message", (&erc20_addr_pid, &payload)).await {
                 Ok(res) => res,
                 Err((code, err)) => {
@@ -69,7 +69,7 @@ impl Tera for Principal {
 
         match send {
             (Ok(outgoing_message),) => Ok(outgoing_message),
-            (Err(error),) => Err(TxError::Other(format!("Send Message: {:?}", error))),
+            (Err(error),) => Err(error.into()),
         }
     }
 }
@@ -1,29 +1,55 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use ic_kit::candid::{CandidType, Deserialize, Nat, Principal};
-use serde::Serialize;
+use payload_codec::{impl_payload, Bytes32String, SizedPrincipal};
+pub use terabethia_common::{IncomingMessageHashParams, Message, MessageHash, NonceBytes};
 
 pub type Nonce = Nat;
 
-pub type NonceBytes = [u8; 32];
-
 pub type TokenId = Principal;
 
-pub type MessageHash = String;
-
 pub type EthereumAddr = Principal;
 
 pub type TxReceipt = Result<Nat, TxError>;
 
 pub type MagicResponse = Result<Principal, FactoryError>;
 
+/// Payload of a deposit message from `ERC20Bridge.sol`
+pub struct DepositPayload {
+    pub token: EthereumAddr,
+    pub to: SizedPrincipal,
+    pub amount: Nat,
+    pub name: Bytes32String,
+    pub symbol: Bytes32String,
+    pub decimals: u8,
+}
+
+impl_payload!(DepositPayload {
+    token,
+    to,
+    amount,
+    name,
+    symbol,
+    decimals,
+});
+
+/// Payload of a withdrawal message to `ERC20Bridge.sol`
+pub struct WithdrawPayload {
+    pub token: EthereumAddr,
+    pub eth_addr: EthereumAddr,
+    pub amount: Nat,
+}
+
+impl_payload!(WithdrawPayload {
+    token,
+    eth_addr,
+    amount,
+});
+
 #[derive(CandidType, Deserialize)]
 pub struct WithdrawableBalance(pub Vec<(String, String, Nat)>);
 
-#[derive(Serialize, CandidType, Deserialize)]
-pub struct Message;
-
 #[derive(CandidType, Deserialize, Debug)]
 pub enum FactoryError {
     CreateCanisterError,
@@ -32,6 +58,7 @@ pub enum FactoryError {
     CodeAlreadyInstalled,
     InstallCodeError,
     InvalidCanisterId,
+    InvalidPayload(String),
 }
 
 #[derive(Clone, CandidType, Deserialize, Eq, PartialEq, Debug)]
@@ -46,21 +73,6 @@ pub enum TxFlag {
     Withdrawing,
 }
 
-#[derive(CandidType, Deserialize)]
-pub struct IncomingMessageHashParams {
-    pub from: Nat,
-    pub to: Nat,
-    pub nonce: Nonce,
-    pub payload: Vec<Nat>,
-}
-
-#[derive(CandidType, Deserialize)]
-pub struct OutgoingMessageHashParams {
-    pub from: Nat,
-    pub to: Nat,
-    pub payload: Vec<Nat>,
-}
-
 #[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq, Hash)]
 pub struct OutgoingMessage {
     pub msg_key: [u8; 32],
@@ -78,22 +90,91 @@ pub struct ClaimableMessage {
     pub from: Principal,
 }
 
+/// Canisters and L1 contract the proxy talks to, set at init
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ProxyConfig {
+    pub tera: Principal,
+    pub cap: Principal,
+    /// magic bridge canister
+    pub magic_ic: Principal,
+    /// `ERC20Bridge.sol` contract
+    pub erc20_eth: EthereumAddr,
+}
+
+/// Burns of a token above `threshold` are held for approval before they are
+/// sent to L1
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct WithdrawalPolicy {
+    /// in the token's own unit, None sends every burn right away
+    pub threshold: Option<Nat>,
+    /// nanoseconds a held burn waits before it can be approved
+    pub delay: u64,
+}
+
+/// Mint that failed after its message was consumed on tera, kept along
+/// with the message to retry it
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct StuckMint {
+    /// DIP20 canister of the token
+    pub token_id: TokenId,
+    pub nonce: Nonce,
+    pub payload: Vec<Nat>,
+    /// failed mint attempts
+    pub attempts: u32,
+    pub last_attempt_at: u64,
+    /// None once the retries gave up, only `force_retry` retries it then
+    pub next_attempt_at: Option<u64>,
+    pub last_error: Option<TxError>,
+}
+
+/// Burn held for approval, its amount is out of the user balances meanwhile
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingWithdrawal {
+    pub id: u64,
+    pub from: Principal,
+    pub eth_addr: EthereumAddr,
+    /// ERC20 contract of the token
+    pub token: TokenId,
+    /// DIP20 canister of the token
+    pub token_canister: Principal,
+    pub token_name: String,
+    pub amount: Nat,
+    pub queued_at: u64,
+    /// time from which it can be approved
+    pub release_at: u64,
+}
+
 #[derive(CandidType, Deserialize, Default)]
 pub struct ProxyState {
     /// store incoming messages against status locks
     pub incoming_messages: RefCell<HashMap<MessageHash, MessageStatus>>,
-    /// user balances
-    pub balances: RefCell<HashMap<Principal, HashMap<TokenId, Vec<(EthereumAddr, Nat)>>>>,
+    /// user balances, summed by destination
+    pub balances: RefCell<HashMap<Principal, HashMap<TokenId, HashMap<EthereumAddr, Nat>>>>,
     /// authorized principals
     pub controllers: RefCell<Vec<Principal>>,
     // store outgoing massages waiting to be claimed
     pub messages_unclaimed: RefCell<HashMap<EthereumAddr, Vec<ClaimableMessage>>>,
     // user state flag
     pub user_actions: RefCell<HashMap<(Principal, Principal), TxFlag>>,
+    /// cycle balance under which the canister is degraded, None until set
+    pub low_cycles_threshold: RefCell<Option<u64>>,
+    /// operations paused by a controller
+    pub paused: RefCell<HashSet<PauseScope>>,
+    /// burns held for approval, by token
+    pub withdrawal_policies: RefCell<HashMap<TokenId, WithdrawalPolicy>>,
+    pub pending_withdrawals: RefCell<HashMap<u64, PendingWithdrawal>>,
+    pub next_withdrawal_id: RefCell<u64>,
+    pub config: RefCell<Option<ProxyConfig>>,
+    /// mints that failed after their message was consumed
+    pub stuck_mints: RefCell<HashMap<MessageHash, StuckMint>>,
+    /// next time the heartbeat looks for stuck mints to retry, not kept across upgrades
+    pub next_mint_retry_check_at: RefCell<u64>,
 }
 
+/// Proxy state as written to stable memory before it was versioned,
+/// fields added along the way are optional
 #[derive(CandidType, Deserialize, Default)]
-pub struct StableProxyState {
+pub struct StableProxyStateV0 {
     /// store incoming messages against status locks
     pub incoming_messages: HashMap<MessageHash, MessageStatus>,
     /// user balances
@@ -106,13 +187,50 @@ pub struct StableProxyState {
     pub user_actions: Option<HashMap<(Principal, Principal), TxFlag>>,
 }
 
+#[derive(CandidType, Deserialize, Default)]
+pub struct StableProxyStateV1 {
+    /// store incoming messages against status locks
+    pub incoming_messages: HashMap<MessageHash, MessageStatus>,
+    /// user balances, summed by destination
+    pub balances: HashMap<Principal, HashMap<TokenId, HashMap<EthereumAddr, Nat>>>,
+    /// authorized principals
+    pub controllers: Vec<Principal>,
+    // store outgoing massages waiting to be claimed
+    pub messages_unclaimed: HashMap<EthereumAddr, Vec<ClaimableMessage>>,
+    // user state flag
+    pub user_actions: HashMap<(Principal, Principal), TxFlag>,
+    /// cycle balance under which the canister is degraded
+    pub low_cycles_threshold: Option<u64>,
+    /// operations paused by a controller
+    pub paused: HashSet<PauseScope>,
+    /// burns held for approval, by token
+    pub withdrawal_policies: HashMap<TokenId, WithdrawalPolicy>,
+    pub pending_withdrawals: HashMap<u64, PendingWithdrawal>,
+    pub next_withdrawal_id: u64,
+    /// canisters the proxy talks to, None in a state upgraded from V0
+    pub config: Option<ProxyConfig>,
+    /// mints that failed after their message was consumed
+    pub stuck_mints: HashMap<MessageHash, StuckMint>,
+}
+
+/// Current version of the proxy state in stable memory
+pub type StableProxyState = StableProxyStateV1;
+
+/// Envelope the proxy state is written to stable memory in. A new version
+/// adds a variant along with the migration from the previous one.
+#[derive(CandidType, Deserialize)]
+pub enum VersionedStableProxyState {
+    V0(StableProxyStateV0),
+    V1(StableProxyStateV1),
+}
+
 #[derive(CandidType, Deserialize, Clone, Copy)]
 pub enum TokenType {
     DIP20,
     DIP721,
 }
 
-#[derive(CandidType, Debug, Deserialize, PartialEq)]
+#[derive(CandidType, Clone, Debug, Deserialize, PartialEq)]
 pub enum TxError {
     InsufficientBalance,
     InsufficientAllowance,
@@ -125,6 +243,46 @@ pub enum TxError {
     Other(String),
 }
 
+/// Errors returned by the tera canister
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum TeraError {
+    NonceAlreadyConsumed,
+    MessageNotFound,
+    OutgoingQueueFull,
+    Unauthorized,
+    CallFailed { code: u8, msg: String },
+    InvalidPayload(String),
+    InvalidBatch(String),
+    SigningFailed(String),
+    LastAdmin,
+    Paused(TeraPauseScope),
+}
+
+/// Operation paused on the tera canister
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TeraPauseScope {
+    StoreMessage,
+    SendMessage,
+}
+
+/// Operation a controller can pause, to stop the bridge without an upgrade
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PauseScope {
+    /// `mint`, along with `handle_message`
+    Mint,
+    Burn,
+    Withdraw,
+}
+
+impl From<TeraError> for TxError {
+    fn from(error: TeraError) -> Self {
+        match error {
+            TeraError::Unauthorized => TxError::Unauthorized,
+            error => TxError::Other(format!("Tera: {:?}", error)),
+        }
+    }
+}
+
 #[derive(CandidType, Deserialize, PartialEq)]
 pub enum OperationFailure {
     Burn(Option<TxError>),
@@ -134,4 +292,7 @@ pub enum OperationFailure {
     SendMessage(Option<TxError>),
     TokenCanisterIdNotFound(Option<TxError>),
     TransferFrom(Option<TxError>),
+    Paused(PauseScope),
+    Mint(Option<TxError>),
+    PendingWithdrawal(Option<TxError>),
 }
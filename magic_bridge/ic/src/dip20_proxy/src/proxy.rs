@@ -4,21 +4,38 @@ use cap_sdk::{DetailsBuilder, IndefiniteEvent, IndefiniteEventBuilder};
 
 use ic_cdk::export::candid::{Nat, Principal};
 use ic_kit::ic;
+use terabethia_common::{CycleMetrics, DEFAULT_LOW_CYCLES_THRESHOLD};
+pub use terabethia_common::{ToBytes, ToNat};
 
 use crate::common::types::{
-    ClaimableMessage, EthereumAddr, MessageHash, MessageStatus, NonceBytes, ProxyState,
-    StableProxyState, TokenId, TxFlag, WithdrawableBalance,
+    ClaimableMessage, EthereumAddr, MessageHash, MessageStatus, Nonce, PauseScope,
+    PendingWithdrawal, ProxyConfig, ProxyState, StableProxyState, StuckMint, TokenId, TxError,
+    TxFlag, WithdrawableBalance, WithdrawalPolicy,
 };
 
-pub const CAP_ADDRESS: &str = "lj532-6iaaa-aaaah-qcc7a-cai";
-pub const TERA_ADDRESS: &str = "timop-6qaaa-aaaab-qaeea-cai";
-pub const MAGIC_ADDRESS_IC: &str = "7z6fu-giaaa-aaaab-qafkq-cai";
-pub const ERC20_ADDRESS_ETH: &str = "0x8CA1651eadeF97D3aC36c25DAE4A552c1368F27d";
+/// Attempts to mint a stuck deposit before the heartbeat gives up on it
+const MAX_MINT_ATTEMPTS: u32 = 10;
+
+/// Delay before the first retry of a stuck mint, one minute in nanoseconds,
+/// doubled by every further attempt
+const MINT_RETRY_BASE_DELAY: u64 = 60 * 1_000_000_000;
+
+/// Longest delay between two mint attempts, six hours in nanoseconds
+const MINT_RETRY_MAX_DELAY: u64 = 6 * 60 * 60 * 1_000_000_000;
 
 thread_local! {
     pub static STATE: ProxyState = ProxyState::default();
 }
 
+/// Delay before the next mint attempt, after `attempts` failed ones
+fn retry_delay(attempts: u32) -> u64 {
+    let exponent = attempts.saturating_sub(1).min(32);
+
+    MINT_RETRY_BASE_DELAY
+        .saturating_mul(1u64 << exponent)
+        .min(MINT_RETRY_MAX_DELAY)
+}
+
 impl ProxyState {
     pub fn store_incoming_message(&self, msg_hash: MessageHash) {
         self.incoming_messages
@@ -35,6 +52,14 @@ impl ProxyState {
         self.incoming_messages.borrow_mut().insert(msg_hash, status);
     }
 
+    pub fn count_incoming_messages(&self, status: MessageStatus) -> u64 {
+        self.incoming_messages
+            .borrow()
+            .values()
+            .filter(|message_status| **message_status == status)
+            .count() as u64
+    }
+
     pub fn remove_incoming_message(&self, msg_hash: MessageHash) -> Option<MessageStatus> {
         self.incoming_messages.borrow_mut().remove(&msg_hash)
     }
@@ -44,26 +69,17 @@ impl ProxyState {
         caller: Principal,
         token_id: TokenId,
         eth_address: EthereumAddr,
-        amount: Nat,
     ) -> Option<Nat> {
-        if let Some(balance) = self
-            .balances
+        self.balances
             .borrow()
             .get(&caller)
-            .unwrap_or(&HashMap::new())
-            .get(&token_id)
-            .unwrap_or(&Vec::new())
-            .into_iter()
-            .find(|m| m.0 == eth_address && m.1 == amount)
-        {
-            return Some(balance.1.clone());
-        } else {
-            return None;
-        }
+            .and_then(|token_balances| token_balances.get(&token_id))
+            .and_then(|balances| balances.get(&eth_address))
+            .cloned()
     }
 
     pub fn get_all_balances(&self, caller: Principal) -> Result<WithdrawableBalance, String> {
-        let token_balances: Option<HashMap<TokenId, Vec<(EthereumAddr, Nat)>>> =
+        let token_balances: Option<HashMap<TokenId, HashMap<EthereumAddr, Nat>>> =
             self.balances.borrow().get(&caller).cloned();
 
         if let Some(balances) = token_balances {
@@ -83,29 +99,29 @@ impl ProxyState {
 
     pub fn add_balance(&self, caller: Principal, to: Principal, token_id: TokenId, amount: Nat) {
         let mut binding = self.balances.borrow_mut();
-        let caller_txs: HashMap<TokenId, Vec<(Principal, Nat)>> = HashMap::new();
-        let token_txs: Vec<(Principal, Nat)> = Vec::new();
-        binding
+        *binding
             .entry(caller)
-            .or_insert(caller_txs)
+            .or_default()
             .entry(token_id)
-            .or_insert(token_txs)
-            .push((to, amount));
+            .or_default()
+            .entry(to)
+            .or_default() += amount;
     }
 
-    /// Panics if theres no balance for the user/token_id or destination
+    /// Panics if the user's balance of token_id for the destination is lower
+    /// than `amount`
     pub fn remove_balance(&self, caller: Principal, to: Principal, token_id: TokenId, amount: Nat) {
         let mut binding = self.balances.borrow_mut();
-        let txs = binding
+        let balances = binding
             .get_mut(&caller)
             .unwrap()
             .get_mut(&token_id)
             .unwrap();
-        let index = txs
-            .into_iter()
-            .position(|tx| tx.0 == to && tx.1 == amount)
-            .unwrap();
-        txs.remove(index);
+        let balance = balances.get_mut(&to).unwrap();
+        *balance -= amount;
+        if *balance == 0 {
+            balances.remove(&to);
+        }
     }
 
     pub fn remove_claimable_message(
@@ -188,13 +204,213 @@ impl ProxyState {
             .ok_or("Caller is not authorized".to_string())
     }
 
+    pub fn set_low_cycles_threshold(&self, threshold: u64) {
+        self.low_cycles_threshold.replace(Some(threshold));
+    }
+
+    pub fn get_cycles(&self) -> CycleMetrics {
+        let threshold = self
+            .low_cycles_threshold
+            .borrow()
+            .unwrap_or(DEFAULT_LOW_CYCLES_THRESHOLD);
+        CycleMetrics::new(ic::balance(), threshold)
+    }
+
+    pub fn pause(&self, scope: PauseScope) {
+        self.paused.borrow_mut().insert(scope);
+    }
+
+    pub fn unpause(&self, scope: PauseScope) {
+        self.paused.borrow_mut().remove(&scope);
+    }
+
+    pub fn is_paused(&self, scope: PauseScope) -> bool {
+        self.paused.borrow().contains(&scope)
+    }
+
+    pub fn get_pause_state(&self) -> Vec<PauseScope> {
+        let mut paused: Vec<PauseScope> = self.paused.borrow().iter().cloned().collect();
+        paused.sort();
+        paused
+    }
+
+    pub fn set_withdrawal_policy(&self, token: TokenId, policy: WithdrawalPolicy) {
+        self.withdrawal_policies.borrow_mut().insert(token, policy);
+    }
+
+    pub fn get_withdrawal_policy(&self, token: TokenId) -> WithdrawalPolicy {
+        self.withdrawal_policies
+            .borrow()
+            .get(&token)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Whether a burn of `amount` of `token` is held for approval
+    pub fn holds_withdrawal(&self, token: TokenId, amount: &Nat) -> bool {
+        self.withdrawal_policies
+            .borrow()
+            .get(&token)
+            .and_then(|policy| policy.threshold.as_ref())
+            .is_some_and(|threshold| amount > threshold)
+    }
+
+    /// Hold a burn for approval, returns its id
+    pub fn hold_withdrawal(
+        &self,
+        from: Principal,
+        eth_addr: EthereumAddr,
+        token: TokenId,
+        token_canister: Principal,
+        token_name: String,
+        amount: Nat,
+    ) -> u64 {
+        let id = self.next_withdrawal_id.replace_with(|id| *id + 1);
+        let queued_at = ic::time();
+        let release_at = queued_at.saturating_add(self.get_withdrawal_policy(token).delay);
+
+        self.insert_pending_withdrawal(PendingWithdrawal {
+            id,
+            from,
+            eth_addr,
+            token,
+            token_canister,
+            token_name,
+            amount,
+            queued_at,
+            release_at,
+        });
+        id
+    }
+
+    pub fn insert_pending_withdrawal(&self, withdrawal: PendingWithdrawal) {
+        self.pending_withdrawals
+            .borrow_mut()
+            .insert(withdrawal.id, withdrawal);
+    }
+
+    pub fn get_pending_withdrawal(&self, id: u64) -> Option<PendingWithdrawal> {
+        self.pending_withdrawals.borrow().get(&id).cloned()
+    }
+
+    pub fn remove_pending_withdrawal(&self, id: u64) -> Option<PendingWithdrawal> {
+        self.pending_withdrawals.borrow_mut().remove(&id)
+    }
+
+    pub fn get_pending_withdrawals(&self) -> Vec<PendingWithdrawal> {
+        let mut pending: Vec<PendingWithdrawal> = self
+            .pending_withdrawals
+            .borrow()
+            .values()
+            .cloned()
+            .collect();
+        pending.sort_by_key(|withdrawal| withdrawal.id);
+        pending
+    }
+
+    /// Keep a mint that failed after its message was consumed, and schedule
+    /// its retry with an exponential backoff, or give up after `MAX_MINT_ATTEMPTS`
+    pub fn mint_failed(
+        &self,
+        msg_hash: MessageHash,
+        token_id: TokenId,
+        nonce: Nonce,
+        payload: Vec<Nat>,
+        now: u64,
+        error: TxError,
+    ) {
+        let mut stuck_mints = self.stuck_mints.borrow_mut();
+        let stuck_mint = stuck_mints.entry(msg_hash).or_insert(StuckMint {
+            token_id,
+            nonce,
+            payload,
+            attempts: 0,
+            last_attempt_at: now,
+            next_attempt_at: None,
+            last_error: None,
+        });
+
+        stuck_mint.attempts += 1;
+        stuck_mint.last_attempt_at = now;
+        stuck_mint.last_error = Some(error);
+        stuck_mint.next_attempt_at = if stuck_mint.attempts >= MAX_MINT_ATTEMPTS {
+            None
+        } else {
+            Some(now + retry_delay(stuck_mint.attempts))
+        };
+    }
+
+    /// Mark a mint attempt as in flight, so that the heartbeat only retries
+    /// it again once the backoff of the attempt ran out
+    pub fn start_mint_attempt(&self, msg_hash: &MessageHash, now: u64) {
+        if let Some(stuck_mint) = self.stuck_mints.borrow_mut().get_mut(msg_hash) {
+            stuck_mint.last_attempt_at = now;
+            if stuck_mint.next_attempt_at.is_some() {
+                stuck_mint.next_attempt_at = Some(now + retry_delay(stuck_mint.attempts + 1));
+            }
+        }
+    }
+
+    pub fn get_stuck_mint(&self, msg_hash: &MessageHash) -> Option<StuckMint> {
+        self.stuck_mints.borrow().get(msg_hash).cloned()
+    }
+
+    pub fn remove_stuck_mint(&self, msg_hash: &MessageHash) -> Option<StuckMint> {
+        self.stuck_mints.borrow_mut().remove(msg_hash)
+    }
+
+    /// Stuck mints whose next attempt is due, oldest first
+    pub fn get_due_mints(&self, now: u64, limit: usize) -> Vec<(MessageHash, StuckMint)> {
+        let mut due: Vec<(MessageHash, StuckMint)> = self
+            .stuck_mints
+            .borrow()
+            .iter()
+            .filter(|(_, mint)| mint.next_attempt_at.is_some_and(|at| at <= now))
+            .map(|(msg_hash, mint)| (msg_hash.clone(), mint.clone()))
+            .collect();
+
+        due.sort_by_key(|(_, mint)| mint.next_attempt_at);
+        due.truncate(limit);
+        due
+    }
+
+    pub fn get_stuck_mints(&self) -> Vec<(MessageHash, StuckMint)> {
+        let mut stuck: Vec<(MessageHash, StuckMint)> = self
+            .stuck_mints
+            .borrow()
+            .iter()
+            .map(|(msg_hash, mint)| (msg_hash.clone(), mint.clone()))
+            .collect();
+        stuck.sort_by_key(|(_, mint)| mint.last_attempt_at);
+        stuck
+    }
+
+    pub fn set_config(&self, config: ProxyConfig) {
+        self.config.replace(Some(config));
+    }
+
+    /// Canisters the proxy talks to, set at init and kept across upgrades
+    pub fn get_config(&self) -> ProxyConfig {
+        self.config
+            .borrow()
+            .clone()
+            .expect("the proxy config is set at init")
+    }
+
     pub fn take_all(&self) -> StableProxyState {
         StableProxyState {
-            balances: Some(self.balances.take()),
+            balances: self.balances.take(),
             controllers: self.controllers.take(),
             incoming_messages: self.incoming_messages.take(),
             messages_unclaimed: self.messages_unclaimed.take(),
-            user_actions: Some(self.user_actions.take()),
+            user_actions: self.user_actions.take(),
+            low_cycles_threshold: self.low_cycles_threshold.take(),
+            paused: self.paused.take(),
+            withdrawal_policies: self.withdrawal_policies.take(),
+            pending_withdrawals: self.pending_withdrawals.take(),
+            next_withdrawal_id: self.next_withdrawal_id.take(),
+            config: self.config.take(),
+            stuck_mints: self.stuck_mints.take(),
         }
     }
 
@@ -204,76 +420,34 @@ impl ProxyState {
         self.incoming_messages.borrow_mut().clear();
         self.messages_unclaimed.borrow_mut().clear();
         self.user_actions.borrow_mut().clear();
+        self.low_cycles_threshold.replace(None);
+        self.paused.borrow_mut().clear();
+        self.withdrawal_policies.borrow_mut().clear();
+        self.pending_withdrawals.borrow_mut().clear();
+        self.next_withdrawal_id.replace(0);
+        self.config.take();
+        self.stuck_mints.borrow_mut().clear();
     }
 
     pub fn replace_all(&self, stable_message_state: StableProxyState) {
-        self.balances
-            .replace(stable_message_state.balances.unwrap_or_default());
+        self.balances.replace(stable_message_state.balances);
         self.controllers.replace(stable_message_state.controllers);
         self.incoming_messages
             .replace(stable_message_state.incoming_messages);
         self.messages_unclaimed
             .replace(stable_message_state.messages_unclaimed);
-        self.user_actions
-            .replace(stable_message_state.user_actions.unwrap_or_default());
-    }
-}
-
-pub trait ToNat {
-    fn to_nat(&self) -> Nat;
-}
-
-impl ToNat for Principal {
-    fn to_nat(&self) -> Nat {
-        Nat::from(num_bigint::BigUint::from_bytes_be(&self.as_slice()[..]))
-    }
-}
-
-impl ToNat for [u8; 32] {
-    fn to_nat(&self) -> Nat {
-        Nat::from(num_bigint::BigUint::from_bytes_be(&self.as_slice()[..]))
-    }
-}
-
-pub trait FromNat {
-    fn from_nat(input: Nat) -> Result<Principal, String>;
-}
-
-impl FromNat for Principal {
-    #[inline(always)]
-    fn from_nat(input: Nat) -> Result<Principal, String> {
-        let be_bytes = input.0.to_bytes_be();
-        let be_bytes_len = be_bytes.len();
-        if be_bytes_len > 29 {
-            return Err("Invalid Nat".to_string());
-        }
-        let padding_bytes = if be_bytes_len > 10 && be_bytes_len < 29 {
-            29 - be_bytes_len
-        } else if be_bytes_len < 10 {
-            10 - be_bytes_len
-        } else {
-            0
-        };
-        let mut p_slice = vec![0u8; padding_bytes];
-        p_slice.extend_from_slice(&be_bytes);
-        Ok(Principal::from_slice(&p_slice))
-    }
-}
-
-pub trait ToBytes {
-    fn to_nonce_bytes(&self) -> NonceBytes;
-}
-impl ToBytes for Nat {
-    fn to_nonce_bytes(&self) -> NonceBytes {
-        let be_bytes = self.0.to_bytes_be();
-        let be_bytes_len = be_bytes.len();
-        let padding_bytes = 32 - be_bytes_len;
-
-        let mut p_slice = vec![0u8; padding_bytes];
-        p_slice.extend_from_slice(&be_bytes);
-
-        let nonce_bytes: [u8; 32] = p_slice.as_slice()[..].try_into().unwrap();
-        nonce_bytes
+        self.user_actions.replace(stable_message_state.user_actions);
+        self.low_cycles_threshold
+            .replace(stable_message_state.low_cycles_threshold);
+        self.paused.replace(stable_message_state.paused);
+        self.withdrawal_policies
+            .replace(stable_message_state.withdrawal_policies);
+        self.pending_withdrawals
+            .replace(stable_message_state.pending_withdrawals);
+        self.next_withdrawal_id
+            .replace(stable_message_state.next_withdrawal_id);
+        self.config.replace(stable_message_state.config);
+        self.stuck_mints.replace(stable_message_state.stuck_mints);
     }
 }
 
@@ -421,8 +595,7 @@ mod tests {
                 amount_1.clone(),
             )
         });
-        let current_balance_1 =
-            STATE.with(|s| s.get_balance(caller, token_id, eth_address_1, amount_1.clone()));
+        let current_balance_1 = STATE.with(|s| s.get_balance(caller, token_id, eth_address_1));
         assert_eq!(current_balance_1.unwrap(), amount_1.clone());
 
         // add amount_2 for token_1 and eth_address_2
@@ -438,7 +611,7 @@ mod tests {
         });
         assert_eq!(withdraw_address_count, 2);
 
-        // add amount_3 for token_1 and eth_address_1 (100 , 100)
+        // add amount_3 for token_1 and eth_address_1 (100 + 300)
         STATE.with(|s| {
             s.add_balance(
                 caller,
@@ -447,9 +620,47 @@ mod tests {
                 amount_3.clone(),
             )
         });
-        let current_balance_1 =
-            STATE.with(|s| s.get_balance(caller, token_id, eth_address_1, amount_3.clone()));
-        assert_eq!(current_balance_1.unwrap(), amount_3);
+        let current_balance_1 = STATE.with(|s| s.get_balance(caller, token_id, eth_address_1));
+        assert_eq!(current_balance_1.unwrap(), amount_1 + amount_3);
+    }
+
+    #[test]
+    fn test_mint_failed_backoff() {
+        let msg_hash = String::from("stuck");
+        let token_id = mock_principals::xtc();
+        let error = TxError::Other(String::from("mint failed"));
+
+        STATE.with(|s| {
+            s.mint_failed(
+                msg_hash.clone(),
+                token_id,
+                Nat::from(1),
+                vec![],
+                0,
+                error.clone(),
+            );
+            assert_eq!(
+                s.get_stuck_mint(&msg_hash).unwrap().next_attempt_at,
+                Some(MINT_RETRY_BASE_DELAY)
+            );
+            assert!(s.get_due_mints(MINT_RETRY_BASE_DELAY - 1, 10).is_empty());
+            assert_eq!(s.get_due_mints(MINT_RETRY_BASE_DELAY, 10).len(), 1);
+
+            for _ in 1..MAX_MINT_ATTEMPTS {
+                s.mint_failed(
+                    msg_hash.clone(),
+                    token_id,
+                    Nat::from(1),
+                    vec![],
+                    0,
+                    error.clone(),
+                );
+            }
+            let stuck_mint = s.get_stuck_mint(&msg_hash).unwrap();
+            assert_eq!(stuck_mint.attempts, MAX_MINT_ATTEMPTS);
+            assert_eq!(stuck_mint.next_attempt_at, None);
+            assert!(s.get_due_mints(u64::MAX, 10).is_empty());
+        });
     }
 
     #[test]
@@ -470,8 +681,7 @@ mod tests {
                 eth_address_2=0
             }
             token_id_2: {
-                eth_address_1=300
-                eth_address_1=100
+                eth_address_1=300 + 100
                 eth_address_2=200
             }
         }
@@ -514,12 +724,6 @@ mod tests {
 
         let all_balances = balances.unwrap().0;
 
-        let w = (
-            token_id_2.clone().to_string(),
-            eth_address_1.clone().to_string(),
-            Nat::from(100),
-        );
-
         let x = (
             token_id_2.clone().to_string(),
             eth_address_2.clone().to_string(),
@@ -528,7 +732,7 @@ mod tests {
         let y = (
             token_id_2.clone().to_string(),
             eth_address_1.clone().to_string(),
-            Nat::from(300),
+            Nat::from(400),
         );
         let z = (
             token_id_1.clone().to_string(),
@@ -536,7 +740,7 @@ mod tests {
             Nat::from(100),
         );
 
-        assert!(all_balances.clone().into_iter().any(|e| e == w));
+        assert_eq!(all_balances.len(), 3);
         assert!(all_balances.clone().into_iter().any(|e| e == x));
         assert!(all_balances.clone().into_iter().any(|e| e == y));
         assert!(all_balances.into_iter().any(|e| e == z));
@@ -597,20 +801,13 @@ mod tests {
         });
 
         let current_balance = STATE
-            .with(|s| {
-                s.get_balance(
-                    caller,
-                    token_id_1.clone(),
-                    eth_address_2.clone(),
-                    amount_2.clone(),
-                )
-            })
+            .with(|s| s.get_balance(caller, token_id_1.clone(), eth_address_2.clone()))
             .unwrap();
 
         assert_eq!(current_balance, amount_2);
 
-        let removed_balance = STATE
-            .with(|s| s.get_balance(caller, token_id_1.clone(), eth_address_1.clone(), amount_1));
+        let removed_balance =
+            STATE.with(|s| s.get_balance(caller, token_id_1.clone(), eth_address_1.clone()));
 
         assert!(removed_balance.is_none());
 
@@ -633,6 +830,24 @@ mod tests {
         assert!(balances_final.0.len() == 0);
     }
 
+    #[test]
+    fn test_remove_part_of_balance() {
+        let caller = mock_principals::bob();
+        let eth_address = mock_principals::alice();
+        let token_id = mock_principals::john();
+
+        STATE.with(|s| {
+            s.add_balance(caller, eth_address, token_id, Nat::from(100));
+            s.add_balance(caller, eth_address, token_id, Nat::from(300));
+            s.remove_balance(caller, eth_address, token_id, Nat::from(150));
+
+            assert_eq!(
+                s.get_balance(caller, token_id, eth_address),
+                Some(Nat::from(250))
+            );
+        });
+    }
+
     #[test]
     fn test_store_incoming_message() {
         let nonce = Nat::from(4_u32);
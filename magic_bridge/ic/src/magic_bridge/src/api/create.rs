@@ -1,6 +1,6 @@
 use crate::api::admin::is_authorized;
-use crate::factory::{FromNat, CAP_ADDRESS};
-use crate::types::FactoryError;
+use crate::factory::CAP_ADDRESS;
+use crate::types::{DepositPayload, FactoryError};
 use crate::{
     factory::{CreateCanisterParam, Factory},
     magic::STATE,
@@ -12,18 +12,18 @@ use ic_kit::{
     macros::update,
     Principal,
 };
-
-use std::str;
+use payload_codec::Payload;
 
 #[update(name = "create", guard = "is_authorized")]
 #[candid_method(update, rename = "create")]
 async fn create(token_type: TokenType, payload: Vec<Nat>) -> MagicResponse {
     let self_id = ic::id();
     let caller = ic::caller();
-    let eth_addr = match Principal::from_nat(payload[0].clone()) {
-        Ok(canister) => canister,
-        Err(_msg) => return Err(FactoryError::InvalidCanisterId),
+    let deposit = match DepositPayload::decode_payload(&payload) {
+        Ok(deposit) => deposit,
+        Err(error) => return Err(FactoryError::InvalidPayload(error.to_string())),
     };
+    let eth_addr = deposit.token;
 
     let canister_exits = STATE.with(|s| s.get_canister(eth_addr));
 
@@ -31,19 +31,12 @@ async fn create(token_type: TokenType, payload: Vec<Nat>) -> MagicResponse {
         canister_id
     } else {
         let logo = String::from("/s");
-        let name = str::from_utf8(&payload[3].0.to_bytes_be()[..])
-            .unwrap()
-            .to_string();
-        let symbol = str::from_utf8(&payload[4].0.to_bytes_be()[..])
-            .unwrap()
-            .to_string();
-        let decimals = u8::from_str_radix(&payload[5].to_string(), 10).unwrap();
 
         let create_param = CreateCanisterParam {
             logo,
-            name,
-            symbol,
-            decimals,
+            name: deposit.name.into_string(),
+            symbol: deposit.symbol.into_string(),
+            decimals: deposit.decimals,
             total_supply: Nat::from(0_u32),
             owner: caller,
             controllers: vec![caller, self_id],
@@ -73,13 +66,8 @@ mod tests {
     use super::*;
     use std::str::FromStr;
 
-    #[test]
-    fn test_to_hex() {
-        let expexted_name = String::from("fighters");
-        let expexted_symbol = String::from("foo");
-        let expexted_decimals = 18;
-
-        let payload = [
+    fn deposit_payload() -> Vec<Nat> {
+        [
             // token
             Nat::from_str("1390849295786071768276380950238675083608645509734").unwrap(),
             // to
@@ -88,30 +76,47 @@ mod tests {
             // amount
             Nat::from_str("100000000000000000").unwrap(),
             // name
-            Nat::from(num_bigint::BigUint::from_bytes_be(expexted_name.as_bytes())),
+            Nat::from(num_bigint::BigUint::from_bytes_be("fighters".as_bytes())),
             // symbol
-            Nat::from(num_bigint::BigUint::from_bytes_be(
-                expexted_symbol.as_bytes(),
-            )),
+            Nat::from(num_bigint::BigUint::from_bytes_be("foo".as_bytes())),
             // decimals
-            Nat::from(expexted_decimals),
+            Nat::from(18),
         ]
-        .to_vec();
+        .to_vec()
+    }
 
-        let name = str::from_utf8(&payload[3].0.to_bytes_be()[..])
-            .unwrap()
-            .to_string();
+    #[test]
+    fn test_to_hex() {
+        let expexted_name = String::from("fighters");
+        let expexted_symbol = String::from("foo");
+        let expexted_decimals = 18;
+
+        let deposit = DepositPayload::decode_payload(&deposit_payload()).unwrap();
+
+        assert_eq!(expexted_name, deposit.name.into_string());
+
+        assert_eq!(expexted_symbol, deposit.symbol.into_string());
 
-        assert_eq!(expexted_name, name);
+        assert_eq!(expexted_decimals, deposit.decimals);
+    }
 
-        let symbol = str::from_utf8(&payload[4].0.to_bytes_be()[..])
-            .unwrap()
-            .to_string();
+    #[test]
+    fn test_principal_from_nat() {
+        let mut payload = deposit_payload();
+        payload[0] = Nat::from_str("1118288024408649503359660893691376548931478070077").unwrap();
 
-        assert_eq!(expexted_symbol, symbol);
+        let deposit = DepositPayload::decode_payload(&payload).unwrap();
 
-        let decimals = u8::from_str_radix(&payload[5].to_string(), 10).unwrap();
+        assert_eq!(
+            deposit.token.to_string(),
+            String::from("t2i2h-eqaaa-aaaaa-aaaaa-bq7by-tzdmi-ryasy-aj22p-4qrgf-evilg-rt2")
+        );
+    }
+
+    #[test]
+    fn test_invalid_payload() {
+        let payload = deposit_payload();
 
-        assert_eq!(expexted_decimals, decimals);
+        assert!(DepositPayload::decode_payload(&payload[..5]).is_err());
     }
 }
@@ -0,0 +1,153 @@
+use ic_kit::{
+    candid::candid_method,
+    ic,
+    macros::{query, update},
+};
+use terabethia_common::CycleMetrics;
+
+use crate::api::admin::is_authorized;
+use crate::{
+    magic::{MagicState, STATE},
+    types::CanisterId,
+};
+
+#[query(name = "get_cycles")]
+#[candid_method(query, rename = "get_cycles")]
+fn get_cycles() -> CycleMetrics {
+    STATE.with(|s| s.get_cycles())
+}
+
+/// Set the cycle balance under which the canister reports itself as degraded,
+/// token canisters under it are topped up by `top_up_canisters`
+#[update(name = "set_low_cycles_threshold", guard = "is_authorized")]
+#[candid_method(update, rename = "set_low_cycles_threshold")]
+fn set_low_cycles_threshold(threshold: u64) {
+    STATE.with(|s| s.set_low_cycles_threshold(threshold))
+}
+
+/// Deposit `cycles` into every token canister under the low-water mark,
+/// returns the cycles deposited into each of them. Stops depositing once
+/// it would bring the bridge itself under the low-water mark.
+#[update(name = "top_up_canisters", guard = "is_authorized")]
+#[candid_method(update, rename = "top_up_canisters")]
+async fn top_up_canisters(cycles: u64) -> Vec<(CanisterId, Result<u64, String>)> {
+    let threshold = STATE.with(|s| s.get_low_cycles_threshold());
+    let canisters = STATE.with(|s| s.get_all_canisters());
+
+    let mut results = vec![];
+    for (_, canister_id) in canisters {
+        let result = top_up_canister(canister_id, cycles, threshold).await;
+        results.push((canister_id, result));
+    }
+
+    results
+}
+
+async fn top_up_canister(
+    canister_id: CanisterId,
+    cycles: u64,
+    threshold: u64,
+) -> Result<u64, String> {
+    let (status,) = MagicState::_canister_status(canister_id)
+        .await
+        .map_err(|(code, err)| format!("RejectionCode: {:?}\n{}", code, err))?;
+
+    if status.cycles >= threshold {
+        return Ok(0);
+    }
+
+    if ic::balance() < threshold.saturating_add(cycles) {
+        return Err(String::from("Not enough cycles left to top up"));
+    }
+
+    MagicState::_deposit_cycles(canister_id, cycles)
+        .await
+        .map_err(|(code, err)| format!("RejectionCode: {:?}\n{}", code, err))?;
+
+    Ok(cycles)
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_kit::candid::Nat;
+    use ic_kit::interfaces::management::{
+        CanisterStatusResponse, DefiniteCanisterSettings, Status,
+    };
+    use ic_kit::{async_test, Method, MockContext, Principal};
+    use terabethia_common::DEFAULT_LOW_CYCLES_THRESHOLD;
+
+    use super::*;
+
+    fn status(cycles: u64) -> CanisterStatusResponse {
+        CanisterStatusResponse {
+            status: Status::Running,
+            settings: DefiniteCanisterSettings {
+                controllers: vec![],
+                compute_allocation: Nat::from(0),
+                memory_allocation: Nat::from(0),
+                freezing_threshold: Nat::from(0),
+            },
+            module_hash: None,
+            memory_size: Nat::from(0),
+            cycles: Nat::from(cycles),
+        }
+    }
+
+    fn token_canister() -> CanisterId {
+        Principal::from_slice(&[1, 0x00])
+    }
+
+    fn before_each(balance: u64, child_cycles: u64) {
+        MockContext::new()
+            .with_balance(balance)
+            .with_handler(
+                Method::new()
+                    .name("canister_status")
+                    .response(status(child_cycles)),
+            )
+            .with_handler(Method::new().name("deposit_cycles").response(()))
+            .inject();
+
+        STATE.with(|s| s.insert_canister(Principal::from_slice(&[2, 0x00]), token_canister()));
+    }
+
+    #[test]
+    fn test_get_cycles() {
+        MockContext::new().with_balance(500).inject();
+        STATE.with(|s| s.set_low_cycles_threshold(1_000));
+
+        let metrics = get_cycles();
+        assert_eq!(metrics.balance, 500);
+        assert!(metrics.degraded);
+    }
+
+    #[async_test]
+    async fn test_top_up_canisters() {
+        before_each(10 * DEFAULT_LOW_CYCLES_THRESHOLD, 0);
+
+        let results = top_up_canisters(1_000).await;
+
+        assert_eq!(results, vec![(token_canister(), Ok(1_000))]);
+    }
+
+    #[async_test]
+    async fn test_top_up_skips_funded_canisters() {
+        before_each(
+            10 * DEFAULT_LOW_CYCLES_THRESHOLD,
+            DEFAULT_LOW_CYCLES_THRESHOLD,
+        );
+
+        let results = top_up_canisters(1_000).await;
+
+        assert_eq!(results, vec![(token_canister(), Ok(0))]);
+    }
+
+    #[async_test]
+    async fn test_top_up_keeps_own_balance() {
+        before_each(DEFAULT_LOW_CYCLES_THRESHOLD, 0);
+
+        let results = top_up_canisters(1_000).await;
+
+        assert!(results[0].1.is_err());
+    }
+}
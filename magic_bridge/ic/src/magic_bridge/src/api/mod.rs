@@ -1,5 +1,6 @@
 mod admin;
 mod create;
+mod cycles;
 mod dab;
 mod get_canister;
 mod init;
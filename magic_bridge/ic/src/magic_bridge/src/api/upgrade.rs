@@ -2,11 +2,49 @@ use ic_kit::ic;
 use ic_kit::macros::*;
 
 use crate::magic::StableMagicState;
+use crate::magic::StableMagicStateV0;
+use crate::magic::StableMagicStateV1;
+use crate::magic::VersionedStableMagicState;
 use crate::magic::STATE;
 
+impl From<StableMagicStateV0> for StableMagicStateV1 {
+    fn from(state: StableMagicStateV0) -> Self {
+        StableMagicStateV1 {
+            canisters: state.canisters,
+            controllers: state.controllers,
+            failed_registration_canisters: state.failed_registration_canisters,
+            low_cycles_threshold: None,
+        }
+    }
+}
+
+impl VersionedStableMagicState {
+    /// Migrate the state one version at a time up to the current one
+    pub fn migrate(self) -> StableMagicState {
+        match self {
+            VersionedStableMagicState::V0(state) => {
+                VersionedStableMagicState::V1(state.into()).migrate()
+            }
+            VersionedStableMagicState::V1(state) => state,
+        }
+    }
+}
+
+/// Restore the state from stable memory, falling back to the snapshot
+/// written before it was wrapped in a versioned envelope
+fn restore() -> VersionedStableMagicState {
+    ic::stable_restore::<(VersionedStableMagicState,)>()
+        .or_else(|_| {
+            ic::stable_restore::<(StableMagicStateV0,)>()
+                .map(|(state,)| (VersionedStableMagicState::V0(state),))
+        })
+        .expect("failed to restore stable magic state")
+        .0
+}
+
 #[pre_upgrade]
 fn pre_upgrade() {
-    let stable_magic_state = STATE.with(|s| s.take_all());
+    let stable_magic_state = VersionedStableMagicState::V1(STATE.with(|s| s.take_all()));
 
     ic::stable_store((stable_magic_state,)).expect("failed to save magic state");
 }
@@ -15,8 +53,82 @@ fn pre_upgrade() {
 fn post_upgrade() {
     STATE.with(|s| s.clear_all());
 
-    let (stable_magic_state,): (StableMagicState,) =
-        ic::stable_restore().expect("failed to restore stable magic state");
+    let stable_magic_state = restore();
+
+    STATE.with(|s| s.replace_all(stable_magic_state.migrate()));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ic_kit::{mock_principals, MockContext};
+
+    use super::*;
+
+    fn v0_fixture() -> StableMagicStateV0 {
+        let mut canisters = HashMap::new();
+        canisters.insert(mock_principals::bob(), mock_principals::xtc());
+
+        StableMagicStateV0 {
+            canisters,
+            controllers: vec![mock_principals::alice()],
+            failed_registration_canisters: HashMap::new(),
+        }
+    }
+
+    fn before_each() {
+        MockContext::new().inject();
+
+        STATE.with(|s| {
+            s.insert_canister(mock_principals::bob(), mock_principals::xtc());
+            s.controllers.borrow_mut().push(mock_principals::alice());
+        });
+    }
+
+    fn assert_restored() {
+        STATE.with(|s| {
+            assert_eq!(
+                s.get_canister(mock_principals::bob()),
+                Some(mock_principals::xtc())
+            );
+            assert_eq!(
+                s.controllers.borrow().clone(),
+                vec![mock_principals::alice()]
+            );
+        });
+    }
+
+    #[test]
+    fn test_post_upgrade_from_unversioned_v0() {
+        MockContext::new().inject();
+        ic::stable_store((v0_fixture(),)).unwrap();
+
+        post_upgrade();
+
+        assert_restored();
+    }
+
+    #[test]
+    fn test_post_upgrade_from_v0() {
+        MockContext::new().inject();
+        ic::stable_store((VersionedStableMagicState::V0(v0_fixture()),)).unwrap();
+
+        post_upgrade();
+
+        assert_restored();
+        STATE.with(|s| assert_eq!(*s.low_cycles_threshold.borrow(), None));
+    }
+
+    #[test]
+    fn test_post_upgrade_from_v1() {
+        before_each();
+        STATE.with(|s| s.set_low_cycles_threshold(5_000));
+
+        pre_upgrade();
+        post_upgrade();
 
-    STATE.with(|s| s.replace_all(stable_magic_state));
+        assert_restored();
+        STATE.with(|s| assert_eq!(*s.low_cycles_threshold.borrow(), Some(5_000)));
+    }
 }
@@ -10,31 +10,6 @@ pub const CAP_ADDRESS: &str = "lj532-6iaaa-aaaah-qcc7a-cai";
 pub const DIP20_WASM: &[u8] = include_bytes!("../../wasm/dip20/token-opt.wasm");
 // const DIP721_WASM: &[u8] = include_bytes!("../../wasm/dip721/nft-opt.wasm");
 
-pub trait FromNat {
-    fn from_nat(input: Nat) -> Result<Principal, String>;
-}
-
-impl FromNat for Principal {
-    #[inline(always)]
-    fn from_nat(input: Nat) -> Result<Principal, String> {
-        let be_bytes = input.0.to_bytes_be();
-        let be_bytes_len = be_bytes.len();
-        if be_bytes_len > 29 {
-            return Err("Invalid Nat".to_string());
-        }
-        let padding_bytes = if be_bytes_len > 10 && be_bytes_len < 29 {
-            29 - be_bytes_len
-        } else if be_bytes_len < 10 {
-            10 - be_bytes_len
-        } else {
-            0
-        };
-        let mut p_slice = vec![0u8; padding_bytes];
-        p_slice.extend_from_slice(&be_bytes);
-        Ok(Principal::from_slice(&p_slice))
-    }
-}
-
 // logo: String,
 // name: String,
 // symbol: String,
@@ -205,22 +180,3 @@ impl Factory {
         Ok(canister_id)
     }
 }
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::str::FromStr;
-
-    #[test]
-    fn test_principal_from_nat() {
-        let eth_address_as_nat =
-            Nat::from_str("1118288024408649503359660893691376548931478070077").unwrap();
-
-        let principal = Principal::from_nat(eth_address_as_nat.clone()).unwrap();
-
-        assert_eq!(
-            principal.to_string(),
-            String::from("t2i2h-eqaaa-aaaaa-aaaaa-bq7by-tzdmi-ryasy-aj22p-4qrgf-evilg-rt2")
-        );
-    }
-}
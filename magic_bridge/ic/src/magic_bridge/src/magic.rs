@@ -11,6 +11,7 @@ use management::UpdateSettingsArgument;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::str;
+use terabethia_common::{CycleMetrics, DEFAULT_LOW_CYCLES_THRESHOLD};
 
 thread_local! {
     pub static STATE: MagicState = MagicState::default();
@@ -22,13 +23,35 @@ pub struct MagicState {
     pub controllers: RefCell<Vec<Principal>>,
     pub failed_registration_canisters:
         RefCell<HashMap<Principal, (CreateCanisterParam, RetryCount)>>,
+    /// cycle balance under which the canister is degraded, None until set
+    pub low_cycles_threshold: RefCell<Option<u64>>,
 }
 
 #[derive(CandidType, Deserialize, Default)]
-pub struct StableMagicState {
-    canisters: HashMap<EthereumAddr, CanisterId>,
-    controllers: Vec<Principal>,
-    failed_registration_canisters: HashMap<Principal, (CreateCanisterParam, RetryCount)>,
+pub struct StableMagicStateV0 {
+    pub canisters: HashMap<EthereumAddr, CanisterId>,
+    pub controllers: Vec<Principal>,
+    pub failed_registration_canisters: HashMap<Principal, (CreateCanisterParam, RetryCount)>,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct StableMagicStateV1 {
+    pub canisters: HashMap<EthereumAddr, CanisterId>,
+    pub controllers: Vec<Principal>,
+    pub failed_registration_canisters: HashMap<Principal, (CreateCanisterParam, RetryCount)>,
+    pub low_cycles_threshold: Option<u64>,
+}
+
+/// Current version of the magic state in stable memory
+pub type StableMagicState = StableMagicStateV1;
+
+/// Envelope the magic state is written to stable memory in. Version 0 was
+/// also written on its own, before the envelope. A new version adds a
+/// variant along with the migration from the previous one.
+#[derive(CandidType, Deserialize)]
+pub enum VersionedStableMagicState {
+    V0(StableMagicStateV0),
+    V1(StableMagicStateV1),
 }
 
 impl MagicState {
@@ -170,11 +193,26 @@ impl MagicState {
             .ok_or("Caller is not authorized".to_string())
     }
 
+    pub fn get_low_cycles_threshold(&self) -> u64 {
+        self.low_cycles_threshold
+            .borrow()
+            .unwrap_or(DEFAULT_LOW_CYCLES_THRESHOLD)
+    }
+
+    pub fn set_low_cycles_threshold(&self, threshold: u64) {
+        self.low_cycles_threshold.replace(Some(threshold));
+    }
+
+    pub fn get_cycles(&self) -> CycleMetrics {
+        CycleMetrics::new(ic::balance(), self.get_low_cycles_threshold())
+    }
+
     pub fn take_all(&self) -> StableMagicState {
         StableMagicState {
             canisters: self.canisters.take(),
             controllers: self.controllers.take(),
             failed_registration_canisters: self.failed_registration_canisters.take(),
+            low_cycles_threshold: self.low_cycles_threshold.take(),
         }
     }
 
@@ -182,6 +220,7 @@ impl MagicState {
         self.canisters.borrow_mut().clear();
         self.controllers.borrow_mut().clear();
         self.failed_registration_canisters.borrow_mut().clear();
+        self.low_cycles_threshold.replace(None);
     }
 
     pub fn replace_all(&self, stable_magic_state: StableMagicState) {
@@ -189,5 +228,7 @@ impl MagicState {
         self.controllers.replace(stable_magic_state.controllers);
         self.failed_registration_canisters
             .replace(stable_magic_state.failed_registration_canisters);
+        self.low_cycles_threshold
+            .replace(stable_magic_state.low_cycles_threshold);
     }
 }
@@ -13,6 +13,7 @@ fn main() {
     use ic_kit::candid;
     use ic_kit::candid::Nat;
     use ic_kit::Principal;
+    use terabethia_common::CycleMetrics;
     use types::*;
 
     candid::export_service!();
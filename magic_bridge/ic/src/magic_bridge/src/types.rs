@@ -1,5 +1,6 @@
-use ic_cdk::export::candid::{CandidType, Deserialize, Principal};
+use ic_cdk::export::candid::{CandidType, Deserialize, Nat, Principal};
 use ic_kit::interfaces::management::InstallMode;
+use payload_codec::{impl_payload, Bytes32String, SizedPrincipal};
 
 pub type EthereumAddr = Principal;
 
@@ -9,6 +10,26 @@ pub type RetryCount = u8;
 
 pub type MagicResponse = Result<Principal, FactoryError>;
 
+/// Payload of a deposit message from `ERC20Bridge.sol`
+pub struct DepositPayload {
+    /// the token contract, canisters are keyed by its padded principal
+    pub token: EthereumAddr,
+    pub to: SizedPrincipal,
+    pub amount: Nat,
+    pub name: Bytes32String,
+    pub symbol: Bytes32String,
+    pub decimals: u8,
+}
+
+impl_payload!(DepositPayload {
+    token,
+    to,
+    amount,
+    name,
+    symbol,
+    decimals,
+});
+
 #[derive(CandidType, Deserialize, Clone, Copy)]
 pub enum TokenType {
     DIP20,
@@ -23,6 +44,7 @@ pub enum FactoryError {
     CodeAlreadyInstalled,
     InstallCodeError,
     InvalidCanisterId,
+    InvalidPayload(String),
 }
 #[derive(CandidType, Deserialize, Debug)]
 